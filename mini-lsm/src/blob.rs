@@ -0,0 +1,235 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::column_family::ColumnFamily;
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::mvcc::ValueType;
+use crate::table::{verify_checksum, FileObject, SsTable, SsTableIterator};
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// A reference to a record of a blob file, which an SST stores in place of a large value. It is
+/// encoded as `file id (u64) | offset (u64) | record length (u32)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlobRef {
+    pub file_id: usize,
+    pub offset: u64,
+    pub len: u32,
+}
+
+impl BlobRef {
+    /// The size of an encoded reference.
+    pub const ENCODED_SIZE: usize = 20;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(Self::ENCODED_SIZE);
+        buf.put_u64(self.file_id as u64);
+        buf.put_u64(self.offset);
+        buf.put_u32(self.len);
+        buf
+    }
+
+    /// Decode a reference, or return `None` if `buf` is not one.
+    pub fn decode(mut buf: &[u8]) -> Option<Self> {
+        if buf.len() != Self::ENCODED_SIZE {
+            return None;
+        }
+        Some(Self {
+            file_id: buf.get_u64() as usize,
+            offset: buf.get_u64(),
+            len: buf.get_u32(),
+        })
+    }
+}
+
+/// Append a record of `key` and `value` to the data of a blob file, and return its length. A
+/// record is `key len (u32) | key | value len (u32) | value | checksum (u32)`, where the checksum
+/// is the CRC32C of the rest of the record.
+pub(crate) fn encode_record(buf: &mut Vec<u8>, key: &[u8], value: &[u8]) -> u32 {
+    let start = buf.len();
+    buf.put_u32(key.len() as u32);
+    buf.put_slice(key);
+    buf.put_u32(value.len() as u32);
+    buf.put_slice(value);
+    let checksum = crc32c::crc32c(&buf[start..]);
+    buf.put_u32(checksum);
+    (buf.len() - start) as u32
+}
+
+/// A blob file is a log of the values of an SST builder that are at least
+/// [`LsmStorageOptions::min_blob_size`] bytes, which the SSTs refer to by [`BlobRef`]s. The
+/// references are copied as they are by compaction, so the values are not rewritten with them.
+///
+/// [`LsmStorageOptions::min_blob_size`]: crate::options::LsmStorageOptions::min_blob_size
+pub struct BlobFile {
+    id: usize,
+    file: FileObject,
+}
+
+impl BlobFile {
+    pub fn new(id: usize, file: FileObject) -> Self {
+        Self { id, file }
+    }
+
+    /// Open an existing blob file on the disk.
+    pub fn open(id: usize, path: &Path) -> Result<Self> {
+        Ok(Self::new(id, FileObject::open(path)?))
+    }
+
+    /// Read the value of the record `blob_ref` refers to, which must be a record of `key`.
+    /// Returns [`Error::BlobCorruption`] if it is not, or if it fails its checksum.
+    pub fn read(&self, key: &[u8], blob_ref: &BlobRef) -> Result<Bytes> {
+        let corruption = || Error::BlobCorruption {
+            file_id: self.id,
+            offset: blob_ref.offset,
+        };
+        if blob_ref.offset + blob_ref.len as u64 > self.size() {
+            return Err(corruption().into());
+        }
+        let record = self.file.read(blob_ref.offset, blob_ref.len as u64)?;
+        let mut record = verify_checksum(&record).ok_or_else(corruption)?;
+        if record.remaining() < SIZEOF_U32 {
+            return Err(corruption().into());
+        }
+        let key_len = record.get_u32() as usize;
+        if record.remaining() < key_len + SIZEOF_U32 || &record[..key_len] != key {
+            return Err(corruption().into());
+        }
+        record.advance(key_len);
+        let value_len = record.get_u32() as usize;
+        if record.remaining() != value_len {
+            return Err(corruption().into());
+        }
+        Ok(Bytes::copy_from_slice(record))
+    }
+
+    /// Get the id of the blob file.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the size of the blob file in bytes.
+    pub fn size(&self) -> u64 {
+        self.file.size()
+    }
+}
+
+/// Read the value of `key` from the blob file record `reference` refers to. `reference` is the
+/// value of an entry of type [`ValueType::Blob`].
+pub(crate) fn read_blob(
+    blob_files: &HashMap<usize, Arc<BlobFile>>,
+    key: &[u8],
+    reference: &[u8],
+) -> Result<Bytes> {
+    let blob_ref = match BlobRef::decode(reference) {
+        Some(blob_ref) => blob_ref,
+        None => bail!("invalid blob reference of {} bytes", reference.len()),
+    };
+    match blob_files.get(&blob_ref.file_id) {
+        Some(blob_file) => blob_file.read(key, &blob_ref),
+        None => Err(Error::BlobCorruption {
+            file_id: blob_ref.file_id,
+            offset: blob_ref.offset,
+        }
+        .into()),
+    }
+}
+
+impl LsmStorageInner {
+    /// Rewrite the blob files of a column family whose live ratio, the share of their bytes that
+    /// the SSTs still refer to, is below [`LsmStorageOptions::blob_gc_live_ratio`].
+    ///
+    /// Each SST referring to such a blob file is rewritten into a new SST in its place, which has
+    /// the same entries, but writes the values in the blob file to a new blob file. The rewrite
+    /// is recorded in the manifest before it becomes visible, and then the blob files no SST
+    /// refers to anymore are removed.
+    ///
+    /// [`LsmStorageOptions::blob_gc_live_ratio`]: crate::options::LsmStorageOptions::blob_gc_live_ratio
+    pub(crate) fn collect_blob_garbage(&self, cf: &ColumnFamily) -> Result<()> {
+        let snapshot = cf.snapshot();
+        let live_bytes = snapshot.live_blob_bytes();
+        let garbage: HashSet<usize> = snapshot
+            .blob_files
+            .values()
+            .filter(|blob_file| {
+                let live = live_bytes.get(&blob_file.id()).copied().unwrap_or(0);
+                (live as f64) < blob_file.size() as f64 * self.options.blob_gc_live_ratio
+            })
+            .map(|blob_file| blob_file.id())
+            .collect();
+        if garbage.is_empty() {
+            return Ok(());
+        }
+
+        let mut rewritten = Vec::new();
+        for id in snapshot.sst_ids_newest_first() {
+            let table = &snapshot.sstables[id];
+            if table
+                .blob_refs()
+                .iter()
+                .any(|(file_id, _)| garbage.contains(file_id))
+            {
+                let new_sst = self.rewrite_blobs(&snapshot, table.clone(), &garbage)?;
+                rewritten.push((*id, new_sst));
+            }
+        }
+        self.sync_dir()?;
+
+        let ssts: Vec<(usize, usize)> = rewritten
+            .iter()
+            .map(|(id, new_sst)| (*id, new_sst.sst_id()))
+            .collect();
+        let blob_files_to_remove = {
+            let mut guard = cf.state.write();
+            let mut snapshot = guard.as_ref().clone();
+            for (id, new_sst) in rewritten {
+                snapshot.replace_sst(id, new_sst.sst_id());
+                snapshot.sstables.remove(&id);
+                snapshot.sstables.insert(new_sst.sst_id(), new_sst);
+            }
+            let blob_files_to_remove = self.update_blob_files(&mut snapshot)?;
+            self.manifest
+                .add_record(ManifestRecord::BlobGarbageCollection(cf.id(), ssts.clone()))?;
+            *guard = Arc::new(snapshot);
+            blob_files_to_remove
+        };
+
+        self.remove_files(ssts.into_iter().map(|(id, _)| id), blob_files_to_remove)
+    }
+
+    /// Rewrite an SST, moving the values it refers to in the `garbage` blob files to a new blob
+    /// file.
+    fn rewrite_blobs(
+        &self,
+        snapshot: &LsmStorageState,
+        table: Arc<SsTable>,
+        garbage: &HashSet<usize>,
+    ) -> Result<Arc<SsTable>> {
+        let mut builder = self.new_sst_builder();
+        for tombstone in table.range_tombstones() {
+            builder.add_range_tombstone(tombstone.clone());
+        }
+        let mut iter = SsTableIterator::create_and_seek_to_first(table)?;
+        while iter.is_valid() {
+            match BlobRef::decode(iter.value()) {
+                Some(blob_ref)
+                    if iter.value_type() == ValueType::Blob
+                        && garbage.contains(&blob_ref.file_id) =>
+                {
+                    let value = read_blob(&snapshot.blob_files, iter.key(), iter.value())?;
+                    builder.add(iter.key(), iter.seq(), ValueType::Put, &value);
+                }
+                _ => builder.add(iter.key(), iter.seq(), iter.value_type(), iter.value()),
+            }
+            iter.next()?;
+        }
+        self.build_sst(builder)
+    }
+}
@@ -5,36 +5,59 @@ pub use builder::BlockBuilder;
 use bytes::{Buf, BufMut, Bytes};
 pub use iterator::BlockIterator;
 
-pub const SIZEOF_U16: usize = std::mem::size_of::<u16>();
+pub const SIZEOF_U8: usize = std::mem::size_of::<u8>();
+pub const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+pub const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Number of entries between two restart points.
+pub const RESTART_INTERVAL: usize = 16;
 
 /// A block is the smallest unit of read and caching in LSM tree. It is a collection of sorted
 /// key-value pairs.
+///
+/// Each entry is encoded as
+/// `overlap (u32) | rest_len (u32) | rest | seq (u64) | value_type (u8) | value_len (u32) | value`,
+/// where the key is the first `overlap` bytes of the previous key followed by `rest`, and
+/// `value_type` is the tag of a [`ValueType`](crate::mvcc::ValueType). Entries are sorted by key,
+/// and then from the newest version of the key to the oldest. Every
+/// [`RESTART_INTERVAL`] entries there is a restart point, an entry stored with the full key, so
+/// that the block can be searched without decoding it from the beginning. The block ends with the
+/// offsets of the restart points (u32 each) and their number (u32).
+///
+/// A block holds at least one entry, however large: an entry larger than the block size gets a
+/// block of its own.
 pub struct Block {
     data: Vec<u8>,
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    restarts: Vec<u32>,
 }
 
 impl Block {
+    /// Get the number of bytes the decoded block takes in memory.
+    pub fn size(&self) -> usize {
+        self.data.len() + self.restarts.len() * SIZEOF_U32
+    }
+
     pub fn encode(&self) -> Bytes {
         let mut buf = self.data.clone();
-        let offsets_len = self.offsets.len();
-        for offset in &self.offsets {
-            buf.put_u16(*offset);
+        let restarts_len = self.restarts.len();
+        for offset in &self.restarts {
+            buf.put_u32(*offset);
         }
-        buf.put_u16(offsets_len as u16);
+        buf.put_u32(restarts_len as u32);
         buf.into()
     }
 
     pub fn decode(data: &[u8]) -> Self {
-        let entry_offsets_len = (&data[data.len() - SIZEOF_U16..]).get_u16() as usize;
-        let data_end = data.len() - SIZEOF_U16 - entry_offsets_len * SIZEOF_U16;
-        let offsets_raw = &data[data_end..data.len() - SIZEOF_U16];
-        let offsets = offsets_raw
-            .chunks(SIZEOF_U16)
-            .map(|mut x| x.get_u16())
+        let restarts_len = (&data[data.len() - SIZEOF_U32..]).get_u32() as usize;
+        let data_end = data.len() - SIZEOF_U32 - restarts_len * SIZEOF_U32;
+        let restarts_raw = &data[data_end..data.len() - SIZEOF_U32];
+        let restarts = restarts_raw
+            .chunks(SIZEOF_U32)
+            .map(|mut x| x.get_u32())
             .collect();
         let data = data[0..data_end].to_vec();
-        Self { data, offsets }
+        Self { data, restarts }
     }
 }
 
@@ -1,51 +1,85 @@
 use bytes::BufMut;
 
-use super::{Block, SIZEOF_U16};
+use super::{Block, RESTART_INTERVAL, SIZEOF_U32, SIZEOF_U64, SIZEOF_U8};
+use crate::mvcc::ValueType;
 
 /// Builds a block.
 pub struct BlockBuilder {
-    /// Offsets of each key-value entries.
-    offsets: Vec<u16>,
+    /// Offsets of the restart points.
+    restarts: Vec<u32>,
     /// All key-value pairs in the block.
     data: Vec<u8>,
     /// The expected block size.
     block_size: usize,
+    /// Number of entries in the block.
+    num_entries: usize,
+    /// The last key added, which the next key is compressed against.
+    last_key: Vec<u8>,
+}
+
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
 }
 
 impl BlockBuilder {
     /// Creates a new block builder.
     pub fn new(block_size: usize) -> Self {
         Self {
-            offsets: Vec::new(),
+            restarts: Vec::new(),
             data: Vec::new(),
             block_size,
+            num_entries: 0,
+            last_key: Vec::new(),
         }
     }
 
     fn estimated_size(&self) -> usize {
-        self.offsets.len() * SIZEOF_U16 + self.data.len() + SIZEOF_U16
+        self.restarts.len() * SIZEOF_U32 + self.data.len() + SIZEOF_U32
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Adds an entry written with sequence number `seq` to the block. Returns false when the
+    /// block is full. The first entry is always added, even if it is larger than the block size,
+    /// and then the block is full.
     #[must_use]
-    pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
+    pub fn add(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) -> bool {
         assert!(!key.is_empty(), "key must not be empty");
-        if self.estimated_size() + key.len() + value.len() + SIZEOF_U16 * 3 > self.block_size
+        let is_restart = self.num_entries % RESTART_INTERVAL == 0;
+        let overlap = if is_restart {
+            0
+        } else {
+            shared_prefix_len(&self.last_key, key)
+        };
+        let rest = &key[overlap..];
+        if self.estimated_size()
+            + rest.len()
+            + value.len()
+            + SIZEOF_U32 * 4
+            + SIZEOF_U64
+            + SIZEOF_U8
+            > self.block_size
             && !self.is_empty()
         {
             return false;
         }
-        self.offsets.push(self.data.len() as u16);
-        self.data.put_u16(key.len() as u16);
-        self.data.put(key);
-        self.data.put_u16(value.len() as u16);
+        if is_restart {
+            self.restarts.push(self.data.len() as u32);
+        }
+        self.data.put_u32(overlap as u32);
+        self.data.put_u32(rest.len() as u32);
+        self.data.put(rest);
+        self.data.put_u64(seq);
+        self.data.put_u8(value_type.to_u8());
+        self.data.put_u32(value.len() as u32);
         self.data.put(value);
+        self.num_entries += 1;
+        self.last_key.clear();
+        self.last_key.extend(key);
         true
     }
 
     /// Check if there is no key-value pair in the block.
     pub fn is_empty(&self) -> bool {
-        self.offsets.is_empty()
+        self.num_entries == 0
     }
 
     /// Finalize the block.
@@ -55,7 +89,7 @@ impl BlockBuilder {
         }
         Block {
             data: self.data,
-            offsets: self.offsets,
+            restarts: self.restarts,
         }
     }
 }
@@ -3,13 +3,20 @@ use std::sync::Arc;
 use bytes::Buf;
 
 use super::Block;
+use crate::comparator::Comparator;
+use crate::mvcc::ValueType;
 
 /// Iterates on a block.
 pub struct BlockIterator {
     block: Arc<Block>,
     key: Vec<u8>,
+    seq: u64,
+    value_type: ValueType,
     value: Vec<u8>,
-    idx: usize,
+    /// Offset of the current entry.
+    offset: usize,
+    /// Offset of the entry after the current one.
+    next_offset: usize,
 }
 
 impl BlockIterator {
@@ -17,11 +24,22 @@ impl BlockIterator {
         Self {
             block,
             key: Vec::new(),
+            seq: 0,
+            value_type: ValueType::Put,
             value: Vec::new(),
-            idx: 0,
+            offset: 0,
+            next_offset: 0,
         }
     }
 
+    /// Creates an iterator that is not valid, for an SST without data blocks.
+    pub fn empty() -> Self {
+        Self::new(Arc::new(Block {
+            data: Vec::new(),
+            restarts: Vec::new(),
+        }))
+    }
+
     /// Creates a block iterator and seek to the first entry.
     pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
         let mut iter = Self::new(block);
@@ -29,10 +47,34 @@ impl BlockIterator {
         iter
     }
 
-    /// Creates a block iterator and seek to the first key that >= `key`.
-    pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
+    /// Creates a block iterator and seek to the last entry.
+    pub fn create_and_seek_to_last(block: Arc<Block>) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_last();
+        iter
+    }
+
+    /// Creates a block iterator and seek to the newest version of the first key that >= `key`,
+    /// where the keys of the block are ordered by `comparator`.
+    pub fn create_and_seek_to_key(
+        block: Arc<Block>,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> Self {
+        let mut iter = Self::new(block);
+        iter.seek_to_key(key, comparator);
+        iter
+    }
+
+    /// Creates a block iterator and seek to the oldest version of the last key that <= `key`,
+    /// where the keys of the block are ordered by `comparator`.
+    pub fn create_and_seek_for_prev(
+        block: Arc<Block>,
+        key: &[u8],
+        comparator: &dyn Comparator,
+    ) -> Self {
         let mut iter = Self::new(block);
-        iter.seek_to_key(key);
+        iter.seek_for_prev(key, comparator);
         iter
     }
 
@@ -42,6 +84,18 @@ impl BlockIterator {
         &self.key
     }
 
+    /// Returns the sequence number of the current entry.
+    pub fn seq(&self) -> u64 {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.seq
+    }
+
+    /// Returns whether the current entry is a put or a delete.
+    pub fn value_type(&self) -> ValueType {
+        debug_assert!(!self.key.is_empty(), "invalid iterator");
+        self.value_type
+    }
+
     /// Returns the value of the current entry.
     pub fn value(&self) -> &[u8] {
         debug_assert!(!self.key.is_empty(), "invalid iterator");
@@ -55,55 +109,145 @@ impl BlockIterator {
 
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        self.seek_to(0);
+        self.seek_to_restart(0);
     }
 
-    /// Seeks to the idx-th key in the block.
-    fn seek_to(&mut self, idx: usize) {
-        if idx >= self.block.offsets.len() {
-            self.key.clear();
-            self.value.clear();
+    /// Seeks to the last entry in the block.
+    pub fn seek_to_last(&mut self) {
+        if self.block.restarts.is_empty() {
+            self.invalidate();
             return;
         }
-        let offset = self.block.offsets[idx] as usize;
-        self.seek_to_offset(offset);
-        self.idx = idx;
+        self.seek_to_restart(self.block.restarts.len() - 1);
+        while self.next_offset < self.block.data.len() {
+            self.next();
+        }
     }
 
-    /// Move to the next key in the block.
-    pub fn next(&mut self) {
-        self.idx += 1;
-        self.seek_to(self.idx);
+    /// Seeks to the idx-th restart point in the block.
+    fn seek_to_restart(&mut self, idx: usize) {
+        self.key.clear();
+        self.next_offset = self.block.restarts[idx] as usize;
+        self.next();
     }
 
+    /// Seeks to the entry at `offset`. The key of an entry is decoded from the restart point
+    /// before it.
     fn seek_to_offset(&mut self, offset: usize) {
-        let mut entry = &self.block.data[offset..];
-        let key_len = entry.get_u16() as usize;
-        let key = entry[..key_len].to_vec();
-        entry.advance(key_len);
+        let idx = self
+            .block
+            .restarts
+            .partition_point(|restart| *restart as usize <= offset);
+        self.seek_to_restart(idx - 1);
+        while self.offset < offset {
+            self.next();
+        }
+    }
+
+    fn invalidate(&mut self) {
         self.key.clear();
-        self.key.extend(key);
-        let value_len = entry.get_u16() as usize;
-        let value = entry[..value_len].to_vec();
-        entry.advance(value_len);
         self.value.clear();
-        self.value.extend(value);
     }
 
-    /// Seek to the first key that >= `key`.
-    pub fn seek_to_key(&mut self, key: &[u8]) {
+    /// Move to the next key in the block.
+    pub fn next(&mut self) {
+        if self.next_offset >= self.block.data.len() {
+            self.invalidate();
+            return;
+        }
+        self.offset = self.next_offset;
+        let mut entry = &self.block.data[self.next_offset..];
+        let overlap = entry.get_u32() as usize;
+        let rest_len = entry.get_u32() as usize;
+        self.key.truncate(overlap);
+        self.key.extend(&entry[..rest_len]);
+        entry.advance(rest_len);
+        self.seq = entry.get_u64();
+        self.value_type =
+            ValueType::from_u8(entry.get_u8()).expect("invalid value type in a checksummed block");
+        let value_len = entry.get_u32() as usize;
+        self.value.clear();
+        self.value.extend(&entry[..value_len]);
+        entry.advance(value_len);
+        self.next_offset = self.block.data.len() - entry.len();
+    }
+
+    /// Move to the previous key in the block. The iterator is not valid after moving before the
+    /// first entry.
+    pub fn prev(&mut self) {
+        if self.offset == 0 {
+            self.invalidate();
+            return;
+        }
+        // Scan to the entry before the current one from the restart point before it.
+        let offset = self.offset;
+        let idx = self
+            .block
+            .restarts
+            .partition_point(|restart| (*restart as usize) < offset);
+        self.seek_to_restart(idx - 1);
+        while self.next_offset < offset {
+            self.next();
+        }
+    }
+
+    /// Get the full key of the idx-th restart point.
+    fn restart_key(&self, idx: usize) -> &[u8] {
+        let mut entry = &self.block.data[self.block.restarts[idx] as usize..];
+        let overlap = entry.get_u32();
+        debug_assert_eq!(overlap, 0, "restart point must store the full key");
+        let key_len = entry.get_u32() as usize;
+        &entry[..key_len]
+    }
+
+    /// Seek to the newest version of the first key that >= `key`, where the keys of the block are
+    /// ordered by `comparator`. Binary search finds the last restart point before `key`, and the
+    /// entries from there are scanned linearly. A restart point with `key` itself may be preceded
+    /// by newer versions of it, so it is not a start.
+    pub fn seek_to_key(&mut self, key: &[u8], comparator: &dyn Comparator) {
         let mut low = 0;
-        let mut high = self.block.offsets.len();
+        let mut high = self.block.restarts.len();
         while low < high {
             let mid = low + (high - low) / 2;
-            self.seek_to(mid);
-            assert!(self.is_valid());
-            match self.key().cmp(key) {
-                std::cmp::Ordering::Less => low = mid + 1,
-                std::cmp::Ordering::Greater => high = mid,
-                std::cmp::Ordering::Equal => return,
+            if comparator.compare(self.restart_key(mid), key).is_lt() {
+                low = mid + 1;
+            } else {
+                high = mid;
             }
         }
-        self.seek_to(low);
+        self.seek_to_restart(low.saturating_sub(1));
+        while self.is_valid() && comparator.compare(self.key(), key).is_lt() {
+            self.next();
+        }
+    }
+
+    /// Seek to the oldest version of the last key that <= `key`, where the keys of the block are
+    /// ordered by `comparator`. The iterator is not valid if every key is larger than `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8], comparator: &dyn Comparator) {
+        // Find the first restart point after `key`. The entry is before it.
+        let mut low = 0;
+        let mut high = self.block.restarts.len();
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if comparator.compare(self.restart_key(mid), key).is_le() {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            self.invalidate();
+            return;
+        }
+        self.seek_to_restart(low - 1);
+        let mut offset = self.offset;
+        loop {
+            self.next();
+            if !self.is_valid() || comparator.compare(self.key(), key).is_gt() {
+                break;
+            }
+            offset = self.offset;
+        }
+        self.seek_to_offset(offset);
     }
 }
@@ -3,19 +3,21 @@ use std::sync::Arc;
 use super::builder::BlockBuilder;
 use super::iterator::BlockIterator;
 use super::*;
+use crate::comparator::BytewiseComparator;
+use crate::mvcc::ValueType;
 
 #[test]
 fn test_block_build_single_key() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"233", b"233333"));
+    assert!(builder.add(b"233", 1, ValueType::Put, b"233333"));
     builder.build();
 }
 
 #[test]
 fn test_block_build_full() {
     let mut builder = BlockBuilder::new(16);
-    assert!(builder.add(b"11", b"11"));
-    assert!(!builder.add(b"22", b"22"));
+    assert!(builder.add(b"11", 1, ValueType::Put, b"11"));
+    assert!(!builder.add(b"22", 2, ValueType::Put, b"22"));
     builder.build();
 }
 
@@ -36,7 +38,7 @@ fn generate_block() -> Block {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        assert!(builder.add(&key[..], &value[..]));
+        assert!(builder.add(&key[..], idx as u64, ValueType::Put, &value[..]));
     }
     builder.build()
 }
@@ -57,7 +59,7 @@ fn test_block_decode() {
     let block = generate_block();
     let encoded = block.encode();
     let decoded_block = Block::decode(&encoded);
-    assert_eq!(block.offsets, decoded_block.offsets);
+    assert_eq!(block.restarts, decoded_block.restarts);
     assert_eq!(block.data, decoded_block.data);
 }
 
@@ -96,7 +98,7 @@ fn test_block_iterator() {
 #[test]
 fn test_block_seek_key() {
     let block = Arc::new(generate_block());
-    let mut iter = BlockIterator::create_and_seek_to_key(block, &key_of(0));
+    let mut iter = BlockIterator::create_and_seek_to_key(block, &key_of(0), &BytewiseComparator);
     for offset in 1..=5 {
         for i in 0..num_of_keys() {
             let key = iter.key();
@@ -115,8 +117,152 @@ fn test_block_seek_key() {
                 as_bytes(&value_of(i)),
                 as_bytes(value)
             );
-            iter.seek_to_key(&format!("key_{:03}", i * 5 + offset).into_bytes());
+            iter.seek_to_key(
+                &format!("key_{:03}", i * 5 + offset).into_bytes(),
+                &BytewiseComparator,
+            );
         }
-        iter.seek_to_key(b"k");
+        iter.seek_to_key(b"k", &BytewiseComparator);
+    }
+}
+
+#[test]
+fn test_block_prefix_compression() {
+    let block = generate_block();
+    let full_size: usize = (0..num_of_keys())
+        .map(|idx| {
+            key_of(idx).len() + value_of(idx).len() + SIZEOF_U32 * 3 + SIZEOF_U64 + SIZEOF_U8
+        })
+        .sum();
+    assert!(block.data.len() < full_size);
+    assert_eq!(
+        block.restarts.len(),
+        (num_of_keys() + RESTART_INTERVAL - 1) / RESTART_INTERVAL
+    );
+}
+
+#[test]
+fn test_block_seek_key_between_restarts() {
+    let block = Arc::new(generate_block());
+    let mut iter = BlockIterator::create_and_seek_to_key(
+        block,
+        &key_of(RESTART_INTERVAL + 3),
+        &BytewiseComparator,
+    );
+    for i in RESTART_INTERVAL + 3..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    iter.seek_to_key(b"key_999", &BytewiseComparator);
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_seek_key_versions() {
+    // The versions of "b" span a restart point.
+    let mut builder = BlockBuilder::new(10000);
+    assert!(builder.add(b"a", 1, ValueType::Put, b"a"));
+    for seq in (1..=RESTART_INTERVAL as u64 * 2).rev() {
+        assert!(builder.add(b"b", seq, ValueType::Put, format!("b{}", seq).as_bytes()));
+    }
+    assert!(builder.add(b"c", 1, ValueType::Put, b"c"));
+    let block = Arc::new(builder.build());
+    let mut iter = BlockIterator::create_and_seek_to_key(block, b"b", &BytewiseComparator);
+    for seq in (1..=RESTART_INTERVAL as u64 * 2).rev() {
+        assert_eq!(iter.key(), b"b");
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.value(), format!("b{}", seq).as_bytes());
+        iter.next();
+    }
+    assert_eq!(iter.key(), b"c");
+}
+
+#[test]
+fn test_block_iterate_backward() {
+    let block = Arc::new(generate_block());
+    let mut iter = BlockIterator::create_and_seek_to_last(block.clone());
+    for i in (0..num_of_keys()).rev() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.prev();
+    }
+    assert!(!iter.is_valid());
+
+    for i in 0..num_of_keys() {
+        // Between two keys, and at a key.
+        let key = format!("key_{:03}", i * 5 + 2).into_bytes();
+        iter.seek_for_prev(&key, &BytewiseComparator);
+        assert_eq!(iter.key(), key_of(i));
+        iter.seek_for_prev(&key_of(i), &BytewiseComparator);
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+    }
+    iter.seek_for_prev(b"k", &BytewiseComparator);
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_seek_for_prev_versions() {
+    // The versions of "b" span a restart point.
+    let mut builder = BlockBuilder::new(10000);
+    assert!(builder.add(b"a", 1, ValueType::Put, b"a"));
+    for seq in (1..=RESTART_INTERVAL as u64 * 2).rev() {
+        assert!(builder.add(b"b", seq, ValueType::Put, format!("b{}", seq).as_bytes()));
+    }
+    assert!(builder.add(b"c", 1, ValueType::Put, b"c"));
+    let block = Arc::new(builder.build());
+    let mut iter = BlockIterator::create_and_seek_for_prev(block, b"b", &BytewiseComparator);
+    for seq in 1..=RESTART_INTERVAL as u64 * 2 {
+        assert_eq!(iter.key(), b"b");
+        assert_eq!(iter.seq(), seq);
+        assert_eq!(iter.value(), format!("b{}", seq).as_bytes());
+        iter.prev();
+    }
+    assert_eq!(iter.key(), b"a");
+    iter.prev();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_large_entries() {
+    let large_key = vec![b'k'; 100_000];
+    let large_value = vec![b'v'; 200_000];
+    // A large entry does not fit in a block with other entries.
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(b"a", 1, ValueType::Put, b"a"));
+    assert!(!builder.add(&large_key, 1, ValueType::Put, &large_value));
+
+    // It gets a block of its own.
+    let mut builder = BlockBuilder::new(4096);
+    assert!(builder.add(&large_key, 1, ValueType::Put, &large_value));
+    assert!(!builder.add(b"l", 1, ValueType::Put, b"l"));
+    let block = Arc::new(Block::decode(&builder.build().encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    assert_eq!(iter.key(), &large_key[..]);
+    assert_eq!(iter.value(), &large_value[..]);
+    iter.next();
+    assert!(!iter.is_valid());
+
+    // Large keys are prefix-compressed like the others.
+    let mut builder = BlockBuilder::new(1 << 20);
+    for idx in 0..RESTART_INTERVAL * 2 {
+        let mut key = large_key.clone();
+        key.extend(format!("{:03}", idx).as_bytes());
+        assert!(builder.add(&key, 1, ValueType::Put, &large_value[..idx * 500]));
+    }
+    let block = builder.build();
+    // Only the keys of the two restart points are stored in full.
+    let values_len: usize = (0..RESTART_INTERVAL * 2).map(|idx| idx * 500).sum();
+    assert!(block.data.len() < large_key.len() * 3 + values_len);
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+    for idx in 0..RESTART_INTERVAL * 2 {
+        assert_eq!(
+            &iter.key()[large_key.len()..],
+            format!("{:03}", idx).as_bytes()
+        );
+        assert_eq!(iter.value().len(), idx * 500);
+        iter.next();
     }
 }
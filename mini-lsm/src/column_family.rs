@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::lsm_storage::LsmStorageState;
+
+/// The id of the column family that every storage has, and that cannot be dropped.
+pub const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// The name of the default column family.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// A named keyspace of the storage, with its own memtables, L0 and levels.
+///
+/// The column families of a storage share its block cache and its WAL, so a write batch across
+/// column families is atomic. They also freeze their memtables together, and the memtables frozen
+/// together are flushed together, after which their WAL is removed.
+pub struct ColumnFamily {
+    id: u32,
+    name: String,
+    pub(crate) state: RwLock<Arc<LsmStorageState>>,
+    /// Set once the column family is dropped. Reads and writes of a dropped column family fail.
+    dropped: AtomicBool,
+}
+
+impl ColumnFamily {
+    pub(crate) fn new(id: u32, name: String, state: LsmStorageState) -> Self {
+        Self {
+            id,
+            name,
+            state: RwLock::new(Arc::new(state)),
+            dropped: AtomicBool::new(false),
+        }
+    }
+
+    /// Get the id of the column family, which is never reused by another column family.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the column family has been dropped.
+    pub fn is_dropped(&self) -> bool {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn mark_dropped(&self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+
+    /// Get the current structure of the column family.
+    pub(crate) fn snapshot(&self) -> Arc<LsmStorageState> {
+        let guard = self.state.read();
+        Arc::clone(&guard)
+    }
+}
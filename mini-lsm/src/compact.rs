@@ -0,0 +1,399 @@
+mod leveled;
+#[cfg(test)]
+mod tests;
+mod tiered;
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+pub use leveled::{LeveledCompactionController, LeveledCompactionOptions, LeveledCompactionTask};
+use serde::{Deserialize, Serialize};
+pub use tiered::{TieredCompactionController, TieredCompactionOptions, TieredCompactionTask};
+
+use crate::blob::{read_blob, BlobFile};
+use crate::column_family::ColumnFamily;
+use crate::comparator::Comparator;
+use crate::iterators::merge_iterator::MergeIterator;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorageInner, LsmStorageState};
+use crate::manifest::ManifestRecord;
+use crate::merge_operator::{full_merge, MergeOperator};
+use crate::mvcc::ValueType;
+use crate::range_tombstone::RangeTombstone;
+use crate::table::{SsTable, SsTableIterator};
+
+/// A compaction job generated by a compaction controller.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompactionTask {
+    Leveled(LeveledCompactionTask),
+    Tiered(TieredCompactionTask),
+}
+
+impl CompactionTask {
+    /// Ids of the SSTs to be merged. If the same key appears in several SSTs, the one that comes
+    /// first holds the latest version.
+    fn input_sst_ids(&self) -> Vec<usize> {
+        match self {
+            CompactionTask::Leveled(task) => {
+                let upper = task.upper_level_sst_ids.iter();
+                let upper: Vec<usize> = if task.upper_level.is_none() {
+                    // L0 SSTs are ordered from earliest to latest.
+                    upper.rev().copied().collect()
+                } else {
+                    upper.copied().collect()
+                };
+                upper
+                    .into_iter()
+                    .chain(task.lower_level_sst_ids.iter().copied())
+                    .collect()
+            }
+            // Tiers are ordered from the newest to the oldest.
+            CompactionTask::Tiered(task) => task
+                .tiers
+                .iter()
+                .flat_map(|(_, ssts)| ssts.iter().copied())
+                .collect(),
+        }
+    }
+
+    /// Whether the output goes to the bottom level, in which case tombstones can be dropped.
+    fn compact_to_bottom_level(&self) -> bool {
+        match self {
+            CompactionTask::Leveled(task) => task.is_lower_level_bottom_level,
+            CompactionTask::Tiered(task) => task.bottom_tier_included,
+        }
+    }
+}
+
+/// How SSTs are compacted.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum CompactionOptions {
+    /// Leveled compaction: each level below L0 is a single sorted run, and is merged into the
+    /// next level when it grows too large.
+    Leveled(LeveledCompactionOptions),
+    /// Tiered (universal) compaction: each flush creates a new tier, which is a sorted run, and
+    /// several tiers are merged into one. This trades read and space amplification for lower
+    /// write amplification.
+    Tiered(TieredCompactionOptions),
+    /// No compaction. All SSTs stay in L0.
+    NoCompaction,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        Self::Leveled(LeveledCompactionOptions::default())
+    }
+}
+
+impl CompactionOptions {
+    /// The name of the compaction strategy.
+    pub fn strategy(&self) -> &'static str {
+        match self {
+            CompactionOptions::Leveled(_) => "leveled",
+            CompactionOptions::Tiered(_) => "tiered",
+            CompactionOptions::NoCompaction => "no compaction",
+        }
+    }
+}
+
+pub(crate) enum CompactionController {
+    Leveled(LeveledCompactionController),
+    Tiered(TieredCompactionController),
+    NoCompaction,
+}
+
+impl CompactionController {
+    pub(crate) fn new(options: &CompactionOptions, comparator: Arc<dyn Comparator>) -> Self {
+        match options {
+            CompactionOptions::Leveled(options) => Self::Leveled(LeveledCompactionController::new(
+                options.clone(),
+                comparator,
+            )),
+            CompactionOptions::Tiered(options) => {
+                Self::Tiered(TieredCompactionController::new(options.clone()))
+            }
+            CompactionOptions::NoCompaction => Self::NoCompaction,
+        }
+    }
+
+    /// The levels of an empty LSM tree.
+    pub(crate) fn initial_levels(&self) -> Vec<(usize, Vec<usize>)> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl.initial_levels(),
+            CompactionController::Tiered(_) | CompactionController::NoCompaction => Vec::new(),
+        }
+    }
+
+    /// Add an SST flushed from a memtable to the LSM tree structure.
+    pub(crate) fn add_flushed_sst(&self, snapshot: &mut LsmStorageState, sst_id: usize) {
+        match self {
+            // Each flushed SST becomes the newest tier.
+            CompactionController::Tiered(_) => snapshot.levels.insert(0, (sst_id, vec![sst_id])),
+            CompactionController::Leveled(_) | CompactionController::NoCompaction => {
+                snapshot.l0_sstables.push(sst_id)
+            }
+        }
+    }
+
+    pub(crate) fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<CompactionTask> {
+        match self {
+            CompactionController::Leveled(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(CompactionTask::Leveled),
+            CompactionController::Tiered(ctrl) => ctrl
+                .generate_compaction_task(snapshot)
+                .map(CompactionTask::Tiered),
+            CompactionController::NoCompaction => None,
+        }
+    }
+
+    /// Replace the input SSTs of `task` with `output` in the LSM tree structure. Returns the new
+    /// structure and the ids of the SSTs to remove.
+    ///
+    /// In recovery, the SSTs are not opened yet, so the levels are left unsorted. Call
+    /// [`CompactionController::sort_levels_after_recovery`] once they are opened.
+    pub(crate) fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &CompactionTask,
+        output: &[usize],
+        in_recovery: bool,
+    ) -> (LsmStorageState, Vec<usize>) {
+        match (self, task) {
+            (CompactionController::Leveled(ctrl), CompactionTask::Leveled(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output, in_recovery)
+            }
+            (CompactionController::Tiered(ctrl), CompactionTask::Tiered(task)) => {
+                ctrl.apply_compaction_result(snapshot, task, output)
+            }
+            _ => unreachable!("compaction task does not match the compaction strategy"),
+        }
+    }
+
+    pub(crate) fn sort_levels_after_recovery(&self, snapshot: &mut LsmStorageState) {
+        if let CompactionController::Leveled(ctrl) = self {
+            ctrl.sort_levels(snapshot);
+        }
+    }
+}
+
+/// A version of a key: its sequence number, type and value.
+type Version = (u64, ValueType, Bytes);
+
+/// Drop the versions of a key, given from the newest to the oldest, that no read can see, and
+/// merge the operands that no read can see apart. A read at sequence number `r` sees the newest
+/// version not newer than `r`, and reads happen either at the latest version or at one of the
+/// live `snapshots`. If `is_bottom_level`, there is no older version of the key in the LSM tree,
+/// so tombstones at the end are dropped as well, and operands at the end are merged without a
+/// base value. A base value in a blob file is read from `blob_files`, while the other blob
+/// references are kept as they are.
+fn compact_versions(
+    key: &[u8],
+    versions: Vec<Version>,
+    snapshots: &[u64],
+    is_bottom_level: bool,
+    merge_operator: Option<&dyn MergeOperator>,
+    blob_files: &HashMap<usize, Arc<BlobFile>>,
+) -> Result<Vec<Version>> {
+    let mut compacted = Vec::new();
+    let mut versions = versions.into_iter().peekable();
+    while let Some(newest) = versions.next() {
+        // The versions of a stripe are seen by the same reads, from the newest version of the
+        // stripe up to the next snapshot, so only the newest one is visible.
+        let stripe = snapshots.iter().find(|r| **r >= newest.0).copied();
+        let in_stripe =
+            |(seq, _, _): &Version| snapshots.iter().find(|r| **r >= *seq).copied() == stripe;
+        if newest.1 != ValueType::Merge {
+            compacted.push(newest);
+            while versions.next_if(in_stripe).is_some() {}
+            continue;
+        }
+        // Collect the operands down to a put or a delete in the stripe.
+        let seq = newest.0;
+        let mut operands = vec![newest];
+        let mut base = None;
+        while let Some(version) = versions.next_if(in_stripe) {
+            match version.1 {
+                ValueType::Merge => operands.push(version),
+                ValueType::Put => {
+                    base = Some(Some(version.2));
+                    break;
+                }
+                ValueType::Blob => {
+                    base = Some(Some(read_blob(blob_files, key, &version.2)?));
+                    break;
+                }
+                ValueType::Delete => {
+                    base = Some(None);
+                    break;
+                }
+            }
+        }
+        while versions.next_if(in_stripe).is_some() {}
+        if base.is_none() && is_bottom_level && versions.peek().is_none() {
+            base = Some(None);
+        }
+        let values: Vec<Bytes> = operands.iter().map(|(_, _, value)| value.clone()).collect();
+        if let Some(base) = base {
+            let value = full_merge(merge_operator, key, base.as_deref(), &values)?;
+            compacted.push((seq, ValueType::Put, value));
+            continue;
+        }
+        // The base value is in an older stripe or level, so the operands can only be combined.
+        if operands.len() > 1 {
+            let oldest_first: Vec<&[u8]> = values.iter().rev().map(|x| &x[..]).collect();
+            if let Some(operand) =
+                merge_operator.and_then(|op| op.partial_merge(key, &oldest_first))
+            {
+                compacted.push((seq, ValueType::Merge, operand));
+                continue;
+            }
+        }
+        compacted.extend(operands);
+    }
+    if is_bottom_level {
+        while compacted
+            .last()
+            .map_or(false, |(_, value_type, _)| *value_type == ValueType::Delete)
+        {
+            compacted.pop();
+        }
+    }
+    Ok(compacted)
+}
+
+impl LsmStorageInner {
+    /// Merge the input SSTs of `task` in a column family into new SSTs of about `target_sst_size`
+    /// each. The versions of a key that are no longer visible are dropped, and merge operands are
+    /// merged, see [`compact_versions`]. All versions of a key go to the same SST, so SSTs in a
+    /// level never share a key.
+    ///
+    /// A range tombstone of the column family counts as a delete version of each key it contains,
+    /// so the versions it hides are dropped as well, and the operands newer than it are not
+    /// merged with the ones it hides, even if it is not in an input SST. The tombstones of the
+    /// input SSTs go to the first output SST, and are only dropped at the bottom level once no
+    /// snapshot is older than them.
+    fn compact(&self, cf: &ColumnFamily, task: &CompactionTask) -> Result<Vec<Arc<SsTable>>> {
+        let snapshot = cf.snapshot();
+        let input_sst_ids = task.input_sst_ids();
+        let mut iters = Vec::with_capacity(input_sst_ids.len());
+        let mut range_tombstones: Vec<RangeTombstone> = Vec::new();
+        for id in input_sst_ids {
+            let table = snapshot.sstables[&id].clone();
+            range_tombstones.extend_from_slice(table.range_tombstones());
+            iters.push(Box::new(SsTableIterator::create_and_seek_to_first(table)?));
+        }
+        let comparator = self.options.comparator.as_ref();
+        let all_range_tombstones = snapshot.range_tombstones();
+        let mut iter = MergeIterator::create(iters, self.options.comparator.clone());
+
+        let compact_to_bottom_level = task.compact_to_bottom_level();
+        let snapshots = self.snapshots.seqs();
+        let mut builder = self.new_sst_builder();
+        let oldest_snapshot = snapshots.first().copied();
+        for tombstone in &range_tombstones {
+            if !compact_to_bottom_level || oldest_snapshot.map_or(false, |r| r < tombstone.seq) {
+                builder.add_range_tombstone(tombstone.clone());
+            }
+        }
+        let mut new_ssts = Vec::new();
+        while iter.is_valid() {
+            let key = Bytes::copy_from_slice(iter.key());
+            let mut versions = Vec::new();
+            while iter.is_valid() && iter.key() == key {
+                let value = Bytes::copy_from_slice(iter.value());
+                versions.push((iter.seq(), iter.value_type(), value));
+                iter.next()?;
+            }
+            let covering_seqs: Vec<u64> = all_range_tombstones
+                .iter()
+                .filter(|tombstone| tombstone.contains(comparator, &key))
+                .map(|tombstone| tombstone.seq)
+                .collect();
+            if !covering_seqs.is_empty() {
+                versions.extend(
+                    covering_seqs
+                        .iter()
+                        .map(|&seq| (seq, ValueType::Delete, Bytes::new())),
+                );
+                versions.sort_by_key(|(seq, _, _)| Reverse(*seq));
+            }
+            let mut versions = compact_versions(
+                &key,
+                versions,
+                &snapshots,
+                compact_to_bottom_level,
+                self.options.merge_operator.as_deref(),
+                &snapshot.blob_files,
+            )?;
+            versions.retain(|(seq, _, _)| !covering_seqs.contains(seq));
+            for (seq, value_type, value) in versions {
+                builder.add(&key, seq, value_type, &value);
+            }
+            if builder.estimated_size() >= self.options.target_sst_size {
+                let builder = std::mem::replace(&mut builder, self.new_sst_builder());
+                new_ssts.push(self.build_sst(builder)?);
+            }
+        }
+        if !builder.is_empty() {
+            new_ssts.push(self.build_sst(builder)?);
+        }
+        Ok(new_ssts)
+    }
+
+    /// Run compaction tasks in every column family until the compaction controller finds nothing
+    /// more to do, and then collect the garbage in its blob files.
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_lock = self.compaction_lock.lock();
+        for cf in self.column_families() {
+            self.compact_column_family(&cf)?;
+            self.collect_blob_garbage(&cf)?;
+        }
+        Ok(())
+    }
+
+    /// Run compaction tasks in a column family until the compaction controller finds nothing more
+    /// to do. The result of each task is recorded in the manifest before it becomes visible.
+    fn compact_column_family(&self, cf: &ColumnFamily) -> Result<()> {
+        loop {
+            let snapshot = cf.snapshot();
+            let task = match self
+                .compaction_controller
+                .generate_compaction_task(&snapshot)
+            {
+                Some(task) => task,
+                None => return Ok(()),
+            };
+            let new_ssts = self.compact(cf, &task)?;
+            let output: Vec<usize> = new_ssts.iter().map(|x| x.sst_id()).collect();
+            self.sync_dir()?;
+
+            let (ssts_to_remove, blob_files_to_remove) = {
+                let mut guard = cf.state.write();
+                let mut snapshot = guard.as_ref().clone();
+                for sst in new_ssts {
+                    snapshot.sstables.insert(sst.sst_id(), sst);
+                }
+                let (mut snapshot, ssts_to_remove) = self
+                    .compaction_controller
+                    .apply_compaction_result(&snapshot, &task, &output, false);
+                for id in &ssts_to_remove {
+                    snapshot.sstables.remove(id);
+                }
+                let blob_files_to_remove = self.update_blob_files(&mut snapshot)?;
+                self.manifest
+                    .add_record(ManifestRecord::Compaction(cf.id(), task, output))?;
+                *guard = Arc::new(snapshot);
+                (ssts_to_remove, blob_files_to_remove)
+            };
+
+            self.remove_files(ssts_to_remove, blob_files_to_remove)?;
+        }
+    }
+}
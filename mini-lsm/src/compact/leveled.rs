@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::comparator::Comparator;
+use crate::lsm_storage::LsmStorageState;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeveledCompactionOptions {
+    /// Compact L0 into L1 once L0 has this many SSTs.
+    pub level0_file_num_compaction_trigger: usize,
+    /// Number of levels below L0.
+    pub max_levels: usize,
+    /// Target size of L1 in bytes.
+    pub base_level_size: u64,
+    /// Each level is this many times larger than the level above it.
+    pub level_size_multiplier: u64,
+}
+
+impl Default for LeveledCompactionOptions {
+    fn default() -> Self {
+        Self {
+            level0_file_num_compaction_trigger: 4,
+            max_levels: 4,
+            base_level_size: 64 << 20,
+            level_size_multiplier: 10,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeveledCompactionTask {
+    /// The upper level, or `None` for L0.
+    pub upper_level: Option<usize>,
+    pub upper_level_sst_ids: Vec<usize>,
+    pub lower_level: usize,
+    pub lower_level_sst_ids: Vec<usize>,
+    pub is_lower_level_bottom_level: bool,
+}
+
+pub struct LeveledCompactionController {
+    options: LeveledCompactionOptions,
+    /// Orders the key ranges of the SSTs.
+    comparator: Arc<dyn Comparator>,
+}
+
+impl LeveledCompactionController {
+    pub fn new(options: LeveledCompactionOptions, comparator: Arc<dyn Comparator>) -> Self {
+        Self {
+            options,
+            comparator,
+        }
+    }
+
+    pub(crate) fn initial_levels(&self) -> Vec<(usize, Vec<usize>)> {
+        (1..=self.options.max_levels)
+            .map(|level| (level, Vec::new()))
+            .collect()
+    }
+
+    fn target_level_size(&self, level: usize) -> u64 {
+        self.options.base_level_size * self.options.level_size_multiplier.pow(level as u32 - 1)
+    }
+
+    fn level_size(snapshot: &LsmStorageState, level: usize) -> u64 {
+        snapshot.levels[level - 1]
+            .1
+            .iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    /// Find the SSTs in `level` whose key range overlaps with the key range of `sst_ids`.
+    fn find_overlapping_ssts(
+        &self,
+        snapshot: &LsmStorageState,
+        sst_ids: &[usize],
+        level: usize,
+    ) -> Vec<usize> {
+        let comparator = self.comparator.as_ref();
+        let first_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].first_key())
+            .min_by(|a, b| comparator.compare(a, b))
+            .unwrap();
+        let last_key = sst_ids
+            .iter()
+            .map(|id| snapshot.sstables[id].last_key())
+            .max_by(|a, b| comparator.compare(a, b))
+            .unwrap();
+        snapshot.levels[level - 1]
+            .1
+            .iter()
+            .filter(|id| {
+                let table = &snapshot.sstables[*id];
+                comparator.compare(table.first_key(), last_key).is_le()
+                    && comparator.compare(table.last_key(), first_key).is_ge()
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Compact L0 into L1 once the L0 SST count reaches the trigger. Otherwise, pick the level
+    /// that exceeds its target size by the largest ratio, and merge its oldest SST with the
+    /// overlapping SSTs of the next level.
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<LeveledCompactionTask> {
+        if !snapshot.l0_sstables.is_empty()
+            && snapshot.l0_sstables.len() >= self.options.level0_file_num_compaction_trigger
+        {
+            return Some(LeveledCompactionTask {
+                upper_level: None,
+                upper_level_sst_ids: snapshot.l0_sstables.clone(),
+                lower_level: 1,
+                lower_level_sst_ids: self.find_overlapping_ssts(snapshot, &snapshot.l0_sstables, 1),
+                is_lower_level_bottom_level: self.options.max_levels == 1,
+            });
+        }
+
+        let mut level_to_compact = None;
+        let mut max_ratio = 1.0;
+        for level in 1..self.options.max_levels {
+            let ratio =
+                Self::level_size(snapshot, level) as f64 / self.target_level_size(level) as f64;
+            if ratio > max_ratio {
+                level_to_compact = Some(level);
+                max_ratio = ratio;
+            }
+        }
+        let level = level_to_compact?;
+        let sst_id = *snapshot.levels[level - 1].1.iter().min().unwrap();
+        Some(LeveledCompactionTask {
+            upper_level: Some(level),
+            upper_level_sst_ids: vec![sst_id],
+            lower_level: level + 1,
+            lower_level_sst_ids: self.find_overlapping_ssts(snapshot, &[sst_id], level + 1),
+            is_lower_level_bottom_level: level + 1 == self.options.max_levels,
+        })
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &LeveledCompactionTask,
+        output: &[usize],
+        in_recovery: bool,
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let upper_level_sst_ids: HashSet<usize> =
+            task.upper_level_sst_ids.iter().copied().collect();
+        let lower_level_sst_ids: HashSet<usize> =
+            task.lower_level_sst_ids.iter().copied().collect();
+
+        // New L0 SSTs may have been flushed while compacting, so only remove the compacted ones.
+        let upper_level = match task.upper_level {
+            Some(level) => &mut snapshot.levels[level - 1].1,
+            None => &mut snapshot.l0_sstables,
+        };
+        upper_level.retain(|id| !upper_level_sst_ids.contains(id));
+
+        let lower_level = &mut snapshot.levels[task.lower_level - 1].1;
+        lower_level.retain(|id| !lower_level_sst_ids.contains(id));
+        lower_level.extend(output);
+        if !in_recovery {
+            let sstables = &snapshot.sstables;
+            lower_level.sort_by(|x, y| {
+                self.comparator
+                    .compare(sstables[x].first_key(), sstables[y].first_key())
+            });
+        }
+
+        let mut ssts_to_remove = task.upper_level_sst_ids.clone();
+        ssts_to_remove.extend(&task.lower_level_sst_ids);
+        (snapshot, ssts_to_remove)
+    }
+
+    /// Sort the SSTs of each level by key range.
+    pub(crate) fn sort_levels(&self, snapshot: &mut LsmStorageState) {
+        let sstables = &snapshot.sstables;
+        for (_, ssts) in &mut snapshot.levels {
+            ssts.sort_by(|x, y| {
+                self.comparator
+                    .compare(sstables[x].first_key(), sstables[y].first_key())
+            });
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::*;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::tests::merge_tests::AppendOperator;
+
+#[test]
+fn test_compact_with_range_tombstone_outside_task() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::NoCompaction,
+        merge_operator: Some(Arc::new(AppendOperator)),
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    storage.merge(b"k", b"a").unwrap();
+    storage.sync().unwrap();
+    storage.delete_range(b"k", b"l").unwrap();
+    storage.sync().unwrap();
+    storage.merge(b"k", b"b").unwrap();
+    storage.sync().unwrap();
+
+    // Compact the SSTs of the operands, but not the one of the range tombstone between them.
+    let cf = storage.inner.default_cf.clone();
+    let l0_sstables = cf.snapshot().l0_sstables.clone();
+    assert_eq!(l0_sstables.len(), 3);
+    let task = CompactionTask::Leveled(LeveledCompactionTask {
+        upper_level: None,
+        upper_level_sst_ids: vec![l0_sstables[0], l0_sstables[2]],
+        lower_level: 1,
+        lower_level_sst_ids: vec![],
+        is_lower_level_bottom_level: false,
+    });
+    let new_ssts = storage.inner.compact(&cf, &task).unwrap();
+    assert_eq!(new_ssts.len(), 1);
+    let mut iter = SsTableIterator::create_and_seek_to_first(new_ssts[0].clone()).unwrap();
+    assert_eq!(iter.key(), b"k");
+    assert_eq!(iter.value_type(), ValueType::Put);
+    assert_eq!(iter.value(), Bytes::from("b"));
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+}
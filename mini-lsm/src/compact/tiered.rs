@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lsm_storage::LsmStorageState;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TieredCompactionOptions {
+    /// Do not compact until there are at least this many tiers.
+    pub num_tiers: usize,
+    /// Compact all tiers into one when the size of all tiers except the oldest one exceeds this
+    /// percentage of the size of the oldest one.
+    pub max_size_amplification_percent: usize,
+    /// Compact the newer tiers together when the next tier is larger than them combined by more
+    /// than this percentage.
+    pub size_ratio: usize,
+    /// Minimum number of tiers to compact together by the size ratio trigger.
+    pub min_merge_width: usize,
+}
+
+impl Default for TieredCompactionOptions {
+    fn default() -> Self {
+        Self {
+            num_tiers: 8,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TieredCompactionTask {
+    /// The tiers to compact, as (tier id, SST ids), from the newest to the oldest.
+    pub tiers: Vec<(usize, Vec<usize>)>,
+    pub bottom_tier_included: bool,
+}
+
+pub struct TieredCompactionController {
+    options: TieredCompactionOptions,
+}
+
+impl TieredCompactionController {
+    pub fn new(options: TieredCompactionOptions) -> Self {
+        Self { options }
+    }
+
+    fn tier_size(snapshot: &LsmStorageState, tier: &[usize]) -> u64 {
+        tier.iter()
+            .map(|id| snapshot.sstables[id].table_size())
+            .sum()
+    }
+
+    fn compact_newest_tiers(snapshot: &LsmStorageState, num_tiers: usize) -> TieredCompactionTask {
+        TieredCompactionTask {
+            tiers: snapshot.levels[..num_tiers].to_vec(),
+            bottom_tier_included: num_tiers == snapshot.levels.len(),
+        }
+    }
+
+    /// Generate a task once there are at least `num_tiers` tiers. The triggers are checked in
+    /// order: space amplification, which compacts all tiers; size ratio, which compacts the newer
+    /// tiers that are small compared to the next one; and otherwise, the newest tiers are
+    /// compacted to bring the number of tiers below `num_tiers`.
+    pub fn generate_compaction_task(
+        &self,
+        snapshot: &LsmStorageState,
+    ) -> Option<TieredCompactionTask> {
+        let num_levels = snapshot.levels.len();
+        if num_levels < self.options.num_tiers || num_levels < 2 {
+            return None;
+        }
+
+        let sizes: Vec<u64> = snapshot
+            .levels
+            .iter()
+            .map(|(_, tier)| Self::tier_size(snapshot, tier))
+            .collect();
+
+        // Space amplification trigger.
+        let bottom_size = sizes[num_levels - 1];
+        let upper_size: u64 = sizes[..num_levels - 1].iter().sum();
+        if upper_size * 100 >= bottom_size * self.options.max_size_amplification_percent as u64 {
+            return Some(Self::compact_newest_tiers(snapshot, num_levels));
+        }
+
+        // Size ratio trigger.
+        let mut size = 0;
+        for tier in 0..num_levels - 1 {
+            size += sizes[tier];
+            let next_tier_size = sizes[tier + 1];
+            if next_tier_size * 100 > size * (100 + self.options.size_ratio as u64)
+                && tier + 1 >= self.options.min_merge_width
+            {
+                return Some(Self::compact_newest_tiers(snapshot, tier + 1));
+            }
+        }
+
+        // Reduce the number of sorted runs.
+        let num_tiers_to_compact = (num_levels + 2 - self.options.num_tiers).min(num_levels);
+        Some(Self::compact_newest_tiers(snapshot, num_tiers_to_compact))
+    }
+
+    pub fn apply_compaction_result(
+        &self,
+        snapshot: &LsmStorageState,
+        task: &TieredCompactionTask,
+        output: &[usize],
+    ) -> (LsmStorageState, Vec<usize>) {
+        let mut snapshot = snapshot.clone();
+        let tier_ids: HashSet<usize> = task.tiers.iter().map(|(id, _)| *id).collect();
+
+        // New tiers may have been flushed while compacting. The compacted tiers are still
+        // adjacent, so the new tier takes the place of the first one.
+        let position = snapshot
+            .levels
+            .iter()
+            .position(|(id, _)| tier_ids.contains(id))
+            .expect("compacted tier not found");
+        snapshot.levels.retain(|(id, _)| !tier_ids.contains(id));
+        if !output.is_empty() {
+            snapshot
+                .levels
+                .insert(position, (output[0], output.to_vec()));
+        }
+
+        let ssts_to_remove = task
+            .tiers
+            .iter()
+            .flat_map(|(_, ssts)| ssts.iter().copied())
+            .collect();
+        (snapshot, ssts_to_remove)
+    }
+}
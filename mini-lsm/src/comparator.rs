@@ -0,0 +1,70 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Bound;
+
+/// Orders the keys of the storage. Every component that orders keys — memtables, SSTs, iterators
+/// and compaction — uses the comparator the storage is opened with.
+///
+/// Its name is persisted with the storage, which cannot be reopened with a comparator of another
+/// name, so a comparator must keep the same order as long as it keeps its name.
+pub trait Comparator: Send + Sync {
+    /// The name of the comparator.
+    fn name(&self) -> &str;
+
+    /// Compare two keys. The order must be total, and only equal keys may compare as equal.
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+}
+
+impl fmt::Debug for dyn Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Orders keys byte-wise, which is the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "bytewise"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+}
+
+/// Orders keys byte-wise, from the largest to the smallest.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReverseBytewiseComparator;
+
+impl Comparator for ReverseBytewiseComparator {
+    fn name(&self) -> &str {
+        "reverse_bytewise"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+}
+
+/// Check if `key` is within the range between `lower` and `upper`, as ordered by `comparator`.
+pub(crate) fn range_contains(
+    comparator: &dyn Comparator,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    key: &[u8],
+) -> bool {
+    let above_lower = match lower {
+        Bound::Included(lower) => comparator.compare(key, lower).is_ge(),
+        Bound::Excluded(lower) => comparator.compare(key, lower).is_gt(),
+        Bound::Unbounded => true,
+    };
+    let below_upper = match upper {
+        Bound::Included(upper) => comparator.compare(key, upper).is_le(),
+        Bound::Excluded(upper) => comparator.compare(key, upper).is_lt(),
+        Bound::Unbounded => true,
+    };
+    above_lower && below_upper
+}
@@ -0,0 +1,65 @@
+use bytes::Bytes;
+use thiserror::Error;
+
+/// Errors of the storage engine that callers may want to handle. They are returned wrapped in
+/// `anyhow::Error`, and can be recovered with `downcast_ref`.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum Error {
+    /// The data read from an SST does not match its checksum, or cannot be decoded.
+    #[error("corruption in SST {file_id} at offset {offset} (block {block_idx:?})")]
+    Corruption {
+        file_id: usize,
+        /// The data block, or `None` for the metadata of the SST.
+        block_idx: Option<usize>,
+        offset: u64,
+    },
+    /// A record of the manifest does not match its checksum, and it is not the last record, so it
+    /// is not the result of a crash while writing it.
+    #[error("corruption in the manifest at offset {offset}")]
+    ManifestCorruption { offset: u64 },
+    /// A record read from a blob file does not match its checksum or the key referring to it, or
+    /// the blob file does not exist.
+    #[error("corruption in blob file {file_id} at offset {offset}")]
+    BlobCorruption { file_id: usize, offset: u64 },
+    /// An SST is written in a version of the format that this build cannot read.
+    #[error("SST {file_id} has unsupported format version {version}")]
+    UnsupportedFormat { file_id: usize, version: u32 },
+    /// A transaction did not commit, because `key` was written by someone else since the
+    /// transaction began.
+    #[error("transaction conflict on key {key:?}")]
+    TransactionConflict { key: Bytes },
+    /// A key or a value is larger than the storage supports.
+    #[error("{what} of {len} bytes is larger than the limit of {limit} bytes")]
+    TooLarge {
+        what: &'static str,
+        len: u64,
+        limit: u64,
+    },
+    /// The storage was closed, so it takes no more writes.
+    #[error("the storage is closed")]
+    Closed,
+    /// A background thread failed to flush or compact, and has not succeeded since.
+    #[error("background {thread} failed: {message}")]
+    BackgroundFailed {
+        thread: &'static str,
+        message: String,
+    },
+    /// An option given to [`LsmStorage::open`] is out of range.
+    ///
+    /// [`LsmStorage::open`]: crate::lsm_storage::LsmStorage::open
+    #[error("invalid option {option}: {reason}")]
+    InvalidOptions {
+        option: &'static str,
+        reason: String,
+    },
+    /// An option given to [`LsmStorage::open`] differs from the one the storage was created with,
+    /// and cannot change.
+    ///
+    /// [`LsmStorage::open`]: crate::lsm_storage::LsmStorage::open
+    #[error("option {option} is {given}, but the storage was created with {persisted}")]
+    IncompatibleOptions {
+        option: &'static str,
+        persisted: String,
+        given: String,
+    },
+}
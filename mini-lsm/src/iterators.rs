@@ -1,13 +1,22 @@
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
+use crate::mvcc::ValueType;
+
 pub trait StorageIterator {
-    /// Get the current value.
+    /// Get the current value. It is empty for a delete.
     fn value(&self) -> &[u8];
 
+    /// Get whether the current entry is a put or a delete.
+    fn value_type(&self) -> ValueType;
+
     /// Get the current key.
     fn key(&self) -> &[u8];
 
+    /// Get the sequence number of the current entry. Entries with the same key are ordered from
+    /// the newest (the largest sequence number) to the oldest.
+    fn seq(&self) -> u64;
+
     /// Check if the current iterator is valid.
     fn is_valid(&self) -> bool;
 
@@ -15,5 +24,60 @@ pub trait StorageIterator {
     fn next(&mut self) -> anyhow::Result<()>;
 }
 
+/// A [`StorageIterator`] that can also be repositioned and move backward.
+pub trait BidirectionalIterator: StorageIterator {
+    /// Seek to the first entry.
+    fn seek_to_first(&mut self) -> anyhow::Result<()>;
+
+    /// Seek to the last entry.
+    fn seek_to_last(&mut self) -> anyhow::Result<()>;
+
+    /// Seek to the newest version of the first key that >= `key`. The iterator is repositioned in
+    /// place, so it can be reused to page through a range.
+    fn seek(&mut self, key: &[u8]) -> anyhow::Result<()>;
+
+    /// Seek to the last entry whose key <= `key`. Of the versions of a key, the oldest is the last.
+    fn seek_for_prev(&mut self, key: &[u8]) -> anyhow::Result<()>;
+
+    /// Move to the previous position. The iterator is not valid after moving before the first
+    /// entry.
+    fn prev(&mut self) -> anyhow::Result<()>;
+}
+
+/// The direction an iterator moves in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Forward,
+    Backward,
+}
+
+/// Position `iter` at the first entry after the version of `key` with sequence number `seq`,
+/// whether or not `iter` has that version.
+pub(crate) fn seek_after<I: BidirectionalIterator + ?Sized>(
+    iter: &mut I,
+    key: &[u8],
+    seq: u64,
+) -> anyhow::Result<()> {
+    iter.seek(key)?;
+    while iter.is_valid() && iter.key() == key && iter.seq() >= seq {
+        iter.next()?;
+    }
+    Ok(())
+}
+
+/// Position `iter` at the last entry before the version of `key` with sequence number `seq`,
+/// whether or not `iter` has that version.
+pub(crate) fn seek_before<I: BidirectionalIterator + ?Sized>(
+    iter: &mut I,
+    key: &[u8],
+    seq: u64,
+) -> anyhow::Result<()> {
+    iter.seek_for_prev(key)?;
+    while iter.is_valid() && iter.key() == key && iter.seq() <= seq {
+        iter.prev()?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests;
@@ -1,12 +1,21 @@
 use std::cmp::{self};
 use std::collections::binary_heap::PeekMut;
 use std::collections::BinaryHeap;
+use std::sync::Arc;
 
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{seek_after, seek_before, BidirectionalIterator, Direction, StorageIterator};
+use crate::comparator::Comparator;
+use crate::mvcc::{compare_entries, ValueType};
 
-struct HeapWrapper<I: StorageIterator>(pub usize, pub Box<I>);
+/// An iterator in the heap, with its index, the comparator and the direction of the merge.
+struct HeapWrapper<I: StorageIterator>(
+    pub usize,
+    pub Box<I>,
+    pub Arc<dyn Comparator>,
+    pub Direction,
+);
 
 impl<I: StorageIterator> PartialEq for HeapWrapper<I> {
     fn eq(&self, other: &Self) -> bool {
@@ -18,12 +27,18 @@ impl<I: StorageIterator> Eq for HeapWrapper<I> {}
 
 impl<I: StorageIterator> PartialOrd for HeapWrapper<I> {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
-        match self.1.key().cmp(other.1.key()) {
-            cmp::Ordering::Greater => Some(cmp::Ordering::Greater),
-            cmp::Ordering::Less => Some(cmp::Ordering::Less),
-            cmp::Ordering::Equal => self.0.partial_cmp(&other.0),
+        match compare_entries(
+            self.2.as_ref(),
+            self.1.key(),
+            self.1.seq(),
+            other.1.key(),
+            other.1.seq(),
+        ) {
+            cmp::Ordering::Equal => self.0.partial_cmp(&other.0).map(|x| x.reverse()),
+            // The heap pops the largest, which is the smallest entry when moving forward.
+            order if self.3 == Direction::Forward => Some(order.reverse()),
+            order => Some(order),
         }
-        .map(|x| x.reverse())
     }
 }
 
@@ -33,95 +48,99 @@ impl<I: StorageIterator> Ord for HeapWrapper<I> {
     }
 }
 
-/// Merge multiple iterators of the same type. If the same key occurs multiple times in some
-/// iterators, perfer the one with smaller index.
-pub struct MergeIterator<I: StorageIterator> {
+/// Merge multiple iterators of the same type, whose keys are ordered by `comparator`. If the same
+/// key with the same sequence number occurs multiple times in some iterators, perfer the one with
+/// smaller index.
+///
+/// When the merge changes direction, every iterator is repositioned around the current entry.
+pub struct MergeIterator<I: BidirectionalIterator> {
     iters: BinaryHeap<HeapWrapper<I>>,
     current: Option<HeapWrapper<I>>,
+    /// The iterators that are not valid, kept to be repositioned.
+    exhausted: Vec<HeapWrapper<I>>,
+    direction: Direction,
 }
 
-impl<I: StorageIterator> MergeIterator<I> {
-    pub fn create(iters: Vec<Box<I>>) -> Self {
-        if iters.is_empty() {
-            return Self {
-                iters: BinaryHeap::new(),
-                current: None,
-            };
-        }
-
-        let mut heap = BinaryHeap::new();
-
-        if iters.iter().all(|x| !x.is_valid()) {
-            // All invalid, select the last one as the current.
-            let mut iters = iters;
-            return Self {
-                iters: heap,
-                current: Some(HeapWrapper(0, iters.pop().unwrap())),
-            };
-        }
+impl<I: BidirectionalIterator> MergeIterator<I> {
+    pub fn create(iters: Vec<Box<I>>, comparator: Arc<dyn Comparator>) -> Self {
+        let mut iter = Self {
+            iters: BinaryHeap::new(),
+            current: None,
+            exhausted: iters
+                .into_iter()
+                .enumerate()
+                .map(|(idx, iter)| HeapWrapper(idx, iter, comparator.clone(), Direction::Forward))
+                .collect(),
+            direction: Direction::Forward,
+        };
+        iter.rebuild(Direction::Forward);
+        iter
+    }
 
-        for (idx, iter) in iters.into_iter().enumerate() {
-            if iter.is_valid() {
-                heap.push(HeapWrapper(idx, iter));
+    /// Select the current iterator again after the iterators are repositioned to move in
+    /// `direction`.
+    fn rebuild(&mut self, direction: Direction) {
+        self.direction = direction;
+        let mut iters = std::mem::take(&mut self.iters).into_vec();
+        iters.append(&mut self.exhausted);
+        iters.extend(self.current.take());
+        for mut iter in iters {
+            iter.3 = direction;
+            if iter.1.is_valid() {
+                self.iters.push(iter);
+            } else {
+                self.exhausted.push(iter);
             }
         }
-
-        let current = heap.pop().unwrap();
-        Self {
-            iters: heap,
-            current: Some(current),
-        }
+        // If all iterators are invalid, select any of them as the current.
+        self.current = self.iters.pop().or_else(|| self.exhausted.pop());
     }
-}
 
-impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
-    fn key(&self) -> &[u8] {
-        unsafe { self.current.as_ref().unwrap_unchecked() }.1.key()
-    }
-
-    fn value(&self) -> &[u8] {
-        unsafe { self.current.as_ref().unwrap_unchecked() }
-            .1
-            .value()
+    /// Reposition every iterator with `f`, and then move in `direction`.
+    fn reposition(
+        &mut self,
+        direction: Direction,
+        mut f: impl FnMut(&mut I) -> Result<()>,
+    ) -> Result<()> {
+        self.exhausted
+            .extend(self.iters.drain().chain(self.current.take()));
+        let result = self
+            .exhausted
+            .iter_mut()
+            .try_for_each(|iter| f(iter.1.as_mut()));
+        self.rebuild(direction);
+        result
     }
 
-    fn is_valid(&self) -> bool {
-        self.current
-            .as_ref()
-            .map(|x| x.1.is_valid())
-            .unwrap_or(false)
-    }
-
-    fn next(&mut self) -> Result<()> {
+    /// Move the current iterator, and the other iterators at the current entry, to the next entry
+    /// in the direction of the merge with `step`.
+    fn step(&mut self, step: impl Fn(&mut I) -> Result<()>) -> Result<()> {
         let current = unsafe { self.current.as_mut().unwrap_unchecked() };
         // Pop the item out of the heap if they have the same value.
         while let Some(mut inner_iter) = self.iters.peek_mut() {
-            debug_assert!(
-                inner_iter.1.key() >= current.1.key(),
-                "heap invariant violated"
-            );
-            if inner_iter.1.key() == current.1.key() {
-                // Case 1: an error occurred when calling `next`.
-                if let e @ Err(_) = inner_iter.1.next() {
-                    PeekMut::pop(inner_iter);
+            debug_assert!(*inner_iter <= *current, "heap invariant violated");
+            if inner_iter.1.key() == current.1.key() && inner_iter.1.seq() == current.1.seq() {
+                // Case 1: an error occurred when calling `step`.
+                if let e @ Err(_) = step(&mut inner_iter.1) {
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                     return e;
                 }
 
                 // Case 2: iter is no longer valid.
                 if !inner_iter.1.is_valid() {
-                    PeekMut::pop(inner_iter);
+                    self.exhausted.push(PeekMut::pop(inner_iter));
                 }
             } else {
                 break;
             }
         }
 
-        current.1.next()?;
+        step(&mut current.1)?;
 
         // If the current iterator is invalid, pop it out of the heap and select the next one.
         if !current.1.is_valid() {
             if let Some(iter) = self.iters.pop() {
-                *current = iter;
+                self.exhausted.push(std::mem::replace(current, iter));
             }
             return Ok(());
         }
@@ -136,3 +155,66 @@ impl<I: StorageIterator> StorageIterator for MergeIterator<I> {
         Ok(())
     }
 }
+
+impl<I: BidirectionalIterator> StorageIterator for MergeIterator<I> {
+    fn key(&self) -> &[u8] {
+        unsafe { self.current.as_ref().unwrap_unchecked() }.1.key()
+    }
+
+    fn seq(&self) -> u64 {
+        unsafe { self.current.as_ref().unwrap_unchecked() }.1.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        unsafe { self.current.as_ref().unwrap_unchecked() }
+            .1
+            .value_type()
+    }
+
+    fn value(&self) -> &[u8] {
+        unsafe { self.current.as_ref().unwrap_unchecked() }
+            .1
+            .value()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.current
+            .as_ref()
+            .map(|x| x.1.is_valid())
+            .unwrap_or(false)
+    }
+
+    fn next(&mut self) -> Result<()> {
+        if self.direction == Direction::Backward {
+            let (key, seq) = (self.key().to_vec(), self.seq());
+            return self.reposition(Direction::Forward, |iter| seek_after(iter, &key, seq));
+        }
+        self.step(|iter| iter.next())
+    }
+}
+
+impl<I: BidirectionalIterator> BidirectionalIterator for MergeIterator<I> {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.reposition(Direction::Forward, |iter| iter.seek_to_first())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.reposition(Direction::Backward, |iter| iter.seek_to_last())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.reposition(Direction::Forward, |iter| iter.seek(key))
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.reposition(Direction::Backward, |iter| iter.seek_for_prev(key))
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            let (key, seq) = (self.key().to_vec(), self.seq());
+            return self.reposition(Direction::Backward, |iter| seek_before(iter, &key, seq));
+        }
+        self.step(|iter| iter.prev())
+    }
+}
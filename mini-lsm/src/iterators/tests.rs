@@ -1,11 +1,19 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use bytes::Bytes;
 
-use super::StorageIterator;
+use super::{BidirectionalIterator, StorageIterator};
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::mvcc::ValueType;
 
 pub mod merge_iterator_test;
 pub mod two_merge_iterator_test;
 
+pub fn bytewise() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
 #[derive(Clone)]
 pub struct MockIterator {
     pub data: Vec<(Bytes, Bytes)>,
@@ -30,6 +38,14 @@ impl StorageIterator for MockIterator {
         self.data[self.index].0.as_ref()
     }
 
+    fn seq(&self) -> u64 {
+        0
+    }
+
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
+    }
+
     fn value(&self) -> &[u8] {
         self.data[self.index].1.as_ref()
     }
@@ -38,3 +54,31 @@ impl StorageIterator for MockIterator {
         self.index < self.data.len()
     }
 }
+
+impl BidirectionalIterator for MockIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.index = 0;
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.index = self.data.len().saturating_sub(1);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.index = self.data.partition_point(|(x, _)| &x[..] < key);
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let index = self.data.partition_point(|(x, _)| &x[..] <= key);
+        self.index = index.checked_sub(1).unwrap_or(self.data.len());
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.index = self.index.checked_sub(1).unwrap_or(self.data.len());
+        Ok(())
+    }
+}
@@ -47,11 +47,14 @@ fn test_merge_1() {
         (Bytes::from("d"), Bytes::from("4.3")),
     ]);
 
-    let iter = MergeIterator::create(vec![
-        Box::new(i1.clone()),
-        Box::new(i2.clone()),
-        Box::new(i3.clone()),
-    ]);
+    let iter = MergeIterator::create(
+        vec![
+            Box::new(i1.clone()),
+            Box::new(i2.clone()),
+            Box::new(i3.clone()),
+        ],
+        bytewise(),
+    );
 
     check_iter_result(
         iter,
@@ -63,7 +66,7 @@ fn test_merge_1() {
         ],
     );
 
-    let iter = MergeIterator::create(vec![Box::new(i3), Box::new(i1), Box::new(i2)]);
+    let iter = MergeIterator::create(vec![Box::new(i3), Box::new(i1), Box::new(i2)], bytewise());
 
     check_iter_result(
         iter,
@@ -110,28 +113,120 @@ fn test_merge_2() {
         (Bytes::from("k"), Bytes::from("4.3")),
     ];
 
-    let iter = MergeIterator::create(vec![
-        Box::new(i1.clone()),
-        Box::new(i2.clone()),
-        Box::new(i3.clone()),
-        Box::new(i4.clone()),
-    ]);
+    let iter = MergeIterator::create(
+        vec![
+            Box::new(i1.clone()),
+            Box::new(i2.clone()),
+            Box::new(i3.clone()),
+            Box::new(i4.clone()),
+        ],
+        bytewise(),
+    );
     check_iter_result(iter, result.clone());
 
-    let iter = MergeIterator::create(vec![
-        Box::new(i2.clone()),
-        Box::new(i4.clone()),
-        Box::new(i3.clone()),
-        Box::new(i1.clone()),
-    ]);
+    let iter = MergeIterator::create(
+        vec![
+            Box::new(i2.clone()),
+            Box::new(i4.clone()),
+            Box::new(i3.clone()),
+            Box::new(i1.clone()),
+        ],
+        bytewise(),
+    );
     check_iter_result(iter, result.clone());
 
-    let iter = MergeIterator::create(vec![Box::new(i4), Box::new(i3), Box::new(i2), Box::new(i1)]);
+    let iter = MergeIterator::create(
+        vec![Box::new(i4), Box::new(i3), Box::new(i2), Box::new(i1)],
+        bytewise(),
+    );
     check_iter_result(iter, result);
 }
 
 #[test]
 fn test_merge_empty() {
-    let iter = MergeIterator::<MockIterator>::create(vec![]);
+    let iter = MergeIterator::<MockIterator>::create(vec![], bytewise());
     check_iter_result(iter, vec![]);
 }
+
+#[test]
+fn test_merge_backward() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let i3 = MockIterator::new(vec![]);
+    let mut iter =
+        MergeIterator::create(vec![Box::new(i1), Box::new(i2), Box::new(i3)], bytewise());
+
+    iter.seek_to_last().unwrap();
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((as_bytes(iter.key()), as_bytes(iter.value())));
+        iter.prev().unwrap();
+    }
+    assert_eq!(
+        result,
+        vec![
+            (Bytes::from("d"), Bytes::from("4.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("a"), Bytes::from("1.1")),
+        ]
+    );
+
+    // Change direction in the middle.
+    iter.seek_for_prev(b"bb").unwrap();
+    assert_eq!(iter.key(), b"b");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"b");
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"1.1");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"b");
+    iter.seek_to_first().unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("a"), Bytes::from("1.1")),
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+    );
+}
+
+#[test]
+fn test_merge_seek() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let mut iter = MergeIterator::create(vec![Box::new(i1), Box::new(i2)], bytewise());
+
+    iter.seek(b"bb").unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.seek(b"a").unwrap();
+    assert_eq!(iter.value(), b"1.1");
+    iter.seek_to_last().unwrap();
+    iter.seek(b"b").unwrap();
+    check_iter_result(
+        iter,
+        vec![
+            (Bytes::from("b"), Bytes::from("2.2")),
+            (Bytes::from("c"), Bytes::from("3.1")),
+            (Bytes::from("d"), Bytes::from("4.2")),
+        ],
+    );
+}
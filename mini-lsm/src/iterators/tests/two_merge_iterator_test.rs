@@ -25,7 +25,7 @@ fn test_merge_1() {
         (Bytes::from("c"), Bytes::from("3.2")),
         (Bytes::from("d"), Bytes::from("4.2")),
     ]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(
         iter,
         vec![
@@ -50,7 +50,7 @@ fn test_merge_2() {
         (Bytes::from("c"), Bytes::from("3.2")),
         (Bytes::from("d"), Bytes::from("4.2")),
     ]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(
         iter,
         vec![
@@ -74,7 +74,7 @@ fn test_merge_3() {
         (Bytes::from("c"), Bytes::from("3.2")),
         (Bytes::from("d"), Bytes::from("4.2")),
     ]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(
         iter,
         vec![
@@ -94,7 +94,7 @@ fn test_merge_4() {
         (Bytes::from("c"), Bytes::from("3.2")),
         (Bytes::from("d"), Bytes::from("4.2")),
     ]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(
         iter,
         vec![
@@ -109,7 +109,7 @@ fn test_merge_4() {
         (Bytes::from("c"), Bytes::from("3.2")),
         (Bytes::from("d"), Bytes::from("4.2")),
     ]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(
         iter,
         vec![
@@ -124,6 +124,42 @@ fn test_merge_4() {
 fn test_merge_5() {
     let i2 = MockIterator::new(vec![]);
     let i1 = MockIterator::new(vec![]);
-    let iter = TwoMergeIterator::create(i1, i2).unwrap();
+    let iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
     check_iter_result(iter, vec![])
 }
+
+#[test]
+fn test_merge_backward() {
+    let i1 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.1")),
+        (Bytes::from("c"), Bytes::from("3.1")),
+    ]);
+    let i2 = MockIterator::new(vec![
+        (Bytes::from("a"), Bytes::from("1.2")),
+        (Bytes::from("b"), Bytes::from("2.2")),
+        (Bytes::from("d"), Bytes::from("4.2")),
+    ]);
+    let mut iter = TwoMergeIterator::create(i1, i2, bytewise()).unwrap();
+    iter.seek_to_last().unwrap();
+    for (key, value) in [("d", "4.2"), ("c", "3.1"), ("b", "2.2"), ("a", "1.1")] {
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    iter.seek_for_prev(b"c").unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), b"b");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"c");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"d");
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    assert_eq!(iter.value(), b"1.1");
+    iter.next().unwrap();
+    assert_eq!(iter.key(), b"b");
+}
@@ -1,48 +1,76 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 
-use super::StorageIterator;
+use super::{seek_after, seek_before, BidirectionalIterator, Direction, StorageIterator};
+use crate::comparator::Comparator;
+use crate::mvcc::{compare_entries, ValueType};
 
-/// Merges two iterators of different types into one. If the two iterators have the same key, only
-/// produce the key once and prefer the entry from A.
-pub struct TwoMergeIterator<A: StorageIterator, B: StorageIterator> {
+/// Merges two iterators of different types, whose keys are ordered by `comparator`, into one. If
+/// the two iterators have the same key with the same sequence number, only produce the entry once
+/// and prefer the entry from A.
+///
+/// When the merge changes direction, both iterators are repositioned around the current entry.
+pub struct TwoMergeIterator<A: BidirectionalIterator, B: BidirectionalIterator> {
     a: A,
     b: B,
     choose_a: bool,
+    comparator: Arc<dyn Comparator>,
+    direction: Direction,
 }
 
-impl<A: StorageIterator, B: StorageIterator> TwoMergeIterator<A, B> {
-    fn choose_a(a: &A, b: &B) -> bool {
+impl<A: BidirectionalIterator, B: BidirectionalIterator> TwoMergeIterator<A, B> {
+    fn choose_a(comparator: &dyn Comparator, a: &A, b: &B, direction: Direction) -> bool {
         if !a.is_valid() {
             return false;
         }
         if !b.is_valid() {
             return true;
         }
-        a.key() < b.key()
+        let order = compare_entries(comparator, a.key(), a.seq(), b.key(), b.seq());
+        match direction {
+            Direction::Forward => order.is_lt(),
+            Direction::Backward => order.is_gt(),
+        }
     }
 
     fn skip_b(&mut self) -> Result<()> {
         if self.a.is_valid() {
-            while self.b.is_valid() && self.b.key() == self.a.key() {
-                self.b.next()?;
+            while self.b.is_valid() && self.b.key() == self.a.key() && self.b.seq() == self.a.seq()
+            {
+                match self.direction {
+                    Direction::Forward => self.b.next()?,
+                    Direction::Backward => self.b.prev()?,
+                }
             }
         }
         Ok(())
     }
 
-    pub fn create(a: A, b: B) -> Result<Self> {
+    /// Select the current iterator after the iterators move in `direction`.
+    fn update(&mut self, direction: Direction) -> Result<()> {
+        self.direction = direction;
+        self.skip_b()?;
+        self.choose_a = Self::choose_a(self.comparator.as_ref(), &self.a, &self.b, direction);
+        Ok(())
+    }
+
+    pub fn create(a: A, b: B, comparator: Arc<dyn Comparator>) -> Result<Self> {
         let mut iter = Self {
             choose_a: false,
             a,
             b,
+            comparator,
+            direction: Direction::Forward,
         };
-        iter.skip_b()?;
-        iter.choose_a = Self::choose_a(&iter.a, &iter.b);
+        iter.update(Direction::Forward)?;
         Ok(iter)
     }
 }
 
-impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterator<A, B> {
+impl<A: BidirectionalIterator, B: BidirectionalIterator> StorageIterator
+    for TwoMergeIterator<A, B>
+{
     fn key(&self) -> &[u8] {
         if self.choose_a {
             self.a.key()
@@ -51,6 +79,22 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
         }
     }
 
+    fn seq(&self) -> u64 {
+        if self.choose_a {
+            self.a.seq()
+        } else {
+            self.b.seq()
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        if self.choose_a {
+            self.a.value_type()
+        } else {
+            self.b.value_type()
+        }
+    }
+
     fn value(&self) -> &[u8] {
         if self.choose_a {
             self.a.value()
@@ -68,13 +112,56 @@ impl<A: StorageIterator, B: StorageIterator> StorageIterator for TwoMergeIterato
     }
 
     fn next(&mut self) -> Result<()> {
-        if self.choose_a {
+        if self.direction == Direction::Backward {
+            let (key, seq) = (self.key().to_vec(), self.seq());
+            seek_after(&mut self.a, &key, seq)?;
+            seek_after(&mut self.b, &key, seq)?;
+        } else if self.choose_a {
             self.a.next()?;
         } else {
             self.b.next()?;
         }
-        self.skip_b()?;
-        self.choose_a = Self::choose_a(&self.a, &self.b);
-        Ok(())
+        self.update(Direction::Forward)
+    }
+}
+
+impl<A: BidirectionalIterator, B: BidirectionalIterator> BidirectionalIterator
+    for TwoMergeIterator<A, B>
+{
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.a.seek_to_first()?;
+        self.b.seek_to_first()?;
+        self.update(Direction::Forward)
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.a.seek_to_last()?;
+        self.b.seek_to_last()?;
+        self.update(Direction::Backward)
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.a.seek(key)?;
+        self.b.seek(key)?;
+        self.update(Direction::Forward)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.a.seek_for_prev(key)?;
+        self.b.seek_for_prev(key)?;
+        self.update(Direction::Backward)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            let (key, seq) = (self.key().to_vec(), self.seq());
+            seek_before(&mut self.a, &key, seq)?;
+            seek_before(&mut self.b, &key, seq)?;
+        } else if self.choose_a {
+            self.a.prev()?;
+        } else {
+            self.b.prev()?;
+        }
+        self.update(Direction::Backward)
     }
 }
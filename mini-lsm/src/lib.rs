@@ -1,9 +1,22 @@
+pub mod blob;
 pub mod block;
+pub mod column_family;
+pub mod compact;
+pub mod comparator;
+pub mod error;
 pub mod iterators;
 pub mod lsm_iterator;
 pub mod lsm_storage;
+pub mod manifest;
 pub mod mem_table;
+pub mod merge_operator;
+pub mod mvcc;
+pub mod options;
+pub mod prefix_extractor;
+pub mod range_tombstone;
 pub mod table;
+pub mod wal;
+pub mod write_batch;
 
 #[cfg(test)]
 mod tests;
@@ -1,54 +1,287 @@
+use std::collections::HashMap;
 use std::ops::Bound;
+use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::Bytes;
 
+use crate::blob::{read_blob, BlobFile};
+use crate::comparator::Comparator;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{
+    seek_after, seek_before, BidirectionalIterator, Direction, StorageIterator,
+};
+use crate::lsm_storage::LsmStorageState;
 use crate::mem_table::MemTableIterator;
+use crate::merge_operator::{full_merge, MergeOperator};
+use crate::mvcc::{ValueType, MAX_SEQ};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableIterator;
 
 type LsmIteratorInner =
     TwoMergeIterator<MergeIterator<MemTableIterator>, MergeIterator<SsTableIterator>>;
 
+/// Iterates over the latest version of each key with a sequence number not larger than the read
+/// sequence number, skipping deleted keys, resolving merge operands and reading values from blob
+/// files. It stays within its bounds, and its prefix if it has one, in both directions.
 pub struct LsmIterator {
     iter: LsmIteratorInner,
+    start_bound: Bound<Bytes>,
     end_bound: Bound<Bytes>,
+    /// The prefix of every key of the iterator. The keys with the prefix follow the start bound.
+    prefix: Option<Bytes>,
+    read_seq: u64,
+    /// The range tombstones visible at the read sequence number.
+    range_tombstones: Vec<RangeTombstone>,
+    /// The blob files that the SSTs refer to.
+    blob_files: HashMap<usize, Arc<BlobFile>>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    comparator: Arc<dyn Comparator>,
+    /// The key, sequence number and value of the current key if `iter` is not at its latest
+    /// version: when the value is merged from operands or read from a blob file, or when moving
+    /// backward. `iter` is then
+    /// past the versions of the key in the direction of the iterator.
+    current: Option<(Bytes, u64, Bytes)>,
+    direction: Direction,
     is_valid: bool,
 }
 
 impl LsmIterator {
-    pub(crate) fn new(iter: LsmIteratorInner, end_bound: Bound<Bytes>) -> Result<Self> {
+    /// Create an iterator over the range between `start_bound` and `end_bound` of `state`. `iter`
+    /// is at the first entry of the range.
+    pub(crate) fn new(
+        iter: LsmIteratorInner,
+        start_bound: Bound<Bytes>,
+        end_bound: Bound<Bytes>,
+        read_seq: u64,
+        state: &LsmStorageState,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
         let mut iter = Self {
             is_valid: iter.is_valid(),
             iter,
+            start_bound,
             end_bound,
+            prefix: None,
+            read_seq,
+            range_tombstones: state
+                .range_tombstones()
+                .into_iter()
+                .filter(|tombstone| tombstone.seq <= read_seq)
+                .collect(),
+            blob_files: state.blob_files.clone(),
+            merge_operator,
+            comparator,
+            current: None,
+            direction: Direction::Forward,
         };
-        iter.move_to_non_delete()?;
+        iter.move_to_visible_key()?;
         Ok(iter)
     }
 
+    /// Stop the iterator once keys leave `prefix`. The keys with the prefix must be adjacent and
+    /// start from the start bound of the iterator.
+    pub(crate) fn with_prefix(mut self, prefix: Bytes) -> Self {
+        if self.is_valid && !self.key().starts_with(&prefix) {
+            self.is_valid = false;
+        }
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Check if `key` is after the keys of the prefix of the iterator, if it has one.
+    fn after_prefix(&self, key: &[u8]) -> bool {
+        match self.prefix {
+            Some(ref prefix) => {
+                !key.starts_with(prefix) && self.comparator.compare(key, prefix).is_gt()
+            }
+            None => false,
+        }
+    }
+
     fn next_inner(&mut self) -> Result<()> {
         self.iter.next()?;
-        if !self.iter.is_valid() {
-            self.is_valid = false;
-            return Ok(());
+        self.is_valid = self.iter.is_valid();
+        Ok(())
+    }
+
+    fn check_start_bound(&mut self) {
+        match self.start_bound.as_ref() {
+            Bound::Unbounded => {}
+            Bound::Included(key) => {
+                self.is_valid = self.comparator.compare(self.iter.key(), key).is_ge()
+            }
+            Bound::Excluded(key) => {
+                self.is_valid = self.comparator.compare(self.iter.key(), key).is_gt()
+            }
+        }
+        if let Some(ref prefix) = self.prefix {
+            self.is_valid = self.is_valid && self.iter.key().starts_with(prefix);
         }
+    }
+
+    fn check_end_bound(&mut self) {
         match self.end_bound.as_ref() {
             Bound::Unbounded => {}
-            Bound::Included(key) => self.is_valid = self.iter.key() <= key.as_ref(),
-            Bound::Excluded(key) => self.is_valid = self.iter.key() < key.as_ref(),
+            Bound::Included(key) => {
+                self.is_valid = self.comparator.compare(self.iter.key(), key).is_le()
+            }
+            Bound::Excluded(key) => {
+                self.is_valid = self.comparator.compare(self.iter.key(), key).is_lt()
+            }
+        }
+        if let Some(ref prefix) = self.prefix {
+            self.is_valid = self.is_valid && self.iter.key().starts_with(prefix);
         }
-        Ok(())
     }
 
-    fn move_to_non_delete(&mut self) -> Result<()> {
-        while self.is_valid() && self.iter.value().is_empty() {
+    /// Skip the remaining versions of the current key.
+    fn skip_current_key(&mut self) -> Result<()> {
+        let key = self.iter.key().to_vec();
+        while self.is_valid && self.iter.key() == key {
             self.next_inner()?;
         }
         Ok(())
     }
+
+    /// Move to the latest visible version of the next key that is not deleted.
+    fn move_to_visible_key(&mut self) -> Result<()> {
+        loop {
+            // The versions newer than the read sequence number are invisible.
+            while self.is_valid && self.iter.seq() > self.read_seq {
+                self.next_inner()?;
+            }
+            if !self.is_valid {
+                return Ok(());
+            }
+            self.check_end_bound();
+            if !self.is_valid {
+                return Ok(());
+            }
+            if !self.is_deleted(self.iter.key(), self.iter.seq(), self.iter.value_type()) {
+                if matches!(self.iter.value_type(), ValueType::Merge | ValueType::Blob) {
+                    self.resolve_current_key()?;
+                }
+                return Ok(());
+            }
+            // The key is deleted.
+            self.skip_current_key()?;
+        }
+    }
+
+    /// Move backward to the previous key that is not deleted, leaving `iter` at the key before
+    /// it. The versions of a key are visited from the oldest, so they are collected and resolved
+    /// from the latest visible one.
+    fn move_to_visible_key_backward(&mut self) -> Result<()> {
+        loop {
+            self.is_valid = self.iter.is_valid();
+            if !self.is_valid {
+                return Ok(());
+            }
+            self.check_start_bound();
+            if !self.is_valid {
+                return Ok(());
+            }
+            let key = Bytes::copy_from_slice(self.iter.key());
+            let mut versions = Vec::new();
+            while self.iter.is_valid() && self.iter.key() == key {
+                if self.iter.seq() <= self.read_seq {
+                    versions.push((
+                        self.iter.seq(),
+                        self.iter.value_type(),
+                        Bytes::copy_from_slice(self.iter.value()),
+                    ));
+                }
+                self.iter.prev()?;
+            }
+            let seq = match versions.last() {
+                Some((seq, _, _)) => *seq,
+                None => continue,
+            };
+            if let Some(value) = self.resolve(&key, versions.iter().rev())? {
+                self.current = Some((key, seq, value));
+                return Ok(());
+            }
+        }
+    }
+
+    /// Check if a version is a delete, or is deleted by a range tombstone.
+    fn is_deleted(&self, key: &[u8], seq: u64, value_type: ValueType) -> bool {
+        value_type == ValueType::Delete
+            || self
+                .range_tombstones
+                .iter()
+                .any(|tombstone| tombstone.covers(self.comparator.as_ref(), key, seq))
+    }
+
+    /// Resolve the value of `key` from its visible versions, from the latest, by applying the
+    /// merge operands down to the latest put, whose value may be in a blob file. Returns `None` if
+    /// the key is deleted.
+    fn resolve<'a>(
+        &self,
+        key: &[u8],
+        versions: impl Iterator<Item = &'a (u64, ValueType, Bytes)>,
+    ) -> Result<Option<Bytes>> {
+        let mut operands = Vec::new();
+        let mut base = None;
+        for (seq, value_type, value) in versions {
+            if self.is_deleted(key, *seq, *value_type) {
+                break;
+            }
+            match value_type {
+                ValueType::Put => {
+                    base = Some(value.clone());
+                    break;
+                }
+                ValueType::Blob => {
+                    base = Some(read_blob(&self.blob_files, key, value)?);
+                    break;
+                }
+                _ => {}
+            }
+            operands.push(value.clone());
+        }
+        if operands.is_empty() {
+            return Ok(base);
+        }
+        full_merge(
+            self.merge_operator.as_deref(),
+            key,
+            base.as_deref(),
+            &operands,
+        )
+        .map(Some)
+    }
+
+    /// Apply the merge operands of the current key down to its latest put or delete, or read its
+    /// value from a blob file, leaving `iter` at the next key.
+    fn resolve_current_key(&mut self) -> Result<()> {
+        let key = Bytes::copy_from_slice(self.iter.key());
+        let seq = self.iter.seq();
+        let mut versions = Vec::new();
+        while self.iter.is_valid() && self.iter.key() == key {
+            let value_type = self.iter.value_type();
+            versions.push((
+                self.iter.seq(),
+                value_type,
+                Bytes::copy_from_slice(self.iter.value()),
+            ));
+            if value_type != ValueType::Merge {
+                break;
+            }
+            self.iter.next()?;
+        }
+        let value = self
+            .resolve(&key, versions.iter())?
+            .expect("the latest version is a visible merge operand or blob reference");
+        while self.iter.is_valid() && self.iter.key() == key {
+            self.iter.next()?;
+        }
+        self.current = Some((key, seq, value));
+        Ok(())
+    }
 }
 
 impl StorageIterator for LsmIterator {
@@ -57,20 +290,134 @@ impl StorageIterator for LsmIterator {
     }
 
     fn key(&self) -> &[u8] {
-        self.iter.key()
+        match self.current {
+            Some((ref key, _, _)) => key,
+            None => self.iter.key(),
+        }
+    }
+
+    fn seq(&self) -> u64 {
+        match self.current {
+            Some((_, seq, _)) => seq,
+            None => self.iter.seq(),
+        }
+    }
+
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
     }
 
     fn value(&self) -> &[u8] {
-        self.iter.value()
+        match self.current {
+            Some((_, _, ref value)) => value,
+            None => self.iter.value(),
+        }
     }
 
     fn next(&mut self) -> Result<()> {
-        self.next_inner()?;
-        self.move_to_non_delete()?;
+        match self.direction {
+            Direction::Forward => {
+                if self.current.take().is_some() {
+                    // `iter` is already at the next key.
+                    self.is_valid = self.iter.is_valid();
+                } else {
+                    self.skip_current_key()?;
+                }
+            }
+            Direction::Backward => {
+                let (key, _, _) = self.current.take().expect("valid when moving backward");
+                seek_after(&mut self.iter, &key, 0)?;
+                self.direction = Direction::Forward;
+                self.is_valid = self.iter.is_valid();
+            }
+        }
+        self.move_to_visible_key()?;
         Ok(())
     }
 }
 
+impl BidirectionalIterator for LsmIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.current = None;
+        self.direction = Direction::Forward;
+        match self.start_bound.clone() {
+            Bound::Unbounded => self.iter.seek_to_first()?,
+            Bound::Included(key) => self.iter.seek(&key)?,
+            Bound::Excluded(key) => seek_after(&mut self.iter, &key, 0)?,
+        }
+        self.is_valid = self.iter.is_valid();
+        self.move_to_visible_key()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.current = None;
+        self.direction = Direction::Backward;
+        match self.end_bound.clone() {
+            Bound::Unbounded => match self.prefix.clone() {
+                Some(prefix) => {
+                    // Walk to the first key after the keys of the prefix.
+                    self.iter.seek(&prefix)?;
+                    while self.iter.is_valid() && !self.after_prefix(self.iter.key()) {
+                        self.iter.next()?;
+                    }
+                    if self.iter.is_valid() {
+                        let key = Bytes::copy_from_slice(self.iter.key());
+                        seek_before(&mut self.iter, &key, MAX_SEQ)?;
+                    } else {
+                        self.iter.seek_to_last()?;
+                    }
+                }
+                None => self.iter.seek_to_last()?,
+            },
+            Bound::Included(key) => self.iter.seek_for_prev(&key)?,
+            Bound::Excluded(key) => seek_before(&mut self.iter, &key, MAX_SEQ)?,
+        }
+        self.move_to_visible_key_backward()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let before_start = match self.start_bound.as_ref() {
+            Bound::Unbounded => false,
+            Bound::Included(start) => self.comparator.compare(key, start).is_lt(),
+            Bound::Excluded(start) => self.comparator.compare(key, start).is_le(),
+        };
+        if before_start {
+            return self.seek_to_first();
+        }
+        self.current = None;
+        self.direction = Direction::Forward;
+        self.iter.seek(key)?;
+        self.is_valid = self.iter.is_valid();
+        self.move_to_visible_key()
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let after_end = match self.end_bound.as_ref() {
+            Bound::Unbounded => false,
+            Bound::Included(end) => self.comparator.compare(key, end).is_gt(),
+            Bound::Excluded(end) => self.comparator.compare(key, end).is_ge(),
+        };
+        if after_end || self.after_prefix(key) {
+            return self.seek_to_last();
+        }
+        self.current = None;
+        self.direction = Direction::Backward;
+        self.iter.seek_for_prev(key)?;
+        self.move_to_visible_key_backward()
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        if self.direction == Direction::Forward {
+            // Move `iter` before the versions of the current key.
+            let key = Bytes::copy_from_slice(self.key());
+            seek_before(&mut self.iter, &key, MAX_SEQ)?;
+            self.direction = Direction::Backward;
+        }
+        self.current = None;
+        self.move_to_visible_key_backward()
+    }
+}
+
 /// A wrapper around existing iterator, will prevent users from calling `next` when the iterator is
 /// invalid.
 pub struct FusedIterator<I: StorageIterator> {
@@ -92,6 +439,14 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         self.iter.key()
     }
 
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.iter.value_type()
+    }
+
     fn value(&self) -> &[u8] {
         self.iter.value()
     }
@@ -104,3 +459,29 @@ impl<I: StorageIterator> StorageIterator for FusedIterator<I> {
         Ok(())
     }
 }
+
+impl<I: BidirectionalIterator> BidirectionalIterator for FusedIterator<I> {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.iter.seek_to_first()
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.iter.seek_to_last()
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek(key)
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.iter.seek_for_prev(key)
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        // only move when the iterator is valid
+        if self.iter.is_valid() {
+            self.iter.prev()?;
+        }
+        Ok(())
+    }
+}
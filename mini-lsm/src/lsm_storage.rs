@@ -1,229 +1,1535 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
 use std::ops::Bound;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Condvar, Mutex, RwLock};
 
+use crate::blob::{read_blob, BlobFile};
 use crate::block::Block;
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::compact::CompactionController;
+use crate::comparator::{range_contains, Comparator};
+use crate::error::Error;
 use crate::iterators::merge_iterator::MergeIterator;
 use crate::iterators::two_merge_iterator::TwoMergeIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
 use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::manifest::{Manifest, ManifestRecord};
 use crate::mem_table::{map_bound, MemTable};
-use crate::table::{SsTable, SsTableBuilder, SsTableIterator};
+use crate::merge_operator::full_merge;
+use crate::mvcc::{Snapshot, SnapshotList, Transaction, ValueType};
+pub use crate::options::LsmStorageOptions;
+use crate::range_tombstone::{max_covering_seq, RangeTombstone};
+use crate::table::{
+    BlobFileOptions, FileObject, SsTable, SsTableBuilder, SsTableBuilderOptions, SsTableIterator,
+};
+use crate::wal::{Wal, WalEntry};
+use crate::write_batch::WriteBatch;
+use crossbeam_channel::{Receiver, Sender};
 
 pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
 
+/// The largest key, in bytes. Keys are copied into the block index of SSTs, so they are limited
+/// to much less than values.
+pub const MAX_KEY_SIZE: usize = 64 << 20;
+
+/// The largest value, in bytes.
+pub const MAX_VALUE_SIZE: usize = 1 << 30;
+
+/// Check that `len` is at most `limit`, failing with [`Error::TooLarge`] if it is not.
+fn check_size(what: &'static str, len: usize, limit: usize) -> Result<()> {
+    if len > limit {
+        return Err(Error::TooLarge {
+            what,
+            len: len as u64,
+            limit: limit as u64,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// A snapshot of the structure of the LSM tree of a column family.
 #[derive(Clone)]
-pub struct LsmStorageInner {
+pub struct LsmStorageState {
     /// The current memtable.
-    memtable: Arc<MemTable>,
+    pub(crate) memtable: Arc<MemTable>,
     /// Immutable memTables, from earliest to latest.
-    imm_memtables: Vec<Arc<MemTable>>,
-    /// L0 SsTables, from earliest to latest.
-    l0_sstables: Vec<Arc<SsTable>>,
-    /// L1 - L6 SsTables, sorted by key range.
-    #[allow(dead_code)]
-    levels: Vec<Vec<Arc<SsTable>>>,
-    /// The next SSTable ID.
-    next_sst_id: usize,
+    pub(crate) imm_memtables: Vec<Arc<MemTable>>,
+    /// L0 SsTable ids, from earliest to latest.
+    pub(crate) l0_sstables: Vec<usize>,
+    /// SsTable ids of the levels below L0, as (level id, SST ids). For leveled compaction, these
+    /// are L1 - Lmax, and the SSTs of each level are sorted by key range. For tiered compaction,
+    /// these are the tiers from the newest to the oldest, each identified by the id of its first
+    /// SST.
+    pub(crate) levels: Vec<(usize, Vec<usize>)>,
+    /// All SsTables in L0 and the levels, by id.
+    pub(crate) sstables: HashMap<usize, Arc<SsTable>>,
+    /// The blob files the SsTables refer to, by id.
+    pub(crate) blob_files: HashMap<usize, Arc<BlobFile>>,
 }
 
-impl LsmStorageInner {
-    fn create() -> Self {
+/// The storage engine, shared by [`LsmStorage`] and its background threads.
+pub(crate) struct LsmStorageInner {
+    /// The live column families, by id.
+    column_families: RwLock<HashMap<u32, Arc<ColumnFamily>>>,
+    pub(crate) default_cf: Arc<ColumnFamily>,
+    /// The WAL of the current memtables of all column families.
+    wal: RwLock<Arc<Wal>>,
+    /// Serializes flushes of immutable memtables.
+    flush_lock: Mutex<()>,
+    /// Notified when an immutable memtable is flushed, to wake up stalled writes.
+    flush_done: Condvar,
+    stall_lock: Mutex<()>,
+    /// Serializes compactions.
+    pub(crate) compaction_lock: Mutex<()>,
+    path: PathBuf,
+    pub(crate) block_cache: Arc<BlockCache>,
+    pub(crate) manifest: Manifest,
+    pub(crate) options: LsmStorageOptions,
+    pub(crate) compaction_controller: CompactionController,
+    /// The id of the next SST or memtable.
+    next_sst_id: Arc<AtomicUsize>,
+    /// The id of the next column family.
+    next_column_family_id: AtomicU32,
+    /// Serializes writes, so that they become visible in the order of their sequence numbers.
+    write_lock: Mutex<()>,
+    /// The sequence number of the latest write. Reads see the writes up to it.
+    last_seq: AtomicU64,
+    /// Sequence numbers pinned by live snapshots.
+    pub(crate) snapshots: Arc<SnapshotList>,
+    /// Set when the background threads are stopped. Writes fail from then on.
+    closed: AtomicBool,
+    /// The error of the last background task that failed, cleared when its thread succeeds
+    /// again.
+    background_error: Mutex<Option<Error>>,
+}
+
+/// How often the background threads check for work.
+const BACKGROUND_TICK: Duration = Duration::from_millis(50);
+
+/// The storage interface of the LSM tree.
+///
+/// Immutable memtables are flushed and SSTs are compacted by background threads owned by the
+/// storage. Call [`LsmStorage::close`] to stop them and flush everything to disk.
+pub struct LsmStorage {
+    pub(crate) inner: Arc<LsmStorageInner>,
+    /// Dropping the sender stops the background threads.
+    shutdown: Mutex<Option<Sender<()>>>,
+    flush_thread: Mutex<Option<JoinHandle<()>>>,
+    compaction_thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// The versions of a key visible at a read sequence number, visited from the newest to the oldest
+/// until the base value of the key is found.
+struct KeyVersions<'a> {
+    key: &'a [u8],
+    blob_files: &'a HashMap<usize, Arc<BlobFile>>,
+    read_seq: u64,
+    /// The versions older than it are deleted by a range tombstone.
+    covering_seq: u64,
+    newest_seq: Option<u64>,
+    /// The merge operands on top of the base value, from the newest to the oldest.
+    operands: Vec<Bytes>,
+    /// The value of the latest put, or `None` if the key is deleted. It is `None` until a put or
+    /// a delete is found.
+    base: Option<Option<Bytes>>,
+}
+
+impl<'a> KeyVersions<'a> {
+    fn new(
+        key: &'a [u8],
+        blob_files: &'a HashMap<usize, Arc<BlobFile>>,
+        read_seq: u64,
+        covering_seq: u64,
+    ) -> Self {
         Self {
-            memtable: Arc::new(MemTable::create()),
+            key,
+            blob_files,
+            read_seq,
+            covering_seq,
+            newest_seq: None,
+            operands: Vec::new(),
+            base: None,
+        }
+    }
+
+    /// Visit the versions of the key from the current position of `iter`, until the base value
+    /// is found.
+    fn visit(&mut self, iter: &mut impl StorageIterator) -> Result<()> {
+        while self.base.is_none() && iter.is_valid() && iter.key() == self.key {
+            let seq = iter.seq();
+            if seq < self.covering_seq {
+                self.base = Some(None);
+            } else if seq <= self.read_seq {
+                self.newest_seq.get_or_insert(seq);
+                match iter.value_type() {
+                    ValueType::Put => self.base = Some(Some(Bytes::copy_from_slice(iter.value()))),
+                    ValueType::Delete => self.base = Some(None),
+                    ValueType::Merge => self.operands.push(Bytes::copy_from_slice(iter.value())),
+                    ValueType::Blob => {
+                        self.base = Some(Some(read_blob(self.blob_files, self.key, iter.value())?))
+                    }
+                }
+            }
+            iter.next()?;
+        }
+        Ok(())
+    }
+}
+
+/// Check if the key range of an SST may overlap with a user-given range.
+fn range_overlap(
+    comparator: &dyn Comparator,
+    lower: Bound<&[u8]>,
+    upper: Bound<&[u8]>,
+    table_first_key: &[u8],
+    table_last_key: &[u8],
+) -> bool {
+    match upper {
+        Bound::Excluded(key) if comparator.compare(key, table_first_key).is_le() => return false,
+        Bound::Included(key) if comparator.compare(key, table_first_key).is_lt() => return false,
+        _ => {}
+    }
+    match lower {
+        Bound::Excluded(key) if comparator.compare(key, table_last_key).is_ge() => return false,
+        Bound::Included(key) if comparator.compare(key, table_last_key).is_gt() => return false,
+        _ => {}
+    }
+    true
+}
+
+impl LsmStorageState {
+    /// The structure of an empty LSM tree, with a memtable of the given id.
+    fn new(
+        memtable_id: usize,
+        levels: Vec<(usize, Vec<usize>)>,
+        comparator: Arc<dyn Comparator>,
+    ) -> Self {
+        Self {
+            memtable: Arc::new(MemTable::create(memtable_id, comparator)),
             imm_memtables: vec![],
             l0_sstables: vec![],
-            levels: vec![],
-            next_sst_id: 1,
+            levels,
+            sstables: HashMap::new(),
+            blob_files: HashMap::new(),
         }
     }
-}
 
-/// The storage interface of the LSM tree.
-pub struct LsmStorage {
-    inner: Arc<RwLock<Arc<LsmStorageInner>>>,
-    flush_lock: Mutex<()>,
-    path: PathBuf,
-    block_cache: Arc<BlockCache>,
+    /// Get the bytes of the blob file records that the SSTs refer to, by blob file id.
+    pub(crate) fn live_blob_bytes(&self) -> HashMap<usize, u64> {
+        let mut live_bytes = HashMap::new();
+        for sst in self.sstables.values() {
+            for (file_id, bytes) in sst.blob_refs() {
+                *live_bytes.entry(*file_id).or_default() += bytes;
+            }
+        }
+        live_bytes
+    }
+
+    /// Put the SST with id `new_id` in the place of the one with id `old_id` in L0 or its level.
+    pub(crate) fn replace_sst(&mut self, old_id: usize, new_id: usize) {
+        let ssts = std::iter::once(&mut self.l0_sstables)
+            .chain(self.levels.iter_mut().map(|(_, ssts)| ssts))
+            .flat_map(|ssts| ssts.iter_mut());
+        for id in ssts {
+            if *id == old_id {
+                *id = new_id;
+            }
+        }
+    }
+
+    /// Ids of all SSTs, from the newest to the oldest: L0 SSTs first, and then the levels.
+    pub(crate) fn sst_ids_newest_first(&self) -> impl Iterator<Item = &usize> {
+        self.l0_sstables
+            .iter()
+            .rev()
+            .chain(self.levels.iter().flat_map(|(_, ssts)| ssts.iter()))
+    }
+
+    /// Memtables, from the newest to the oldest.
+    fn memtables_newest_first(&self) -> impl Iterator<Item = &Arc<MemTable>> {
+        std::iter::once(&self.memtable).chain(self.imm_memtables.iter().rev())
+    }
+
+    /// Range tombstones in the memtables and all SSTs.
+    pub(crate) fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        let mut tombstones: Vec<RangeTombstone> = self
+            .memtables_newest_first()
+            .flat_map(|memtable| memtable.range_tombstones())
+            .collect();
+        for sst in self.sstables.values() {
+            tombstones.extend_from_slice(sst.range_tombstones());
+        }
+        tombstones
+    }
+
+    /// Get the sequence number of the newest range tombstone that contains `key` and is visible
+    /// at `read_seq`, or 0 if there is none.
+    fn max_covering_seq(&self, comparator: &dyn Comparator, key: &[u8], read_seq: u64) -> u64 {
+        let memtables = self.memtables_newest_first().map(|memtable| {
+            max_covering_seq(comparator, &memtable.range_tombstones(), key, read_seq)
+        });
+        let ssts = self
+            .sstables
+            .values()
+            .map(|sst| max_covering_seq(comparator, sst.range_tombstones(), key, read_seq));
+        memtables.chain(ssts).max().unwrap_or(0)
+    }
 }
 
 impl LsmStorage {
-    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+    /// Open the storage at `path`, creating the directory if it does not exist, and start the
+    /// background flush and compaction threads.
+    ///
+    /// If there is a manifest in the directory, the structure of the LSM tree is rebuilt by
+    /// replaying it, and memtables that were not flushed before the last shutdown are rebuilt
+    /// from their WALs.
+    pub fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let inner = Arc::new(LsmStorageInner::open(path, options)?);
+        let (shutdown, shutdown_rx) = crossbeam_channel::bounded(0);
+        let flush_thread = inner.spawn_flush_thread(shutdown_rx.clone())?;
+        let compaction_thread = inner.spawn_compaction_thread(shutdown_rx)?;
         Ok(Self {
-            inner: Arc::new(RwLock::new(Arc::new(LsmStorageInner::create()))),
-            flush_lock: Mutex::new(()),
-            path: path.as_ref().to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1 << 20)), // 4GB block cache
+            inner,
+            shutdown: Mutex::new(Some(shutdown)),
+            flush_thread: Mutex::new(Some(flush_thread)),
+            compaction_thread: Mutex::new(Some(compaction_thread)),
         })
     }
 
-    /// Get a key from the storage. In day 7, this can be further optimized by using a bloom filter.
+    /// Mark the storage closed, then stop the background threads and wait for them to exit.
+    fn stop_background_threads(&self) -> Result<()> {
+        {
+            let _write_lock = self.inner.write_lock.lock();
+            self.inner.closed.store(true, Ordering::Release);
+        }
+        self.shutdown.lock().take();
+        for thread in [&self.flush_thread, &self.compaction_thread] {
+            if let Some(handle) = thread.lock().take() {
+                handle
+                    .join()
+                    .map_err(|e| anyhow!("background thread panicked: {:?}", e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop the background threads, and flush all memtables to disk. Writes fail with
+    /// [`Error::Closed`] afterwards. If a background thread failed and has not succeeded since,
+    /// its error is returned after the flush.
+    pub fn close(&self) -> Result<()> {
+        self.stop_background_threads()?;
+        self.inner.sync()?;
+        match self.inner.background_error.lock().take() {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Get a key from the storage.
     pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        self.inner.get(&self.inner.default_cf, key)
+    }
+
+    /// Get a key from a column family.
+    pub fn get_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.inner.get(cf, key)
+    }
+
+    /// Take a snapshot of the storage, as of the latest write.
+    pub fn snapshot(&self) -> Snapshot {
+        self.inner.snapshot()
+    }
+
+    /// Get a key from the storage as of `snapshot`.
+    pub fn get_with_snapshot(&self, key: &[u8], snapshot: &Snapshot) -> Result<Option<Bytes>> {
+        self.inner
+            .get_with_seq(&self.inner.default_cf, key, snapshot.seq())
+    }
+
+    /// Begin an optimistic transaction.
+    pub fn new_txn(&self) -> Transaction {
+        Transaction::new(self.inner.clone())
+    }
+
+    /// Put a key-value pair into the storage.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put(&self.inner.default_cf, key, value)
+    }
+
+    /// Put a key-value pair into a column family.
+    pub fn put_cf(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
+        self.inner.put(cf, key, value)
+    }
+
+    /// Remove a key from the storage.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.delete(&self.inner.default_cf, key)
+    }
+
+    /// Remove a key from a column family.
+    pub fn delete_cf(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        self.inner.delete(cf, key)
+    }
+
+    /// Apply a batch of puts and deletes atomically, even if they span column families.
+    pub fn write(&self, batch: &WriteBatch) -> Result<()> {
+        self.inner.write(batch)
+    }
+
+    /// Merge `operand` into the value of a key with the merge operator of the storage, without
+    /// reading the key.
+    pub fn merge(&self, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(&self.inner.default_cf, key, operand)
+    }
+
+    /// Merge `operand` into the value of a key in a column family.
+    pub fn merge_cf(&self, cf: &ColumnFamily, key: &[u8], operand: &[u8]) -> Result<()> {
+        self.inner.merge(cf, key, operand)
+    }
+
+    /// Remove the keys in `lower..upper` from the storage.
+    pub fn delete_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner
+            .delete_range(&self.inner.default_cf, lower, upper)
+    }
 
-        // Search on the current memtable.
-        if let Some(value) = snapshot.memtable.get(key) {
-            if value.is_empty() {
-                // found tomestone, return key not exists
-                return Ok(None);
+    /// Remove the keys in `lower..upper` from a column family.
+    pub fn delete_range_cf(&self, cf: &ColumnFamily, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.delete_range(cf, lower, upper)
+    }
+
+    /// Create a column family named `name`. Fails if there is one already.
+    pub fn create_column_family(&self, name: &str) -> Result<Arc<ColumnFamily>> {
+        self.inner.create_column_family(name)
+    }
+
+    /// Drop the column family named `name` and remove its data. The default column family cannot
+    /// be dropped.
+    pub fn drop_column_family(&self, name: &str) -> Result<()> {
+        self.inner.drop_column_family(name)
+    }
+
+    /// Get the column family named `name`, if there is one.
+    pub fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.inner.column_family(name)
+    }
+
+    /// Flush all memtables to disk.
+    pub fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan(&self.inner.default_cf, lower, upper)
+    }
+
+    /// Create an iterator over a range of keys of a column family.
+    pub fn scan_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan(cf, lower, upper)
+    }
+
+    /// Create an iterator over a range of keys, positioned at the last key of the range. Move it
+    /// backward with [`BidirectionalIterator::prev`].
+    pub fn scan_reverse(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner
+            .scan_reverse(&self.inner.default_cf, lower, upper)
+    }
+
+    /// Create an iterator over a range of keys of a column family, positioned at the last key of
+    /// the range. Move it backward with [`BidirectionalIterator::prev`].
+    pub fn scan_reverse_cf(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_reverse(cf, lower, upper)
+    }
+
+    /// Create an iterator over the keys starting with `prefix`. If `prefix` is a prefix extracted
+    /// by [`LsmStorageOptions::prefix_extractor`], the SSTs without keys of the prefix are skipped
+    /// by their prefix bloom filters. The keys with the prefix must follow the prefix itself in the
+    /// order of the comparator, as they do in byte-wise order.
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_prefix(&self.inner.default_cf, prefix)
+    }
+
+    /// Create an iterator over the keys of a column family starting with `prefix`.
+    pub fn scan_prefix_cf(
+        &self,
+        cf: &ColumnFamily,
+        prefix: &[u8],
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner.scan_prefix(cf, prefix)
+    }
+
+    /// Create an iterator over a range of keys as of `snapshot`.
+    pub fn scan_with_snapshot(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        snapshot: &Snapshot,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.inner
+            .scan_with_seq(&self.inner.default_cf, lower, upper, snapshot.seq())
+    }
+}
+
+impl Drop for LsmStorage {
+    /// Stop the background threads without flushing, as if the process exited. A panic of a
+    /// background thread is ignored here; call [`LsmStorage::close`] to see it.
+    fn drop(&mut self) {
+        let _ = self.stop_background_threads();
+    }
+}
+
+impl LsmStorageInner {
+    fn open(path: impl AsRef<Path>, options: LsmStorageOptions) -> Result<Self> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        options.validate_and_persist(path)?;
+        let block_cache = Arc::new(
+            BlockCache::builder()
+                .max_capacity(options.block_cache_capacity)
+                .weigher(|_, block: &Arc<Block>| block.size().try_into().unwrap_or(u32::MAX))
+                .build(),
+        );
+        let manifest_path = path.join("MANIFEST");
+
+        let comparator = options.comparator.clone();
+        let compaction_controller =
+            CompactionController::new(&options.compaction_options, comparator.clone());
+
+        let new_state = || {
+            LsmStorageState::new(
+                0,
+                compaction_controller.initial_levels(),
+                comparator.clone(),
+            )
+        };
+        // The name and the state of each column family, by id.
+        let mut states = HashMap::new();
+        states.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            (DEFAULT_COLUMN_FAMILY_NAME.to_string(), new_state()),
+        );
+        let mut memtable_ids = Vec::new();
+        let mut next_sst_id = 1;
+        let mut next_column_family_id = DEFAULT_COLUMN_FAMILY_ID + 1;
+        let manifest = if manifest_path.exists() {
+            let (manifest, records) = Manifest::recover(&manifest_path)?;
+            for record in records {
+                match record {
+                    ManifestRecord::Flush(id, ssts) => {
+                        memtable_ids.retain(|x| *x != id);
+                        for (cf_id, sst_id) in ssts {
+                            if let Some((_, state)) = states.get_mut(&cf_id) {
+                                compaction_controller.add_flushed_sst(state, sst_id);
+                            }
+                            next_sst_id = next_sst_id.max(sst_id + 1);
+                        }
+                    }
+                    ManifestRecord::NewMemtable(id) => {
+                        memtable_ids.push(id);
+                        next_sst_id = next_sst_id.max(id + 1);
+                    }
+                    ManifestRecord::Compaction(cf_id, task, output) => {
+                        if let Some((_, state)) = states.get_mut(&cf_id) {
+                            (*state, _) = compaction_controller
+                                .apply_compaction_result(state, &task, &output, true);
+                        }
+                        next_sst_id = next_sst_id.max(output.iter().max().map_or(0, |x| x + 1));
+                    }
+                    ManifestRecord::BlobGarbageCollection(cf_id, ssts) => {
+                        for (old_id, new_id) in ssts {
+                            if let Some((_, state)) = states.get_mut(&cf_id) {
+                                state.replace_sst(old_id, new_id);
+                            }
+                            next_sst_id = next_sst_id.max(new_id + 1);
+                        }
+                    }
+                    ManifestRecord::CreateColumnFamily(cf_id, name) => {
+                        states.insert(cf_id, (name, new_state()));
+                        next_column_family_id = next_column_family_id.max(cf_id + 1);
+                    }
+                    ManifestRecord::DropColumnFamily(cf_id) => {
+                        if let Some((_, state)) = states.remove(&cf_id) {
+                            // The storage may have crashed before the SSTs were removed. The blob
+                            // files are removed first, so those left are referred to by the SSTs
+                            // left.
+                            for id in state.sst_ids_newest_first() {
+                                let sst_path = Self::path_of_sst_static(path, *id);
+                                if !sst_path.exists() {
+                                    continue;
+                                }
+                                let file = FileObject::open(&sst_path)?;
+                                let sst = SsTable::open(*id, None, file, comparator.clone())?;
+                                for (blob_id, _) in sst.blob_refs() {
+                                    let blob_path = Self::path_of_blob_static(path, *blob_id);
+                                    if blob_path.exists() {
+                                        std::fs::remove_file(blob_path)?;
+                                    }
+                                }
+                                std::fs::remove_file(sst_path)?;
+                            }
+                        }
+                    }
+                }
+            }
+            manifest
+        } else {
+            let manifest = Manifest::create(&manifest_path)?;
+            Self::sync_dir_static(path)?;
+            manifest
+        };
+
+        let mut last_seq = 0;
+        for (_, state) in states.values_mut() {
+            let sst_ids: Vec<usize> = state.sst_ids_newest_first().copied().collect();
+            for id in sst_ids {
+                let file = FileObject::open(&Self::path_of_sst_static(path, id))
+                    .with_context(|| format!("failed to open SST {}", id))?;
+                let sst = SsTable::open(id, Some(block_cache.clone()), file, comparator.clone())?;
+                last_seq = last_seq.max(sst.max_seq());
+                state.sstables.insert(id, Arc::new(sst));
             }
-            return Ok(Some(value));
+            compaction_controller.sort_levels_after_recovery(state);
+            Self::update_blob_files_static(path, state)?;
+            // Blob file ids are taken from the same sequence as SST ids, but are not recorded in
+            // the manifest.
+            next_sst_id = next_sst_id.max(state.blob_files.keys().max().map_or(0, |id| id + 1));
         }
-        // Search on immutable memtables.
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            if let Some(value) = memtable.get(key) {
-                if value.is_empty() {
-                    // found tomestone, return key not exists
-                    return Ok(None);
+        // A crash after a blob file is written, but before its SST is recorded, leaves a blob file
+        // that no SST refers to. Its id may be taken again, so it is removed.
+        let live_blob_files: HashSet<usize> = states
+            .values()
+            .flat_map(|(_, state)| state.blob_files.keys().copied())
+            .collect();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            let id = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".blob"))
+                .and_then(|id| id.parse::<usize>().ok());
+            if let Some(id) = id {
+                if !live_blob_files.contains(&id) {
+                    std::fs::remove_file(entry.path())?;
                 }
-                return Ok(Some(value));
             }
         }
-        let mut iters = Vec::new();
-        iters.reserve(snapshot.l0_sstables.len());
-        for table in snapshot.l0_sstables.iter().rev() {
-            iters.push(Box::new(SsTableIterator::create_and_seek_to_key(
-                table.clone(),
-                key,
-            )?));
+
+        for id in memtable_ids {
+            let wal_path = Self::path_of_wal_static(path, id);
+            if !wal_path.exists() {
+                // The memtables were empty when the storage was last opened, or the storage
+                // crashed before creating their WAL.
+                continue;
+            }
+            let (_, entries) = Wal::recover(&wal_path, options.wal_sync_policy)?;
+            if entries.is_empty() {
+                std::fs::remove_file(&wal_path)?;
+                continue;
+            }
+            let memtables: HashMap<u32, MemTable> = states
+                .keys()
+                .map(|cf_id| (*cf_id, MemTable::create(id, comparator.clone())))
+                .collect();
+            for (cf_id, entry) in entries {
+                // The writes of a dropped column family are discarded.
+                let memtable = match memtables.get(&cf_id) {
+                    Some(memtable) => memtable,
+                    None => continue,
+                };
+                match entry {
+                    WalEntry::Point(key, value_type, value) => {
+                        last_seq = last_seq.max(key.seq);
+                        memtable.put_batch(key.seq, &[(&key.key, value_type, &value)])?;
+                    }
+                    WalEntry::RangeDelete(tombstone) => {
+                        last_seq = last_seq.max(tombstone.seq);
+                        memtable.delete_range(&tombstone.start, &tombstone.end, tombstone.seq)?;
+                    }
+                }
+            }
+            for (cf_id, memtable) in memtables {
+                states
+                    .get_mut(&cf_id)
+                    .unwrap()
+                    .1
+                    .imm_memtables
+                    .push(Arc::new(memtable));
+            }
         }
-        let iter = MergeIterator::create(iters);
-        if iter.is_valid() {
-            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+
+        // The memtable is recorded before its WAL is created, so that a crash in between leaves
+        // no WAL behind whose id could be taken again.
+        manifest.add_record(ManifestRecord::NewMemtable(next_sst_id))?;
+        let wal = Wal::create(
+            Self::path_of_wal_static(path, next_sst_id),
+            options.wal_sync_policy,
+        )?;
+        Self::sync_dir_static(path)?;
+        let column_families: HashMap<u32, Arc<ColumnFamily>> = states
+            .into_iter()
+            .map(|(cf_id, (name, mut state))| {
+                state.memtable = Arc::new(MemTable::create(next_sst_id, comparator.clone()));
+                (cf_id, Arc::new(ColumnFamily::new(cf_id, name, state)))
+            })
+            .collect();
+
+        Ok(Self {
+            default_cf: column_families[&DEFAULT_COLUMN_FAMILY_ID].clone(),
+            column_families: RwLock::new(column_families),
+            wal: RwLock::new(Arc::new(wal)),
+            flush_lock: Mutex::new(()),
+            flush_done: Condvar::new(),
+            stall_lock: Mutex::new(()),
+            compaction_lock: Mutex::new(()),
+            path: path.to_path_buf(),
+            block_cache,
+            manifest,
+            options,
+            compaction_controller,
+            next_sst_id: Arc::new(AtomicUsize::new(next_sst_id + 1)),
+            next_column_family_id: AtomicU32::new(next_column_family_id),
+            write_lock: Mutex::new(()),
+            last_seq: AtomicU64::new(last_seq),
+            snapshots: Arc::new(SnapshotList::default()),
+            closed: AtomicBool::new(false),
+            background_error: Mutex::new(None),
+        })
+    }
+
+    /// Get a key from a column family.
+    pub(crate) fn get(&self, cf: &ColumnFamily, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_with_seq(cf, key, self.last_seq.load(Ordering::Acquire))
+    }
+
+    /// Take a snapshot of the storage, as of the latest write.
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        self.snapshots
+            .acquire(self.last_seq.load(Ordering::Acquire))
+    }
+
+    /// Get the latest version of a key with a sequence number not larger than `read_seq`.
+    pub(crate) fn get_with_seq(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<Bytes>> {
+        Ok(self
+            .get_version(cf, key, read_seq)?
+            .and_then(|(_, value)| value))
+    }
+
+    /// Get the sequence number and the value of the latest version of a key with a sequence
+    /// number not larger than `read_seq`, which is `None` if the key is deleted. A key deleted by
+    /// a range tombstone gets the sequence number of the tombstone, and merge operands are
+    /// resolved down to the latest put or delete.
+    ///
+    /// SSTs are probed from the newest to the oldest, skipping the ones whose key range does not
+    /// contain the key or whose bloom filter rules it out.
+    pub(crate) fn get_version(
+        &self,
+        cf: &ColumnFamily,
+        key: &[u8],
+        read_seq: u64,
+    ) -> Result<Option<(u64, Option<Bytes>)>> {
+        let snapshot = Self::snapshot_of(cf)?;
+
+        let comparator = self.options.comparator.as_ref();
+        let covering_seq = snapshot.max_covering_seq(comparator, key, read_seq);
+        let mut versions = KeyVersions::new(key, &snapshot.blob_files, read_seq, covering_seq);
+        // Search on the current memtable, and then on immutable memtables.
+        for memtable in snapshot.memtables_newest_first() {
+            if versions.base.is_some() {
+                break;
+            }
+            let mut iter = memtable.scan(Bound::Included(key), Bound::Included(key));
+            versions.visit(&mut iter)?;
         }
-        Ok(None)
+        // Search on L0 SSTs, and then on the levels.
+        for table in snapshot.sst_ids_newest_first() {
+            if versions.base.is_some() {
+                break;
+            }
+            let table = snapshot.sstables[table].clone();
+            if comparator.compare(key, table.first_key()).is_lt()
+                || comparator.compare(key, table.last_key()).is_gt()
+                || !table.may_contain(key)
+            {
+                continue;
+            }
+            let mut iter = SsTableIterator::create_and_seek_to_key(table, key)?;
+            versions.visit(&mut iter)?;
+        }
+
+        let newest_seq = match versions.newest_seq {
+            Some(seq) => seq,
+            // The key does not exist, or is deleted by a range tombstone.
+            None => return Ok((covering_seq > 0).then_some((covering_seq, None))),
+        };
+        let base = versions.base.flatten();
+        if versions.operands.is_empty() {
+            return Ok(Some((newest_seq, base)));
+        }
+        let value = full_merge(
+            self.options.merge_operator.as_deref(),
+            key,
+            base.as_deref(),
+            &versions.operands,
+        )?;
+        Ok(Some((newest_seq, Some(value))))
     }
 
-    /// Put a key-value pair into the storage by writing into the current memtable.
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        assert!(!value.is_empty(), "value cannot be empty");
+    /// Get the structure of a column family, failing if it is dropped.
+    fn snapshot_of(cf: &ColumnFamily) -> Result<Arc<LsmStorageState>> {
+        if cf.is_dropped() {
+            bail!("column family {} is dropped", cf.name());
+        }
+        Ok(cf.snapshot())
+    }
+
+    /// Put a key-value pair into a column family by writing into its current memtable. The write
+    /// is logged to the WAL before it becomes visible.
+    pub(crate) fn put(&self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> Result<()> {
         assert!(!key.is_empty(), "key cannot be empty");
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, value);
+        self.write_to_memtables(&[(cf.id(), key, ValueType::Put, value)])
+    }
 
-        Ok(())
+    /// Remove a key from a column family by writing a tombstone.
+    pub(crate) fn delete(&self, cf: &ColumnFamily, key: &[u8]) -> Result<()> {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.write_to_memtables(&[(cf.id(), key, ValueType::Delete, &[])])
     }
 
-    /// Remove a key from the storage by writing an empty value.
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
+    /// Write a merge operand for a key. Fails if there is no merge operator.
+    pub(crate) fn merge(&self, cf: &ColumnFamily, key: &[u8], operand: &[u8]) -> Result<()> {
         assert!(!key.is_empty(), "key cannot be empty");
+        if self.options.merge_operator.is_none() {
+            bail!("merge requires a merge operator");
+        }
+
+        self.write_to_memtables(&[(cf.id(), key, ValueType::Merge, operand)])
+    }
+
+    /// Apply a batch of puts and deletes. The batch is logged as a single WAL record, and becomes
+    /// visible to reads all at once.
+    pub(crate) fn write(&self, batch: &WriteBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_to_memtables(&batch.entries())
+    }
+
+    /// Remove the keys in `lower..upper` of a column family by writing a range tombstone. It is a
+    /// no-op if the range is empty.
+    pub(crate) fn delete_range(&self, cf: &ColumnFamily, lower: &[u8], upper: &[u8]) -> Result<()> {
+        check_size("key", lower.len(), MAX_KEY_SIZE)?;
+        check_size("key", upper.len(), MAX_KEY_SIZE)?;
+        if self.options.comparator.compare(lower, upper).is_ge() {
+            return Ok(());
+        }
+        self.apply_to_memtables(
+            1,
+            || Ok(()),
+            |seq| {
+                let memtable = self.current_memtable(cf.id())?;
+                self.wal.read().delete_range(seq, cf.id(), lower, upper)?;
+                memtable.delete_range(lower, upper, seq)?;
+                Ok(memtable.approximate_size())
+            },
+        )
+    }
 
-        let guard = self.inner.read();
-        guard.memtable.put(key, b"");
+    /// Write into the current memtables with the next sequence numbers, and freeze the memtables
+    /// once one of them reaches `write_buffer_size`. A write is the id of a column family, a key,
+    /// its type and its value.
+    fn write_to_memtables(&self, entries: &[(u32, &[u8], ValueType, &[u8])]) -> Result<()> {
+        self.write_to_memtables_checked(entries, || Ok(()))
+    }
+
+    /// Like [`Self::write_to_memtables`], but only write if `check` passes. No other write can
+    /// happen between `check` and the write. Fails with [`Error::TooLarge`] if a key or a value
+    /// is over [`MAX_KEY_SIZE`] or [`MAX_VALUE_SIZE`].
+    pub(crate) fn write_to_memtables_checked(
+        &self,
+        entries: &[(u32, &[u8], ValueType, &[u8])],
+        check: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        for (_, key, _, value) in entries {
+            check_size("key", key.len(), MAX_KEY_SIZE)?;
+            check_size("value", value.len(), MAX_VALUE_SIZE)?;
+        }
+        self.apply_to_memtables(entries.len() as u64, check, |seq| {
+            // Resolve every column family before logging, so that the batch is never logged in
+            // part.
+            let memtables = entries
+                .iter()
+                .map(|(cf_id, _, _, _)| self.current_memtable(*cf_id))
+                .collect::<Result<Vec<_>>>()?;
+            self.wal.read().put_batch(seq, entries)?;
+            let mut size = 0;
+            for ((&(_, key, value_type, value), memtable), seq) in
+                entries.iter().zip(&memtables).zip(seq..)
+            {
+                memtable.put_batch(seq, &[(key, value_type, value)])?;
+                size = size.max(memtable.approximate_size());
+            }
+            Ok(size)
+        })
+    }
+
+    /// Get the current memtable of the column family with the given id, failing if it is
+    /// dropped.
+    fn current_memtable(&self, cf_id: u32) -> Result<Arc<MemTable>> {
+        let column_families = self.column_families.read();
+        let cf = column_families
+            .get(&cf_id)
+            .with_context(|| format!("column family {} is dropped", cf_id))?;
+        let memtable = cf.state.read().memtable.clone();
+        Ok(memtable)
+    }
+
+    /// Apply `count` writes to the current memtables with `write`, which gets the sequence number
+    /// of the first write and returns the largest size of the memtables written, if `check`
+    /// passes.
+    fn apply_to_memtables(
+        &self,
+        count: u64,
+        check: impl FnOnce() -> Result<()>,
+        write: impl FnOnce(u64) -> Result<usize>,
+    ) -> Result<()> {
+        self.stall_writes()?;
+
+        let size = {
+            let _write_lock = self.write_lock.lock();
+            if self.closed.load(Ordering::Acquire) {
+                return Err(Error::Closed.into());
+            }
+            check()?;
+            let seq = self.last_seq.load(Ordering::Relaxed) + 1;
+            let size = write(seq)?;
+            // The writes become visible to reads together.
+            self.last_seq.store(seq + count - 1, Ordering::Release);
+            size
+        };
+        let write_buffer_size = self.options.write_buffer_size;
+        if size >= write_buffer_size {
+            // Another write may have frozen the memtables in the meantime.
+            self.freeze_memtables_if(|memtable| memtable.approximate_size() >= write_buffer_size)?;
+        }
 
         Ok(())
     }
 
-    fn path_of_sst(&self, id: usize) -> PathBuf {
-        self.path.join(format!("{:05}.sst", id))
+    /// Block until every column family has fewer than `max_imm_memtables` immutable memtables.
+    /// Fails with [`Error::Closed`] if the flush thread is stopped, and with its error if it
+    /// failed, as nothing may drain the immutable memtables then.
+    fn stall_writes(&self) -> Result<()> {
+        let mut guard = self.stall_lock.lock();
+        while self
+            .column_families
+            .read()
+            .values()
+            .any(|cf| cf.state.read().imm_memtables.len() >= self.options.max_imm_memtables)
+        {
+            if self.closed.load(Ordering::Acquire) {
+                return Err(Error::Closed.into());
+            }
+            if let Some(
+                e @ Error::BackgroundFailed {
+                    thread: "flush", ..
+                },
+            ) = self.background_error.lock().clone()
+            {
+                return Err(e.into());
+            }
+            // The flush thread may be busy, so check again after a while even if it does not
+            // notify.
+            self.flush_done.wait_for(&mut guard, BACKGROUND_TICK);
+        }
+        Ok(())
     }
 
-    /// Persist data to disk.
-    ///
-    /// In day 3: flush the current memtable to disk as L0 SST.
-    /// In day 6: call `fsync` on WAL.
-    pub fn sync(&self) -> Result<()> {
+    /// Get the live column families.
+    pub(crate) fn column_families(&self) -> Vec<Arc<ColumnFamily>> {
+        self.column_families.read().values().cloned().collect()
+    }
+
+    /// Get the live column family named `name`.
+    pub(crate) fn column_family(&self, name: &str) -> Option<Arc<ColumnFamily>> {
+        self.column_families
+            .read()
+            .values()
+            .find(|cf| cf.name() == name)
+            .cloned()
+    }
+
+    /// Create a column family named `name`. Its memtable shares the WAL of the current memtables.
+    pub(crate) fn create_column_family(&self, name: &str) -> Result<Arc<ColumnFamily>> {
+        let _write_lock = self.write_lock.lock();
+        let mut column_families = self.column_families.write();
+        if column_families.values().any(|cf| cf.name() == name) {
+            bail!("column family {} already exists", name);
+        }
+        let cf_id = self.next_column_family_id.fetch_add(1, Ordering::SeqCst);
+        self.manifest
+            .add_record(ManifestRecord::CreateColumnFamily(cf_id, name.to_string()))?;
+        let memtable_id = self.default_cf.state.read().memtable.id();
+        let state = LsmStorageState::new(
+            memtable_id,
+            self.compaction_controller.initial_levels(),
+            self.options.comparator.clone(),
+        );
+        let cf = Arc::new(ColumnFamily::new(cf_id, name.to_string(), state));
+        column_families.insert(cf_id, cf.clone());
+        Ok(cf)
+    }
+
+    /// Drop the column family named `name`, and remove its SSTs. Its writes in the WALs are
+    /// discarded when they are flushed or replayed.
+    pub(crate) fn drop_column_family(&self, name: &str) -> Result<()> {
+        if name == DEFAULT_COLUMN_FAMILY_NAME {
+            bail!("the default column family cannot be dropped");
+        }
+        // Wait for the flushes and compactions that may write SSTs of the column family.
+        let _compaction_lock = self.compaction_lock.lock();
         let _flush_lock = self.flush_lock.lock();
+        let cf = {
+            let _write_lock = self.write_lock.lock();
+            let mut column_families = self.column_families.write();
+            let cf_id = match column_families.values().find(|cf| cf.name() == name) {
+                Some(cf) => cf.id(),
+                None => bail!("column family {} does not exist", name),
+            };
+            self.manifest
+                .add_record(ManifestRecord::DropColumnFamily(cf_id))?;
+            let cf = column_families.remove(&cf_id).unwrap();
+            cf.mark_dropped();
+            cf
+        };
+
+        let snapshot = cf.snapshot();
+        self.remove_files(
+            snapshot.sst_ids_newest_first().copied(),
+            snapshot.blob_files.keys().copied(),
+        )
+    }
+
+    /// Remove SSTs and blob files that are no longer part of any column family state. Readers
+    /// holding an old snapshot keep the files open, so they can be removed as soon as the new
+    /// state is visible. The blob files go first, so that recovery finds those left through the
+    /// SSTs left.
+    pub(crate) fn remove_files(
+        &self,
+        sst_ids: impl IntoIterator<Item = usize>,
+        blob_file_ids: impl IntoIterator<Item = usize>,
+    ) -> Result<()> {
+        for id in blob_file_ids {
+            std::fs::remove_file(self.path_of_blob(id))?;
+        }
+        for id in sst_ids {
+            std::fs::remove_file(self.path_of_sst(id))?;
+        }
+        Ok(())
+    }
+
+    fn path_of_sst_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.sst", id))
+    }
+
+    pub(crate) fn path_of_sst(&self, id: usize) -> PathBuf {
+        Self::path_of_sst_static(&self.path, id)
+    }
+
+    fn path_of_blob_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.blob", id))
+    }
+
+    pub(crate) fn path_of_blob(&self, id: usize) -> PathBuf {
+        Self::path_of_blob_static(&self.path, id)
+    }
+
+    /// Open the blob files that the SSTs of `state` refer to and are not open yet, and drop the
+    /// ones no SST refers to anymore. Returns the ids of the dropped blob files, which can be
+    /// removed from disk.
+    fn update_blob_files_static(
+        path: impl AsRef<Path>,
+        state: &mut LsmStorageState,
+    ) -> Result<Vec<usize>> {
+        let live_bytes = state.live_blob_bytes();
+        for id in live_bytes.keys() {
+            if !state.blob_files.contains_key(id) {
+                let blob_file = BlobFile::open(*id, &Self::path_of_blob_static(&path, *id))
+                    .with_context(|| format!("failed to open blob file {}", id))?;
+                state.blob_files.insert(*id, Arc::new(blob_file));
+            }
+        }
+        let unreferenced: Vec<usize> = state
+            .blob_files
+            .keys()
+            .filter(|id| !live_bytes.contains_key(id))
+            .copied()
+            .collect();
+        for id in &unreferenced {
+            state.blob_files.remove(id);
+        }
+        Ok(unreferenced)
+    }
+
+    pub(crate) fn update_blob_files(&self, state: &mut LsmStorageState) -> Result<Vec<usize>> {
+        Self::update_blob_files_static(&self.path, state)
+    }
+
+    fn path_of_wal_static(path: impl AsRef<Path>, id: usize) -> PathBuf {
+        path.as_ref().join(format!("{:05}.wal", id))
+    }
+
+    fn path_of_wal(&self, id: usize) -> PathBuf {
+        Self::path_of_wal_static(&self.path, id)
+    }
+
+    /// `fsync` the directory, so that newly created files are durable.
+    fn sync_dir_static(path: impl AsRef<Path>) -> Result<()> {
+        File::open(path.as_ref())?.sync_all()?;
+        Ok(())
+    }
+
+    pub(crate) fn sync_dir(&self) -> Result<()> {
+        Self::sync_dir_static(&self.path)
+    }
+
+    pub(crate) fn next_sst_id(&self) -> usize {
+        self.next_sst_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Create a builder for a new SST with the configured options. If values may go to a blob
+    /// file, it gets a newly allocated id when the first one does.
+    pub(crate) fn new_sst_builder(&self) -> SsTableBuilder {
+        SsTableBuilder::with_options(SsTableBuilderOptions {
+            block_size: self.options.block_size,
+            bloom_bits_per_key: self.options.bloom_bits_per_key,
+            prefix_extractor: self.options.prefix_extractor.clone(),
+            compression: self.options.compression,
+            comparator: self.options.comparator.clone(),
+            blob_file: self.options.min_blob_size.map(|min_blob_size| {
+                let next_sst_id = self.next_sst_id.clone();
+                let path = self.path.clone();
+                BlobFileOptions {
+                    min_blob_size,
+                    new_blob_file: Arc::new(move || {
+                        let id = next_sst_id.fetch_add(1, Ordering::SeqCst);
+                        (id, Self::path_of_blob_static(&path, id))
+                    }),
+                }
+            }),
+        })
+    }
 
-        let flush_memtable;
-        let sst_id;
+    /// Build an SST with a newly allocated id.
+    pub(crate) fn build_sst(&self, builder: SsTableBuilder) -> Result<Arc<SsTable>> {
+        let id = self.next_sst_id();
+        Ok(Arc::new(builder.build(
+            id,
+            Some(self.block_cache.clone()),
+            self.path_of_sst(id),
+        )?))
+    }
 
-        // Move mutable memtable to immutable memtables.
+    /// Freeze the current memtables, unless all of them are empty.
+    fn force_freeze_memtables(&self) -> Result<()> {
+        self.freeze_memtables_if(|memtable| !memtable.is_empty())
+    }
+
+    /// Move the current memtables of all column families to their immutable memtables, if the
+    /// current memtable of any column family satisfies `predicate`, which is checked while no
+    /// write can happen. The new memtables get a new WAL.
+    fn freeze_memtables_if(&self, predicate: impl Fn(&MemTable) -> bool) -> Result<()> {
+        let _write_lock = self.write_lock.lock();
+        let column_families = self.column_families.read();
+        if !column_families
+            .values()
+            .any(|cf| predicate(&cf.state.read().memtable))
         {
-            let mut guard = self.inner.write();
+            return Ok(());
+        }
+        let memtable_id = self.next_sst_id();
+        // As in `open`, the memtable is recorded before its WAL is created.
+        self.manifest
+            .add_record(ManifestRecord::NewMemtable(memtable_id))?;
+        let wal = Wal::create(self.path_of_wal(memtable_id), self.options.wal_sync_policy)?;
+        self.sync_dir()?;
+        // The sync policy only applies to the current WAL, so the writes it left unsynced in the
+        // old one are synced before it is swapped out.
+        self.wal.read().sync()?;
+        *self.wal.write() = Arc::new(wal);
+        for cf in column_families.values() {
+            let mut guard = cf.state.write();
             // Swap the current memtable with a new one.
             let mut snapshot = guard.as_ref().clone();
-            let memtable = std::mem::replace(&mut snapshot.memtable, Arc::new(MemTable::create()));
-            flush_memtable = memtable.clone();
-            sst_id = snapshot.next_sst_id;
+            let memtable = std::mem::replace(
+                &mut snapshot.memtable,
+                Arc::new(MemTable::create(
+                    memtable_id,
+                    self.options.comparator.clone(),
+                )),
+            );
             // Add the memtable to the immutable memtables.
             snapshot.imm_memtables.push(memtable);
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        Ok(())
+    }
 
-        // At this point, the old memtable should be disabled for write, and all write threads
-        // should be operating on the new memtable. We can safely flush the old memtable to
-        // disk.
-
-        let mut builder = SsTableBuilder::new(4096);
-        flush_memtable.flush(&mut builder)?;
-        let sst = Arc::new(builder.build(
-            sst_id,
-            Some(self.block_cache.clone()),
-            self.path_of_sst(sst_id),
-        )?);
+    /// Flush the earliest immutable memtables, which were frozen together, to disk. Returns false
+    /// if there are none.
+    ///
+    /// Each column family with a non-empty memtable gets a new L0 SST. The flush is recorded in
+    /// the manifest once all SSTs are on disk, and then the WAL of the memtables is removed.
+    fn flush_earliest_imm_memtables(&self) -> Result<bool> {
+        let _flush_lock = self.flush_lock.lock();
 
-        // Add the flushed L0 table to the list.
+        let column_families = self.column_families();
+        let memtable_id = match column_families
+            .iter()
+            .filter_map(|cf| cf.state.read().imm_memtables.first().map(|x| x.id()))
+            .min()
         {
-            let mut guard = self.inner.write();
+            Some(id) => id,
+            None => return Ok(false),
+        };
+
+        let mut flushed = Vec::new();
+        for cf in &column_families {
+            let flush_memtable = match cf.state.read().imm_memtables.first() {
+                Some(memtable) if memtable.id() == memtable_id => memtable.clone(),
+                // The column family was created after the memtables were frozen.
+                _ => continue,
+            };
+            let sst = if flush_memtable.is_empty() {
+                None
+            } else {
+                let mut builder = self.new_sst_builder();
+                flush_memtable.flush(&mut builder)?;
+                Some(self.build_sst(builder)?)
+            };
+            flushed.push((cf, sst));
+        }
+        self.sync_dir()?;
+        let sst_ids = flushed
+            .iter()
+            .filter_map(|(cf, sst)| Some((cf.id(), sst.as_ref()?.sst_id())))
+            .collect();
+        self.manifest
+            .add_record(ManifestRecord::Flush(memtable_id, sst_ids))?;
+
+        for (cf, sst) in flushed {
+            let mut guard = cf.state.write();
             let mut snapshot = guard.as_ref().clone();
             // Remove the memtable from the immutable memtables.
-            snapshot.imm_memtables.pop();
-            // Add L0 table
-            snapshot.l0_sstables.push(sst);
-            // Update SST ID
-            snapshot.next_sst_id += 1;
+            snapshot.imm_memtables.remove(0);
+            if let Some(sst) = sst {
+                // Add L0 table, or a new tier for tiered compaction.
+                self.compaction_controller
+                    .add_flushed_sst(&mut snapshot, sst.sst_id());
+                snapshot.sstables.insert(sst.sst_id(), sst);
+                // A flush only adds references to blob files, so none is dropped.
+                self.update_blob_files(&mut snapshot)?;
+            }
             // Update the snapshot.
             *guard = Arc::new(snapshot);
         }
+        self.flush_done.notify_all();
 
+        // The data is durable in the SSTs now, so the WAL is no longer needed.
+        std::fs::remove_file(self.path_of_wal(memtable_id))?;
+        Ok(true)
+    }
+
+    /// Flush all immutable memtables to disk, from earliest to latest.
+    fn flush_imm_memtables(&self) -> Result<()> {
+        while self.flush_earliest_imm_memtables()? {}
         Ok(())
     }
 
+    /// Persist data to disk.
+    ///
+    /// In day 3: flush the current memtable to disk as L0 SST.
+    /// In day 6: flush the immutable memtables recovered from WAL as well.
+    pub(crate) fn sync(&self) -> Result<()> {
+        self.force_freeze_memtables()?;
+        // At this point, the old memtables should be disabled for write, and all write threads
+        // should be operating on the new memtables. We can safely flush the immutable memtables
+        // to disk.
+        self.flush_imm_memtables()
+    }
+
+    /// Run `task` on every tick of the background ticker, until `shutdown` is disconnected. The
+    /// error of a failed run is kept in `background_error` until a later run succeeds.
+    fn spawn_background_thread(
+        self: &Arc<Self>,
+        name: &'static str,
+        shutdown: Receiver<()>,
+        task: impl Fn(&Self) -> Result<()> + Send + 'static,
+    ) -> Result<JoinHandle<()>> {
+        let this = self.clone();
+        let handle = std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let ticker = crossbeam_channel::tick(BACKGROUND_TICK);
+                loop {
+                    crossbeam_channel::select! {
+                        recv(ticker) -> _ => this.record_background_result(name, task(&this)),
+                        recv(shutdown) -> _ => return,
+                    }
+                }
+            })?;
+        Ok(handle)
+    }
+
+    /// Keep the error of a background run of `thread`, or clear the error of its earlier run if
+    /// it succeeded.
+    fn record_background_result(&self, thread: &'static str, result: Result<()>) {
+        let mut background_error = self.background_error.lock();
+        match result {
+            Err(e) => {
+                *background_error = Some(Error::BackgroundFailed {
+                    thread,
+                    message: format!("{:#}", e),
+                })
+            }
+            Ok(()) => {
+                if let Some(Error::BackgroundFailed { thread: failed, .. }) = &*background_error {
+                    if *failed == thread {
+                        *background_error = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Spawn the thread that flushes immutable memtables, and syncs the WAL for the periodic
+    /// sync policy.
+    fn spawn_flush_thread(self: &Arc<Self>, shutdown: Receiver<()>) -> Result<JoinHandle<()>> {
+        self.spawn_background_thread("flush", shutdown, |this| {
+            this.wal.read().sync_if_due()?;
+            this.flush_imm_memtables()
+        })
+    }
+
+    /// Spawn the thread that runs compaction.
+    fn spawn_compaction_thread(self: &Arc<Self>, shutdown: Receiver<()>) -> Result<JoinHandle<()>> {
+        self.spawn_background_thread("compaction", shutdown, |this| this.trigger_compaction())
+    }
+
     /// Create an iterator over a range of keys.
-    pub fn scan(
+    pub(crate) fn scan(
         &self,
+        cf: &ColumnFamily,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
     ) -> Result<FusedIterator<LsmIterator>> {
-        let snapshot = {
-            let guard = self.inner.read();
-            Arc::clone(&guard)
-        }; // drop global lock here
+        self.scan_with_seq(cf, lower, upper, self.last_seq.load(Ordering::Acquire))
+    }
 
-        let mut memtable_iters = Vec::new();
-        memtable_iters.reserve(snapshot.imm_memtables.len() + 1);
+    /// Create an iterator over a range of keys, positioned at the last key of the range.
+    pub(crate) fn scan_reverse(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let mut iter = self.scan(cf, lower, upper)?;
+        iter.seek_to_last()?;
+        Ok(iter)
+    }
+
+    /// Create an iterator over the keys starting with `prefix`.
+    pub(crate) fn scan_prefix(
+        &self,
+        cf: &ColumnFamily,
+        prefix: &[u8],
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_inner(
+            cf,
+            Bound::Included(prefix),
+            Bound::Unbounded,
+            Some(prefix),
+            self.last_seq.load(Ordering::Acquire),
+        )
+    }
+
+    /// Create an iterator over a range of keys, which yields the latest version of each key with a
+    /// sequence number not larger than `read_seq`.
+    pub(crate) fn scan_with_seq(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        read_seq: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        self.scan_inner(cf, lower, upper, None, read_seq)
+    }
+
+    /// Create an iterator over a range of keys, and only the keys starting with `prefix` if there
+    /// is one. The SSTs whose prefix bloom filter rules out the prefix are skipped.
+    fn scan_inner(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        prefix: Option<&[u8]>,
+        read_seq: u64,
+    ) -> Result<FusedIterator<LsmIterator>> {
+        let snapshot = Self::snapshot_of(cf)?;
+
+        let mut memtable_iters = Vec::with_capacity(snapshot.imm_memtables.len() + 1);
         memtable_iters.push(Box::new(snapshot.memtable.scan(lower, upper)));
         for memtable in snapshot.imm_memtables.iter().rev() {
             memtable_iters.push(Box::new(memtable.scan(lower, upper)));
         }
-        let memtable_iter = MergeIterator::create(memtable_iters);
+        let comparator = &self.options.comparator;
+        let memtable_iter = MergeIterator::create(memtable_iters, comparator.clone());
 
-        let mut table_iters = Vec::new();
-        table_iters.reserve(snapshot.l0_sstables.len());
-        for table in snapshot.l0_sstables.iter().rev() {
-            let iter = match lower {
-                Bound::Included(key) => {
-                    SsTableIterator::create_and_seek_to_key(table.clone(), key)?
-                }
-                Bound::Excluded(key) => {
-                    let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), key)?;
-                    if iter.is_valid() && iter.key() == key {
-                        iter.next()?;
-                    }
-                    iter
+        // The prefix filters can only rule out a prefix as extracted from the keys.
+        let filter_prefix = prefix
+            .zip(self.options.prefix_extractor.as_deref())
+            .filter(|(prefix, extractor)| extractor.prefix(prefix) == Some(*prefix));
+        let mut table_iters = Vec::with_capacity(snapshot.sstables.len());
+        for table in snapshot.sst_ids_newest_first() {
+            let table = &snapshot.sstables[table];
+            if !range_overlap(
+                comparator.as_ref(),
+                lower,
+                upper,
+                table.first_key(),
+                table.last_key(),
+            ) {
+                continue;
+            }
+            if let Some((prefix, extractor)) = filter_prefix {
+                if !table.may_contain_prefix(extractor, prefix) {
+                    continue;
                 }
-                Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table.clone())?,
-            };
-
-            table_iters.push(Box::new(iter));
+            }
+            table_iters.push(Box::new(seek_to_lower(table.clone(), lower)?));
         }
-        let table_iter = MergeIterator::create(table_iters);
+        let table_iter = MergeIterator::create(table_iters, comparator.clone());
 
-        let iter = TwoMergeIterator::create(memtable_iter, table_iter)?;
+        let iter = TwoMergeIterator::create(memtable_iter, table_iter, comparator.clone())?;
 
-        Ok(FusedIterator::new(LsmIterator::new(
+        let mut iter = LsmIterator::new(
             iter,
+            map_bound(lower),
             map_bound(upper),
-        )?))
+            read_seq,
+            &snapshot,
+            self.options.merge_operator.clone(),
+            comparator.clone(),
+        )?;
+        if let Some(prefix) = prefix {
+            iter = iter.with_prefix(Bytes::copy_from_slice(prefix));
+        }
+        Ok(FusedIterator::new(iter))
+    }
+
+    /// Find a key in a range of a column family with a put or a merge newer than `seq`, which is
+    /// a key inserted or updated since a scan as of `seq`. Only the memtables and SSTs with a
+    /// version newer than `seq` are searched, which are usually the few latest ones.
+    pub(crate) fn find_put_after(
+        &self,
+        cf: &ColumnFamily,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        seq: u64,
+    ) -> Result<Option<Bytes>> {
+        let snapshot = Self::snapshot_of(cf)?;
+        let comparator = self.options.comparator.as_ref();
+        for memtable in snapshot.memtables_newest_first() {
+            if memtable.max_seq() > seq {
+                let mut iter = memtable.scan(lower, upper);
+                if let Some(key) = find_put_after(&mut iter, comparator, upper, seq)? {
+                    return Ok(Some(key));
+                }
+            }
+        }
+        for table in snapshot.sstables.values() {
+            if table.max_seq() > seq
+                && range_overlap(
+                    comparator,
+                    lower,
+                    upper,
+                    table.first_key(),
+                    table.last_key(),
+                )
+            {
+                let mut iter = seek_to_lower(table.clone(), lower)?;
+                if let Some(key) = find_put_after(&mut iter, comparator, upper, seq)? {
+                    return Ok(Some(key));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Create an iterator over an SST, positioned at the first key within `lower`.
+fn seek_to_lower(table: Arc<SsTable>, lower: Bound<&[u8]>) -> Result<SsTableIterator> {
+    match lower {
+        Bound::Included(key) => SsTableIterator::create_and_seek_to_key(table, key),
+        Bound::Excluded(key) => {
+            let mut iter = SsTableIterator::create_and_seek_to_key(table, key)?;
+            while iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+            Ok(iter)
+        }
+        Bound::Unbounded => SsTableIterator::create_and_seek_to_first(table),
+    }
+}
+
+/// Find a key with a put or a merge newer than `seq` from the current position of `iter` up to
+/// `upper`.
+fn find_put_after(
+    iter: &mut impl StorageIterator,
+    comparator: &dyn Comparator,
+    upper: Bound<&[u8]>,
+    seq: u64,
+) -> Result<Option<Bytes>> {
+    while iter.is_valid() && range_contains(comparator, Bound::Unbounded, upper, iter.key()) {
+        if iter.seq() > seq && iter.value_type() != ValueType::Delete {
+            return Ok(Some(Bytes::copy_from_slice(iter.key())));
+        }
+        iter.next()?;
     }
+    Ok(None)
 }
@@ -0,0 +1,124 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::compact::CompactionTask;
+use crate::error::Error;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+
+/// A change to the structure of the LSM tree.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ManifestRecord {
+    /// The memtables of all column families with the given id were flushed, each to an L0 SST of
+    /// its column family, given as (column family id, SST id). Empty memtables have no SST.
+    Flush(usize, Vec<(u32, usize)>),
+    /// New memtables with the given id were created for all column families, along with their
+    /// WAL. This also moves `next_sst_id` past it.
+    NewMemtable(usize),
+    /// The input SSTs of the compaction task in a column family were replaced by the output SSTs
+    /// with the given ids.
+    Compaction(u32, CompactionTask, Vec<usize>),
+    /// SSTs of a column family were rewritten to move their values out of blob files with little
+    /// live data, each replaced by a new SST in its place, given as (old SST id, new SST id).
+    BlobGarbageCollection(u32, Vec<(usize, usize)>),
+    /// A column family with the given id and name was created.
+    CreateColumnFamily(u32, String),
+    /// The column family with the given id was dropped, along with its SSTs.
+    DropColumnFamily(u32),
+}
+
+/// The manifest is a log of [`ManifestRecord`]s. Replaying it from the beginning rebuilds the
+/// structure of the LSM tree.
+///
+/// Each record is encoded as `len (u32) | json | checksum (u32)`, where the checksum is the
+/// CRC32C of the json payload. Every record is synced to disk before `add_record` returns, so a
+/// change is either fully recorded or not recorded at all.
+pub struct Manifest {
+    file: Mutex<File>,
+}
+
+impl Manifest {
+    /// Create a new, empty manifest at `path`.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create manifest {}", path.as_ref().display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Read all records from the manifest at `path`, and reopen it for appending.
+    ///
+    /// A torn record at the end of the log is the result of a crash during `add_record`. It was
+    /// never acknowledged, so it is dropped and the log is truncated to the last good record. A
+    /// bad record followed by others is not, so it fails with [`Error::ManifestCorruption`] and
+    /// the log is left as it is.
+    pub fn recover(path: impl AsRef<Path>) -> Result<(Self, Vec<ManifestRecord>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to open manifest {}", path.as_ref().display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+
+        let mut records = Vec::new();
+        let mut rbuf = &buf[..];
+        while rbuf.remaining() >= SIZEOF_U32 {
+            let len = (&rbuf[..SIZEOF_U32]).get_u32() as usize;
+            if rbuf.remaining() < SIZEOF_U32 * 2 + len {
+                break;
+            }
+            let payload = &rbuf[SIZEOF_U32..SIZEOF_U32 + len];
+            let checksum = (&rbuf[SIZEOF_U32 + len..]).get_u32();
+            if checksum != crc32c::crc32c(payload) {
+                if rbuf.remaining() > SIZEOF_U32 * 2 + len {
+                    return Err(Error::ManifestCorruption {
+                        offset: (buf.len() - rbuf.remaining()) as u64,
+                    }
+                    .into());
+                }
+                break;
+            }
+            records.push(serde_json::from_slice(payload)?);
+            rbuf.advance(SIZEOF_U32 * 2 + len);
+        }
+
+        if rbuf.has_remaining() {
+            file.set_len((buf.len() - rbuf.remaining()) as u64)?;
+            file.sync_all()?;
+        }
+        Ok((
+            Self {
+                file: Mutex::new(file),
+            },
+            records,
+        ))
+    }
+
+    /// Append a record to the manifest and sync it to disk.
+    pub fn add_record(&self, record: ManifestRecord) -> Result<()> {
+        let payload = serde_json::to_vec(&record)?;
+        let mut buf = Vec::with_capacity(payload.len() + SIZEOF_U32 * 2);
+        buf.put_u32(payload.len() as u32);
+        buf.put_slice(&payload);
+        buf.put_u32(crc32c::crc32c(&payload));
+
+        let mut file = self.file.lock();
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,74 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+use tempfile::tempdir;
+
+use super::{Manifest, ManifestRecord};
+use crate::error::Error;
+
+#[test]
+fn test_manifest_recover_torn_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        manifest.add_record(ManifestRecord::NewMemtable(1)).unwrap();
+        manifest
+            .add_record(ManifestRecord::Flush(1, vec![(0, 2)]))
+            .unwrap();
+    }
+    // Simulate a crash in the middle of writing a record.
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[0, 0, 0, 32, b'{'])
+        .unwrap();
+
+    let (manifest, records) = Manifest::recover(&path).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            ManifestRecord::NewMemtable(1),
+            ManifestRecord::Flush(1, vec![(0, 2)])
+        ]
+    );
+    manifest.add_record(ManifestRecord::NewMemtable(3)).unwrap();
+    drop(manifest);
+
+    let (_, records) = Manifest::recover(&path).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[2], ManifestRecord::NewMemtable(3));
+}
+
+#[test]
+fn test_manifest_recover_corrupted_record() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("MANIFEST");
+    {
+        let manifest = Manifest::create(&path).unwrap();
+        for id in 0..3 {
+            manifest
+                .add_record(ManifestRecord::NewMemtable(id))
+                .unwrap();
+        }
+    }
+    // Flip a byte in the payload of the second record.
+    let record_len = std::fs::metadata(&path).unwrap().len() / 3;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    file.seek(SeekFrom::Start(record_len + 6)).unwrap();
+    file.write_all(b"X").unwrap();
+    drop(file);
+
+    let err = Manifest::recover(&path).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<Error>(),
+        Some(&Error::ManifestCorruption { offset: record_len })
+    );
+    // The records after it are kept.
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), record_len * 3);
+}
@@ -1,4 +1,6 @@
-use std::ops::Bound;
+use std::cmp::Ordering as CmpOrdering;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -6,13 +8,29 @@ use bytes::Bytes;
 use crossbeam_skiplist::map::Entry;
 use crossbeam_skiplist::SkipMap;
 use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
-use crate::iterators::StorageIterator;
+use crate::comparator::Comparator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::mvcc::{compare_entries, InternalKey, ValueType, MAX_SEQ};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
 
-/// A basic mem-table based on crossbeam-skiplist
+/// A basic mem-table based on crossbeam-skiplist. Every version of a key is kept, ordered by
+/// [`compare_entries`] with the comparator of the mem-table, with its type and value. Range
+/// tombstones are kept apart from the keys.
+///
+/// The mem-table does not log writes. The storage logs them to the WAL shared by the mem-tables
+/// of all column families before they are written here.
 pub struct MemTable {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<MemTableKey, (ValueType, Bytes)>>,
+    range_tombstones: RwLock<Vec<RangeTombstone>>,
+    comparator: Arc<dyn Comparator>,
+    id: usize,
+    /// Approximate number of bytes written into the mem-table, counting overwritten entries.
+    approximate_size: AtomicUsize,
+    /// The largest sequence number written into the mem-table.
+    max_seq: AtomicU64,
 }
 
 pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
@@ -23,85 +41,320 @@ pub(crate) fn map_bound(bound: Bound<&[u8]>) -> Bound<Bytes> {
     }
 }
 
+/// An internal key in the skip map, which carries the comparator that orders it.
+struct MemTableKey {
+    key: InternalKey,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl PartialEq for MemTableKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for MemTableKey {}
+
+impl PartialOrd for MemTableKey {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MemTableKey {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        compare_entries(
+            self.comparator.as_ref(),
+            &self.key.key,
+            self.key.seq,
+            &other.key.key,
+            other.key.seq,
+        )
+    }
+}
+
 impl MemTable {
-    /// Create a new mem-table.
-    pub fn create() -> Self {
+    /// Create a new mem-table, whose keys are ordered by `comparator`. The mem-tables sharing a
+    /// WAL have the same id as the WAL.
+    pub fn create(id: usize, comparator: Arc<dyn Comparator>) -> Self {
         Self {
             map: Arc::new(SkipMap::new()),
+            range_tombstones: RwLock::new(Vec::new()),
+            comparator,
+            id,
+            approximate_size: AtomicUsize::new(0),
+            max_seq: AtomicU64::new(0),
+        }
+    }
+
+    fn map_key(&self, key: Bytes, seq: u64) -> MemTableKey {
+        MemTableKey {
+            key: InternalKey::new(key, seq),
+            comparator: self.comparator.clone(),
+        }
+    }
+
+    /// Map a bound on keys to a bound on internal keys that covers every version of the keys.
+    fn map_lower_bound(&self, bound: Bound<&[u8]>) -> Bound<MemTableKey> {
+        match map_bound(bound) {
+            Bound::Included(x) => Bound::Included(self.map_key(x, u64::MAX)),
+            Bound::Excluded(x) => Bound::Excluded(self.map_key(x, 0)),
+            Bound::Unbounded => Bound::Unbounded,
         }
     }
 
-    /// Get a value by key.
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.map.get(key).map(|e| e.value().clone())
+    fn map_upper_bound(&self, bound: Bound<&[u8]>) -> Bound<MemTableKey> {
+        match map_bound(bound) {
+            Bound::Included(x) => Bound::Included(self.map_key(x, 0)),
+            Bound::Excluded(x) => Bound::Excluded(self.map_key(x, u64::MAX)),
+            Bound::Unbounded => Bound::Unbounded,
+        }
     }
 
-    /// Put a key-value pair into the mem-table.
-    pub fn put(&self, key: &[u8], value: &[u8]) {
+    fn entry_size(key: &[u8], value: &[u8]) -> usize {
+        key.len() + std::mem::size_of::<u64>() + value.len()
+    }
+
+    /// Get the type and the value of the latest version of a key with a sequence number not
+    /// larger than `read_seq`.
+    pub fn get(&self, key: &[u8], read_seq: u64) -> Option<(ValueType, Bytes)> {
+        let lower = self.map_key(Bytes::copy_from_slice(key), read_seq);
         self.map
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+            .range(lower..)
+            .next()
+            .filter(|entry| entry.key().key.key == key)
+            .map(|entry| entry.value().clone())
+    }
+
+    /// Put a key-value pair written with sequence number `seq` into the mem-table.
+    pub fn put(&self, key: &[u8], seq: u64, value: &[u8]) -> Result<()> {
+        self.put_batch(seq, &[(key, ValueType::Put, value)])
+    }
+
+    /// Delete a key with sequence number `seq` in the mem-table.
+    pub fn delete(&self, key: &[u8], seq: u64) -> Result<()> {
+        self.put_batch(seq, &[(key, ValueType::Delete, &[])])
+    }
+
+    /// Put a merge operand for a key written with sequence number `seq` into the mem-table.
+    pub fn merge(&self, key: &[u8], seq: u64, operand: &[u8]) -> Result<()> {
+        self.put_batch(seq, &[(key, ValueType::Merge, operand)])
+    }
+
+    /// Put a batch of writes into the mem-table. A write is a key, its type and its value, and the
+    /// `i`-th write is written with sequence number `seq + i`.
+    pub fn put_batch(&self, seq: u64, entries: &[(&[u8], ValueType, &[u8])]) -> Result<()> {
+        for (&(key, value_type, value), seq) in entries.iter().zip(seq..) {
+            self.map.insert(
+                self.map_key(Bytes::copy_from_slice(key), seq),
+                (value_type, Bytes::copy_from_slice(value)),
+            );
+            self.approximate_size
+                .fetch_add(Self::entry_size(key, value), Ordering::Relaxed);
+            self.max_seq.fetch_max(seq, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Delete the keys in `start..end` with a range tombstone of sequence number `seq`.
+    pub fn delete_range(&self, start: &[u8], end: &[u8], seq: u64) -> Result<()> {
+        self.range_tombstones.write().push(RangeTombstone::new(
+            Bytes::copy_from_slice(start),
+            Bytes::copy_from_slice(end),
+            seq,
+        ));
+        self.approximate_size
+            .fetch_add(Self::entry_size(start, end), Ordering::Relaxed);
+        self.max_seq.fetch_max(seq, Ordering::Relaxed);
+        Ok(())
     }
 
-    /// Get an iterator over a range of keys.
+    /// Get the range tombstones in the mem-table.
+    pub fn range_tombstones(&self) -> Vec<RangeTombstone> {
+        self.range_tombstones.read().clone()
+    }
+
+    /// Get the largest sequence number in the mem-table, or 0 if it is empty.
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq.load(Ordering::Relaxed)
+    }
+
+    /// Get the id of this mem-table, which is the id of its WAL.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the approximate number of bytes written into the mem-table.
+    pub fn approximate_size(&self) -> usize {
+        self.approximate_size.load(Ordering::Relaxed)
+    }
+
+    /// Check if there is no key-value pair or range tombstone in the mem-table.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty() && self.range_tombstones.read().is_empty()
+    }
+
+    /// Get an iterator over every version of a range of keys, positioned at the first entry.
     pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
-        let (lower, upper) = (map_bound(lower), map_bound(upper));
         let mut iter = MemTableIteratorBuilder {
             map: self.map.clone(),
-            iter_builder: |map| map.range((lower, upper)),
-            item: (Bytes::from_static(&[]), Bytes::from_static(&[])),
+            bounds: (self.map_lower_bound(lower), self.map_upper_bound(upper)),
+            comparator: self.comparator.clone(),
+            entry_builder: |_| None,
+            item: (
+                InternalKey::new(Bytes::new(), 0),
+                (ValueType::Put, Bytes::new()),
+            ),
         }
         .build();
-        let entry = iter.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        iter.with_mut(|x| *x.item = entry);
+        iter.move_to(|map, bounds, _| map.lower_bound(bounds.0.as_ref()));
         iter
     }
 
     /// Flush the mem-table to SSTable.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
         for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
+            let (value_type, value) = entry.value();
+            let key = &entry.key().key;
+            builder.add(&key.key, key.seq, *value_type, value);
+        }
+        for tombstone in self.range_tombstones.read().iter() {
+            builder.add_range_tombstone(tombstone.clone());
         }
         Ok(())
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Bytes, (Bound<Bytes>, Bound<Bytes>), Bytes, Bytes>;
+type SkipMapEntry<'a> = Entry<'a, MemTableKey, (ValueType, Bytes)>;
 
 /// An iterator over a range of `SkipMap`.
 #[self_referencing]
 pub struct MemTableIterator {
-    map: Arc<SkipMap<Bytes, Bytes>>,
+    map: Arc<SkipMap<MemTableKey, (ValueType, Bytes)>>,
+    bounds: (Bound<MemTableKey>, Bound<MemTableKey>),
+    comparator: Arc<dyn Comparator>,
+    /// The current entry, or `None` if the iterator is not valid.
     #[borrows(map)]
     #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    item: (Bytes, Bytes),
+    entry: Option<SkipMapEntry<'this>>,
+    item: (InternalKey, (ValueType, Bytes)),
 }
 
 impl MemTableIterator {
-    fn entry_to_item(entry: Option<Entry<'_, Bytes, Bytes>>) -> (Bytes, Bytes) {
+    /// Move to the entry chosen by `f` from the map, the bounds and the current entry. The
+    /// iterator is not valid if the entry is out of the bounds.
+    fn move_to(
+        &mut self,
+        f: impl for<'a> FnOnce(
+            &'a SkipMap<MemTableKey, (ValueType, Bytes)>,
+            &(Bound<MemTableKey>, Bound<MemTableKey>),
+            Option<&SkipMapEntry<'a>>,
+        ) -> Option<SkipMapEntry<'a>>,
+    ) {
+        self.with_mut(|x| {
+            let entry = f(x.map, x.bounds, x.entry.as_ref())
+                .filter(|entry| RangeBounds::contains(x.bounds, entry.key()));
+            *x.item = Self::entry_to_item(entry.as_ref());
+            *x.entry = entry;
+        });
+    }
+
+    fn entry_to_item(entry: Option<&SkipMapEntry<'_>>) -> (InternalKey, (ValueType, Bytes)) {
         entry
-            .map(|x| (x.key().clone(), x.value().clone()))
-            .unwrap_or_else(|| (Bytes::from_static(&[]), Bytes::from_static(&[])))
+            .map(|x| (x.key().key.clone(), x.value().clone()))
+            .unwrap_or_else(|| {
+                (
+                    InternalKey::new(Bytes::new(), 0),
+                    (ValueType::Put, Bytes::new()),
+                )
+            })
     }
 }
 
 impl StorageIterator for MemTableIterator {
     fn value(&self) -> &[u8] {
-        &self.borrow_item().1[..]
+        let (_, value) = &self.borrow_item().1;
+        value
+    }
+
+    fn value_type(&self) -> ValueType {
+        let (value_type, _) = self.borrow_item().1;
+        value_type
     }
 
     fn key(&self) -> &[u8] {
-        &self.borrow_item().0[..]
+        &self.borrow_item().0.key[..]
+    }
+
+    fn seq(&self) -> u64 {
+        self.borrow_item().0.seq
     }
 
     fn is_valid(&self) -> bool {
-        !self.borrow_item().0.is_empty()
+        !self.borrow_item().0.key.is_empty()
     }
 
     fn next(&mut self) -> Result<()> {
-        let entry = self.with_iter_mut(|iter| MemTableIterator::entry_to_item(iter.next()));
-        self.with_mut(|x| *x.item = entry);
+        self.move_to(|_, _, entry| entry.and_then(|entry| entry.next()));
+        Ok(())
+    }
+}
+
+impl BidirectionalIterator for MemTableIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.move_to(|map, bounds, _| map.lower_bound(bounds.0.as_ref()));
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.move_to(|map, bounds, _| map.upper_bound(bounds.1.as_ref()));
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        let key = MemTableKey {
+            key: InternalKey::new(Bytes::copy_from_slice(key), MAX_SEQ),
+            comparator: self.borrow_comparator().clone(),
+        };
+        self.move_to(|map, bounds, _| {
+            // The lower bound of the iterator may be after the key.
+            let after_lower = match &bounds.0 {
+                Bound::Included(lower) => key >= *lower,
+                Bound::Excluded(lower) => key > *lower,
+                Bound::Unbounded => true,
+            };
+            if after_lower {
+                map.lower_bound(Bound::Included(&key))
+            } else {
+                map.lower_bound(bounds.0.as_ref())
+            }
+        });
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let key = MemTableKey {
+            key: InternalKey::new(Bytes::copy_from_slice(key), 0),
+            comparator: self.borrow_comparator().clone(),
+        };
+        self.move_to(|map, bounds, _| {
+            // The upper bound of the iterator may be before the key.
+            let before_upper = match &bounds.1 {
+                Bound::Included(upper) => key <= *upper,
+                Bound::Excluded(upper) => key < *upper,
+                Bound::Unbounded => true,
+            };
+            if before_upper {
+                map.upper_bound(Bound::Included(&key))
+            } else {
+                map.upper_bound(bounds.1.as_ref())
+            }
+        });
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.move_to(|_, _, entry| entry.and_then(|entry| entry.prev()));
         Ok(())
     }
 }
@@ -1,40 +1,45 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
 use tempfile::tempdir;
 
 use super::MemTable;
-use crate::iterators::StorageIterator;
+use crate::comparator::BytewiseComparator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::mvcc::{ValueType, MAX_SEQ};
 use crate::table::{SsTableBuilder, SsTableIterator};
 
 #[test]
 fn test_memtable_get() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value1");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value2");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value3");
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key3", 3, b"value3").unwrap();
+    assert_eq!(&memtable.get(b"key1", MAX_SEQ).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key2", MAX_SEQ).unwrap().1[..], b"value2");
+    assert_eq!(&memtable.get(b"key3", MAX_SEQ).unwrap().1[..], b"value3");
 }
 
 #[test]
 fn test_memtable_overwrite() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
-    memtable.put(b"key1", b"value11");
-    memtable.put(b"key2", b"value22");
-    memtable.put(b"key3", b"value33");
-    assert_eq!(&memtable.get(b"key1").unwrap()[..], b"value11");
-    assert_eq!(&memtable.get(b"key2").unwrap()[..], b"value22");
-    assert_eq!(&memtable.get(b"key3").unwrap()[..], b"value33");
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 4, b"value1").unwrap();
+    memtable.put(b"key2", 5, b"value2").unwrap();
+    memtable.put(b"key3", 6, b"value3").unwrap();
+    memtable.put(b"key1", 7, b"value11").unwrap();
+    memtable.put(b"key2", 8, b"value22").unwrap();
+    memtable.put(b"key3", 9, b"value33").unwrap();
+    assert_eq!(&memtable.get(b"key1", MAX_SEQ).unwrap().1[..], b"value11");
+    assert_eq!(&memtable.get(b"key2", MAX_SEQ).unwrap().1[..], b"value22");
+    assert_eq!(&memtable.get(b"key3", MAX_SEQ).unwrap().1[..], b"value33");
 }
 
 #[test]
 fn test_memtable_flush() {
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 10, b"value1").unwrap();
+    memtable.put(b"key2", 11, b"value2").unwrap();
+    memtable.put(b"key3", 12, b"value3").unwrap();
     let mut builder = SsTableBuilder::new(128);
     memtable.flush(&mut builder).unwrap();
     let dir = tempdir().unwrap();
@@ -55,10 +60,10 @@ fn test_memtable_flush() {
 #[test]
 fn test_memtable_iter() {
     use std::ops::Bound;
-    let memtable = MemTable::create();
-    memtable.put(b"key1", b"value1");
-    memtable.put(b"key2", b"value2");
-    memtable.put(b"key3", b"value3");
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 13, b"value1").unwrap();
+    memtable.put(b"key2", 14, b"value2").unwrap();
+    memtable.put(b"key3", 15, b"value3").unwrap();
 
     {
         let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
@@ -93,3 +98,104 @@ fn test_memtable_iter() {
         assert!(!iter.is_valid());
     }
 }
+
+#[test]
+fn test_memtable_iterate_backward() {
+    use std::ops::Bound;
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key2", 3, b"value22").unwrap();
+    memtable.put(b"key3", 4, b"value3").unwrap();
+
+    let mut iter = memtable.scan(Bound::Unbounded, Bound::Unbounded);
+    iter.seek_to_last().unwrap();
+    for (key, seq) in [(b"key3", 4), (b"key2", 2), (b"key2", 3), (b"key1", 1)] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.seq(), seq);
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // The bounds of the scan still apply.
+    let mut iter = memtable.scan(Bound::Excluded(b"key1"), Bound::Excluded(b"key3"));
+    iter.seek_to_last().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.seek_for_prev(b"key4").unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.prev().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 3));
+    iter.next().unwrap();
+    assert_eq!((iter.key(), iter.seq()), (&b"key2"[..], 2));
+    iter.prev().unwrap();
+    iter.prev().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(b"key1").unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_approximate_size() {
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    assert_eq!(memtable.approximate_size(), 0);
+    memtable.put(b"key1", 16, b"value1").unwrap();
+    assert_eq!(memtable.approximate_size(), 18);
+    memtable.put(b"key1", 17, b"value11").unwrap();
+    assert_eq!(memtable.approximate_size(), 37);
+}
+
+#[test]
+fn test_memtable_get_versions() {
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 1, b"value1").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.put(b"key1", 3, b"value11").unwrap();
+    assert!(memtable.get(b"key1", 0).is_none());
+    assert_eq!(&memtable.get(b"key1", 1).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key1", 2).unwrap().1[..], b"value1");
+    assert_eq!(&memtable.get(b"key1", 3).unwrap().1[..], b"value11");
+    assert!(memtable.get(b"key2", 1).is_none());
+    assert_eq!(memtable.max_seq(), 3);
+
+    // The iterator yields every version, from the newest to the oldest.
+    let mut iter = memtable.scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded);
+    for (key, seq) in [(&b"key1"[..], 3), (b"key1", 1), (b"key2", 2)] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.seq(), seq);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_memtable_delete() {
+    let memtable = MemTable::create(0, Arc::new(BytewiseComparator));
+    memtable.put(b"key1", 1, b"").unwrap();
+    memtable.put(b"key2", 2, b"value2").unwrap();
+    memtable.delete(b"key2", 3).unwrap();
+    assert_eq!(
+        memtable.get(b"key1", MAX_SEQ),
+        Some((ValueType::Put, Bytes::new()))
+    );
+    assert_eq!(
+        memtable.get(b"key2", MAX_SEQ),
+        Some((ValueType::Delete, Bytes::new()))
+    );
+    assert_eq!(
+        memtable.get(b"key2", 2),
+        Some((ValueType::Put, Bytes::from("value2")))
+    );
+    assert_eq!(memtable.get(b"key3", MAX_SEQ), None);
+
+    let mut iter = memtable.scan(std::ops::Bound::Unbounded, std::ops::Bound::Unbounded);
+    for (key, value_type) in [
+        (&b"key1"[..], ValueType::Put),
+        (b"key2", ValueType::Delete),
+        (b"key2", ValueType::Put),
+    ] {
+        assert_eq!(iter.key(), key);
+        assert_eq!(iter.value_type(), value_type);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
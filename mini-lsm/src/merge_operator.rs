@@ -0,0 +1,46 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+
+/// Combines merge operands written by [`LsmStorage::merge`] into the value of a key, so that a
+/// read-modify-write such as incrementing a counter does not need to read the key first.
+///
+/// Operands are resolved lazily: by reads, which see the merged value, and by compaction, which
+/// replaces them with the merged value once the base value of the key is reached.
+///
+/// [`LsmStorage::merge`]: crate::lsm_storage::LsmStorage::merge
+pub trait MergeOperator: Send + Sync {
+    /// The name of the operator.
+    fn name(&self) -> &str;
+
+    /// Apply `operands`, from the oldest to the newest, to the base value of `key`, which is
+    /// `None` if the key does not exist or is deleted.
+    fn full_merge(&self, key: &[u8], base: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes>;
+
+    /// Combine `operands`, from the oldest to the newest, into a single operand without knowing
+    /// the base value. Returns `None` if they cannot be combined, which is the default.
+    fn partial_merge(&self, _key: &[u8], _operands: &[&[u8]]) -> Option<Bytes> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn MergeOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Apply `operands`, given from the newest to the oldest, to `base` with `merge_operator`. Fails
+/// if there is no merge operator to resolve them.
+pub(crate) fn full_merge(
+    merge_operator: Option<&dyn MergeOperator>,
+    key: &[u8],
+    base: Option<&[u8]>,
+    operands: &[Bytes],
+) -> Result<Bytes> {
+    let merge_operator = merge_operator
+        .with_context(|| format!("no merge operator to resolve the operands of {:?}", key))?;
+    let operands: Vec<&[u8]> = operands.iter().rev().map(|x| &x[..]).collect();
+    merge_operator.full_merge(key, base, &operands)
+}
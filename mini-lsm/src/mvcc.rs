@@ -0,0 +1,138 @@
+mod txn;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+pub use txn::{Transaction, TxnIterator};
+
+use crate::comparator::Comparator;
+
+/// Sequence number that reads at the latest version of every key.
+pub const MAX_SEQ: u64 = u64::MAX;
+
+/// Compare two entries by key with `comparator`, and then from the newest version (the largest
+/// sequence number) to the oldest.
+pub fn compare_entries(
+    comparator: &dyn Comparator,
+    key_a: &[u8],
+    seq_a: u64,
+    key_b: &[u8],
+    seq_b: u64,
+) -> Ordering {
+    comparator
+        .compare(key_a, key_b)
+        .then_with(|| seq_b.cmp(&seq_a))
+}
+
+/// Whether an entry puts a value for its key, deletes the key, or merges an operand into the
+/// older versions of the key. A delete is a tombstone that shadows the older versions of the key,
+/// and its value is always empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    Delete,
+    Put,
+    /// A merge operand, see [`MergeOperator`](crate::merge_operator::MergeOperator).
+    Merge,
+    /// A put whose value is in a blob file, and whose stored value is a
+    /// [`BlobRef`](crate::blob::BlobRef) to it. Only SSTs have blob references.
+    Blob,
+}
+
+impl ValueType {
+    /// The tag stored with each entry.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Delete => 0,
+            Self::Put => 1,
+            // 2 marks range tombstones in the WAL.
+            Self::Merge => 3,
+            Self::Blob => 4,
+        }
+    }
+
+    /// Parse the tag stored with an entry.
+    pub fn from_u8(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Self::Delete),
+            1 => Ok(Self::Put),
+            3 => Ok(Self::Merge),
+            4 => Ok(Self::Blob),
+            _ => bail!("unknown value type {}", tag),
+        }
+    }
+
+    /// Get the type of a value, where `None` is a delete.
+    pub fn of<T>(value: &Option<T>) -> Self {
+        match value {
+            Some(_) => Self::Put,
+            None => Self::Delete,
+        }
+    }
+}
+
+/// A key together with the sequence number of the write that produced it. Entries are ordered by
+/// [`compare_entries`] with the comparator of the storage.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InternalKey {
+    pub key: Bytes,
+    pub seq: u64,
+}
+
+impl InternalKey {
+    pub fn new(key: Bytes, seq: u64) -> Self {
+        Self { key, seq }
+    }
+}
+
+/// The sequence numbers pinned by live snapshots, with the number of snapshots pinning each.
+#[derive(Default)]
+pub(crate) struct SnapshotList {
+    seqs: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl SnapshotList {
+    /// Pin `seq` until the returned snapshot is dropped.
+    pub(crate) fn acquire(self: &Arc<Self>, seq: u64) -> Snapshot {
+        *self.seqs.lock().entry(seq).or_default() += 1;
+        Snapshot {
+            seq,
+            list: self.clone(),
+        }
+    }
+
+    /// Get the pinned sequence numbers in ascending order.
+    pub(crate) fn seqs(&self) -> Vec<u64> {
+        self.seqs.lock().keys().copied().collect()
+    }
+}
+
+/// A consistent, point-in-time view of the storage. Reads with a snapshot see every write
+/// acknowledged before the snapshot was taken, and none after. Compaction keeps the versions
+/// visible to a snapshot until it is dropped.
+pub struct Snapshot {
+    seq: u64,
+    list: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    /// Get the sequence number of the latest write visible to the snapshot.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut seqs = self.list.seqs.lock();
+        let count = seqs.get_mut(&self.seq).expect("snapshot not found");
+        *count -= 1;
+        if *count == 0 {
+            seqs.remove(&self.seq);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use parking_lot::Mutex;
+
+use super::{Snapshot, ValueType, MAX_SEQ};
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::comparator::{range_contains, Comparator};
+use crate::error::Error;
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_iterator::{FusedIterator, LsmIterator};
+use crate::lsm_storage::LsmStorageInner;
+use crate::mem_table::map_bound;
+
+/// An optimistic transaction. Reads see the storage as of when the transaction began, overlaid
+/// with the writes of the transaction, which are buffered until commit.
+///
+/// Commit fails with [`Error::TransactionConflict`] if a key read or written by the transaction,
+/// or a key in the range covered by one of its scans, has been written by anyone else since it
+/// began, which makes committed transactions serializable. A transaction dropped without commit
+/// is rolled back. Transactions read and write the default column family.
+pub struct Transaction {
+    inner: Arc<LsmStorageInner>,
+    snapshot: Snapshot,
+    /// The writes of the transaction, where a delete is `None`.
+    write_set: Mutex<BTreeMap<Bytes, Option<Bytes>>>,
+    /// The keys read by the transaction.
+    read_set: Mutex<HashSet<Bytes>>,
+    /// The ranges covered by the scans of the transaction, as (lower, upper).
+    scanned_ranges: Mutex<Vec<(Bound<Bytes>, Bound<Bytes>)>>,
+}
+
+fn as_slice(bound: &Bound<Bytes>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key),
+        Bound::Excluded(key) => Bound::Excluded(key),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl Transaction {
+    pub(crate) fn new(inner: Arc<LsmStorageInner>) -> Self {
+        Self {
+            snapshot: inner.snapshot(),
+            inner,
+            write_set: Mutex::new(BTreeMap::new()),
+            read_set: Mutex::new(HashSet::new()),
+            scanned_ranges: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get a key, as written by the transaction or as of when the transaction began.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.read_set.lock().insert(Bytes::copy_from_slice(key));
+        if let Some(value) = self.write_set.lock().get(key) {
+            return Ok(value.clone());
+        }
+        self.inner
+            .get_with_seq(&self.inner.default_cf, key, self.snapshot.seq())
+    }
+
+    /// Put a key-value pair when the transaction commits.
+    pub fn put(&self, key: &[u8], value: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.write_set.lock().insert(
+            Bytes::copy_from_slice(key),
+            Some(Bytes::copy_from_slice(value)),
+        );
+    }
+
+    /// Remove a key when the transaction commits.
+    pub fn delete(&self, key: &[u8]) {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.write_set
+            .lock()
+            .insert(Bytes::copy_from_slice(key), None);
+    }
+
+    /// Create an iterator over a range of keys, as written by the transaction or as of when the
+    /// transaction began. The range the iterator covers, from `lower` up to its current key or up
+    /// to `upper` once it is exhausted, counts as read by the transaction, so that a key inserted
+    /// into it or updated by anyone else fails the commit. Writes of the transaction after the
+    /// iterator is created are not visible to it.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<TxnIterator<'_>> {
+        let range_idx = {
+            let mut scanned_ranges = self.scanned_ranges.lock();
+            scanned_ranges.push((map_bound(lower), map_bound(lower)));
+            scanned_ranges.len() - 1
+        };
+        let comparator = self.inner.options.comparator.clone();
+        let mut local: Vec<_> = self
+            .write_set
+            .lock()
+            .iter()
+            .filter(|(key, _)| range_contains(comparator.as_ref(), lower, upper, key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        // The write set is ordered byte-wise.
+        local.sort_by(|(a, _), (b, _)| comparator.compare(a, b));
+        let iter = TwoMergeIterator::create(
+            TxnLocalIterator {
+                entries: local,
+                idx: 0,
+                comparator: comparator.clone(),
+            },
+            self.inner
+                .scan_with_seq(&self.inner.default_cf, lower, upper, self.snapshot.seq())?,
+            comparator,
+        )?;
+        TxnIterator::new(self, iter, range_idx, map_bound(upper))
+    }
+
+    /// Apply the writes of the transaction atomically, or fail with
+    /// [`Error::TransactionConflict`] without writing anything if the transaction conflicts with
+    /// another write.
+    pub fn commit(self) -> Result<()> {
+        let Self {
+            inner,
+            snapshot,
+            write_set,
+            read_set,
+            scanned_ranges,
+        } = self;
+        let write_set = write_set.into_inner();
+        if write_set.is_empty() {
+            // A read-only transaction reads a consistent snapshot, so it never conflicts.
+            return Ok(());
+        }
+        let read_set = read_set.into_inner();
+        let scanned_ranges = scanned_ranges.into_inner();
+        let entries: Vec<(u32, &[u8], ValueType, &[u8])> = write_set
+            .iter()
+            .map(|(key, value)| {
+                (
+                    DEFAULT_COLUMN_FAMILY_ID,
+                    &key[..],
+                    ValueType::of(value),
+                    value.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect();
+        inner.write_to_memtables_checked(&entries, || {
+            for key in read_set.iter().chain(write_set.keys()) {
+                if let Some((seq, _)) = inner.get_version(&inner.default_cf, key, MAX_SEQ)? {
+                    if seq > snapshot.seq() {
+                        return Err(Error::TransactionConflict { key: key.clone() }.into());
+                    }
+                }
+            }
+            // The keys the scans yielded are in the read set, so a deleted or updated one is found
+            // above. A key inserted into a covered range has a put newer than the snapshot.
+            for (lower, upper) in &scanned_ranges {
+                if let Some(key) = inner.find_put_after(
+                    &inner.default_cf,
+                    as_slice(lower),
+                    as_slice(upper),
+                    snapshot.seq(),
+                )? {
+                    return Err(Error::TransactionConflict { key }.into());
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Iterates over the writes of a transaction in a range. The writes shadow every version in the
+/// storage, so they are yielded with the largest sequence number.
+struct TxnLocalIterator {
+    entries: Vec<(Bytes, Option<Bytes>)>,
+    /// The index of the current entry, which is not valid if it is out of `entries`.
+    idx: usize,
+    comparator: Arc<dyn Comparator>,
+}
+
+impl StorageIterator for TxnLocalIterator {
+    fn key(&self) -> &[u8] {
+        &self.entries[self.idx].0
+    }
+
+    fn seq(&self) -> u64 {
+        MAX_SEQ
+    }
+
+    fn value_type(&self) -> ValueType {
+        ValueType::of(&self.entries[self.idx].1)
+    }
+
+    fn value(&self) -> &[u8] {
+        self.entries[self.idx].1.as_deref().unwrap_or_default()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.idx < self.entries.len()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.idx += 1;
+        Ok(())
+    }
+}
+
+impl BidirectionalIterator for TxnLocalIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        self.idx = 0;
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        self.idx = self.entries.len().saturating_sub(1);
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        self.idx = self
+            .entries
+            .partition_point(|(x, _)| self.comparator.compare(x, key).is_lt());
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        let idx = self
+            .entries
+            .partition_point(|(x, _)| self.comparator.compare(x, key).is_le());
+        self.idx = idx.checked_sub(1).unwrap_or(self.entries.len());
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.idx = self.idx.checked_sub(1).unwrap_or(self.entries.len());
+        Ok(())
+    }
+}
+
+/// Iterates over the keys of a transaction, overlaying its writes on the storage as of when it
+/// began, and skipping deleted keys.
+pub struct TxnIterator<'a> {
+    txn: &'a Transaction,
+    iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+    /// The index of the range covered by the iterator in the scanned ranges of the transaction.
+    range_idx: usize,
+    /// The upper bound of the scan.
+    upper: Bound<Bytes>,
+}
+
+impl<'a> TxnIterator<'a> {
+    fn new(
+        txn: &'a Transaction,
+        iter: TwoMergeIterator<TxnLocalIterator, FusedIterator<LsmIterator>>,
+        range_idx: usize,
+        upper: Bound<Bytes>,
+    ) -> Result<Self> {
+        let mut iter = Self {
+            txn,
+            iter,
+            range_idx,
+            upper,
+        };
+        iter.move_to_visible_key()?;
+        Ok(iter)
+    }
+
+    /// Skip the entry of the storage for the current key, if it is overwritten by the
+    /// transaction.
+    fn skip_current_key(&mut self) -> Result<()> {
+        let key = self.iter.key().to_vec();
+        while self.iter.is_valid() && self.iter.key() == key {
+            self.iter.next()?;
+        }
+        Ok(())
+    }
+
+    /// Skip deleted keys, and extend the covered range up to the key the iterator stops at.
+    fn move_to_visible_key(&mut self) -> Result<()> {
+        while self.iter.is_valid() && self.iter.value_type() == ValueType::Delete {
+            self.skip_current_key()?;
+        }
+        let covered_upper = if self.iter.is_valid() {
+            let key = Bytes::copy_from_slice(self.iter.key());
+            self.txn.read_set.lock().insert(key.clone());
+            Bound::Included(key)
+        } else {
+            self.upper.clone()
+        };
+        self.txn.scanned_ranges.lock()[self.range_idx].1 = covered_upper;
+        Ok(())
+    }
+}
+
+impl StorageIterator for TxnIterator<'_> {
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn seq(&self) -> u64 {
+        self.iter.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        ValueType::Put
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.skip_current_key()?;
+        self.move_to_visible_key()
+    }
+}
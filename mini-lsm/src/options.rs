@@ -0,0 +1,283 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::compact::CompactionOptions;
+use crate::comparator::{BytewiseComparator, Comparator};
+use crate::error::Error;
+use crate::merge_operator::MergeOperator;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::table::CompressionType;
+use crate::wal::WalSyncPolicy;
+
+/// Options for opening an [`LsmStorage`].
+///
+/// The options are validated when the storage is opened, and persisted in its `OPTIONS` file.
+/// Reopening the storage with options that cannot read what was written under the persisted ones
+/// fails with [`Error::IncompatibleOptions`]; the other options may change between opens.
+///
+/// [`LsmStorage`]: crate::lsm_storage::LsmStorage
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LsmStorageOptions {
+    /// When to `fsync` the write-ahead log.
+    pub wal_sync_policy: WalSyncPolicy,
+    /// Target size of a data block of an SST, in bytes.
+    pub block_size: usize,
+    /// Target size of the SSTs produced by compaction, in bytes.
+    pub target_sst_size: usize,
+    /// Freeze the current memtable once it holds approximately this many bytes.
+    pub write_buffer_size: usize,
+    /// Maximum number of immutable memtables. Writes stall until the flush thread catches up
+    /// once there are this many.
+    pub max_imm_memtables: usize,
+    /// Capacity of the block cache shared by all column families, in bytes of decoded blocks.
+    pub block_cache_capacity: u64,
+    /// Number of bloom filter bits per key in each SST. Bloom filters are disabled if it is 0.
+    pub bloom_bits_per_key: usize,
+    /// Extracts the key prefixes for a prefix bloom filter in each SST, which lets
+    /// [`LsmStorage::scan_prefix`] skip the SSTs without keys of a prefix. It is not persisted, and
+    /// may change between opens: the filter of an SST is only used with an extractor of the name it
+    /// is built with.
+    ///
+    /// [`LsmStorage::scan_prefix`]: crate::lsm_storage::LsmStorage::scan_prefix
+    #[serde(skip)]
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// How data blocks of SSTs are compressed.
+    pub compression: CompressionType,
+    /// Values of at least this many bytes are written to blob files instead of SSTs, which store a
+    /// reference to them, so that compaction does not rewrite them. Values are always stored in
+    /// SSTs if it is `None`. It may change between opens.
+    #[serde(default)]
+    pub min_blob_size: Option<usize>,
+    /// A blob file is rewritten once the share of its bytes that SSTs still refer to drops below
+    /// this ratio, between 0 and 1. Blob files are only removed once no SST refers to them if it is
+    /// 0.
+    #[serde(default = "default_blob_gc_live_ratio")]
+    pub blob_gc_live_ratio: f64,
+    /// How SSTs are compacted, including the number of levels. The strategy and the number of
+    /// levels cannot change once the storage is created.
+    pub compaction_options: CompactionOptions,
+    /// Resolves the operands written by [`LsmStorage::merge`]. Merging fails without it. Only its
+    /// name is persisted, and once the storage is opened with one, it cannot change or be removed,
+    /// as compaction merges the operands written with it.
+    ///
+    /// [`LsmStorage::merge`]: crate::lsm_storage::LsmStorage::merge
+    #[serde(skip)]
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// Orders the keys of all column families. Only its name is persisted, and it cannot change
+    /// once the storage is created.
+    #[serde(skip, default = "default_comparator")]
+    pub comparator: Arc<dyn Comparator>,
+}
+
+fn default_comparator() -> Arc<dyn Comparator> {
+    Arc::new(BytewiseComparator)
+}
+
+fn default_blob_gc_live_ratio() -> f64 {
+    0.5
+}
+
+/// The contents of the `OPTIONS` file.
+#[derive(Serialize, Deserialize)]
+struct PersistedOptions {
+    /// The name of the comparator.
+    comparator: String,
+    /// The name of the merge operator, if any.
+    #[serde(default)]
+    merge_operator: Option<String>,
+    #[serde(flatten)]
+    options: LsmStorageOptions,
+}
+
+impl Default for LsmStorageOptions {
+    fn default() -> Self {
+        Self {
+            wal_sync_policy: WalSyncPolicy::default(),
+            block_size: 4096,
+            target_sst_size: 2 << 20,
+            write_buffer_size: 2 << 20,
+            max_imm_memtables: 4,
+            block_cache_capacity: 64 << 20,
+            bloom_bits_per_key: 10,
+            prefix_extractor: None,
+            compression: CompressionType::default(),
+            min_blob_size: None,
+            blob_gc_live_ratio: default_blob_gc_live_ratio(),
+            compaction_options: CompactionOptions::default(),
+            merge_operator: None,
+            comparator: default_comparator(),
+        }
+    }
+}
+
+fn invalid(option: &'static str, reason: &str) -> anyhow::Error {
+    Error::InvalidOptions {
+        option,
+        reason: reason.to_string(),
+    }
+    .into()
+}
+
+impl LsmStorageOptions {
+    /// Check that the options make sense on their own, failing with [`Error::InvalidOptions`] if
+    /// they do not.
+    pub fn validate(&self) -> Result<()> {
+        if self.block_size == 0 {
+            return Err(invalid("block_size", "must be positive"));
+        }
+        // Offsets within a block are stored as u32.
+        if self.block_size > u32::MAX as usize {
+            return Err(invalid("block_size", "must be at most 4294967295"));
+        }
+        if self.target_sst_size < self.block_size {
+            return Err(invalid("target_sst_size", "must be at least block_size"));
+        }
+        if self.write_buffer_size == 0 {
+            return Err(invalid("write_buffer_size", "must be positive"));
+        }
+        if self.max_imm_memtables == 0 {
+            return Err(invalid("max_imm_memtables", "must be positive"));
+        }
+        if self.min_blob_size == Some(0) {
+            return Err(invalid("min_blob_size", "must be positive"));
+        }
+        if !(0.0..=1.0).contains(&self.blob_gc_live_ratio) {
+            return Err(invalid("blob_gc_live_ratio", "must be between 0 and 1"));
+        }
+        if self.wal_sync_policy == WalSyncPolicy::Batch(0) {
+            return Err(invalid("wal_sync_policy", "batch size must be positive"));
+        }
+        match &self.compaction_options {
+            CompactionOptions::Leveled(options) => {
+                if options.max_levels == 0 {
+                    return Err(invalid("max_levels", "must be positive"));
+                }
+                if options.level0_file_num_compaction_trigger == 0 {
+                    return Err(invalid(
+                        "level0_file_num_compaction_trigger",
+                        "must be positive",
+                    ));
+                }
+                if options.level_size_multiplier < 2 {
+                    return Err(invalid("level_size_multiplier", "must be at least 2"));
+                }
+            }
+            CompactionOptions::Tiered(options) => {
+                if options.num_tiers < 2 {
+                    return Err(invalid("num_tiers", "must be at least 2"));
+                }
+                if options.min_merge_width < 2 {
+                    return Err(invalid("min_merge_width", "must be at least 2"));
+                }
+            }
+            CompactionOptions::NoCompaction => {}
+        }
+        Ok(())
+    }
+
+    /// Check that a storage created with `persisted` can be opened with these options, failing
+    /// with [`Error::IncompatibleOptions`] if it cannot. The data on disk is ordered by the
+    /// comparator, its merge operands are merged by the merge operator, and the structure of the
+    /// LSM tree in the manifest depends on the compaction strategy and the number of levels.
+    fn check_compatible(&self, persisted: &PersistedOptions) -> Result<()> {
+        let incompatible = |option, persisted: String, given: String| -> Result<()> {
+            Err(Error::IncompatibleOptions {
+                option,
+                persisted,
+                given,
+            }
+            .into())
+        };
+        if persisted.comparator != self.comparator.name() {
+            return incompatible(
+                "comparator",
+                persisted.comparator.clone(),
+                self.comparator.name().to_string(),
+            );
+        }
+        if let Some(merge_operator) = &persisted.merge_operator {
+            let given = self.merge_operator.as_ref().map(|x| x.name());
+            if given != Some(merge_operator.as_str()) {
+                return incompatible(
+                    "merge_operator",
+                    merge_operator.clone(),
+                    given.unwrap_or("none").to_string(),
+                );
+            }
+        }
+        match (
+            &persisted.options.compaction_options,
+            &self.compaction_options,
+        ) {
+            (CompactionOptions::Leveled(persisted), CompactionOptions::Leveled(given)) => {
+                if persisted.max_levels != given.max_levels {
+                    return incompatible(
+                        "max_levels",
+                        persisted.max_levels.to_string(),
+                        given.max_levels.to_string(),
+                    );
+                }
+            }
+            (CompactionOptions::Tiered(_), CompactionOptions::Tiered(_))
+            | (CompactionOptions::NoCompaction, CompactionOptions::NoCompaction) => {}
+            (persisted, given) => {
+                return incompatible(
+                    "compaction_options",
+                    persisted.strategy().to_string(),
+                    given.strategy().to_string(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate the options of the storage at `path`, check them against the persisted ones, if
+    /// any, and persist them.
+    pub(crate) fn validate_and_persist(&self, path: &Path) -> Result<()> {
+        self.validate()?;
+        let options_path = path.join("OPTIONS");
+        if options_path.exists() {
+            let persisted: serde_json::Value =
+                serde_json::from_slice(&std::fs::read(&options_path)?)
+                    .with_context(|| format!("failed to parse {}", options_path.display()))?;
+            // A codec whose feature is disabled in this build cannot be parsed, and neither can
+            // the blocks compressed with it.
+            if let Some(compression) = persisted.get("compression") {
+                if serde_json::from_value::<CompressionType>(compression.clone()).is_err() {
+                    return Err(Error::IncompatibleOptions {
+                        option: "compression",
+                        persisted: compression
+                            .as_str()
+                            .map_or_else(|| compression.to_string(), str::to_string),
+                        given: format!("{:?}", self.compression),
+                    }
+                    .into());
+                }
+            }
+            let persisted: PersistedOptions = serde_json::from_value(persisted)
+                .with_context(|| format!("failed to parse {}", options_path.display()))?;
+            self.check_compatible(&persisted)?;
+        }
+        let persisted = PersistedOptions {
+            comparator: self.comparator.name().to_string(),
+            merge_operator: self.merge_operator.as_ref().map(|x| x.name().to_string()),
+            options: self.clone(),
+        };
+        // Replace the file atomically, so that a crash leaves either the old or the new options.
+        let tmp_path = path.join("OPTIONS.tmp");
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(&serde_json::to_vec_pretty(&persisted)?)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &options_path)?;
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use tempfile::tempdir;
+
+use super::LsmStorageOptions;
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions};
+use crate::error::Error;
+use crate::lsm_storage::LsmStorage;
+use crate::tests::merge_tests::AppendOperator;
+
+fn leveled(max_levels: usize) -> LsmStorageOptions {
+    LsmStorageOptions {
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            max_levels,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_validate_options() {
+    assert!(LsmStorageOptions::default().validate().is_ok());
+    let options = LsmStorageOptions {
+        block_size: 0,
+        ..Default::default()
+    };
+    let err = options.validate().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::InvalidOptions {
+            option: "block_size",
+            ..
+        })
+    ));
+    let options = LsmStorageOptions {
+        target_sst_size: 1024,
+        ..Default::default()
+    };
+    assert!(options.validate().is_err());
+    assert!(leveled(0).validate().is_err());
+
+    // Invalid options are rejected before anything is written.
+    let dir = tempdir().unwrap();
+    assert!(LsmStorage::open(&dir, leveled(0)).is_err());
+    assert!(!dir.path().join("MANIFEST").exists());
+}
+
+#[test]
+fn test_reopen_with_incompatible_options() {
+    let dir = tempdir().unwrap();
+    LsmStorage::open(&dir, leveled(4)).unwrap().close().unwrap();
+
+    let err = LsmStorage::open(&dir, leveled(3)).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<Error>(),
+        Some(&Error::IncompatibleOptions {
+            option: "max_levels",
+            persisted: "4".to_string(),
+            given: "3".to_string(),
+        })
+    );
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Tiered(TieredCompactionOptions::default()),
+        ..Default::default()
+    };
+    let err = LsmStorage::open(&dir, options).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::IncompatibleOptions {
+            option: "compaction_options",
+            ..
+        })
+    ));
+
+    // The other options can change, and the new ones are persisted.
+    let options = LsmStorageOptions {
+        block_size: 1024,
+        block_cache_capacity: 1 << 20,
+        ..leveled(4)
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    storage.put(b"1", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+    let persisted: LsmStorageOptions =
+        serde_json::from_slice(&std::fs::read(dir.path().join("OPTIONS")).unwrap()).unwrap();
+    assert_eq!(persisted.block_size, 1024);
+}
+
+#[test]
+fn test_reopen_with_incompatible_merge_operator() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        merge_operator: Some(Arc::new(AppendOperator)),
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    storage.merge(b"1", b"1").unwrap();
+    storage.close().unwrap();
+    drop(storage);
+
+    let err = LsmStorage::open(&dir, LsmStorageOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<Error>(),
+        Some(&Error::IncompatibleOptions {
+            option: "merge_operator",
+            persisted: "append".to_string(),
+            given: "none".to_string(),
+        })
+    );
+    LsmStorage::open(&dir, options).unwrap().close().unwrap();
+}
+
+#[test]
+fn test_reopen_with_unknown_compression() {
+    let dir = tempdir().unwrap();
+    LsmStorage::open(&dir, LsmStorageOptions::default())
+        .unwrap()
+        .close()
+        .unwrap();
+
+    // As if the storage was created by a build with a codec that this one does not have.
+    let path = dir.path().join("OPTIONS");
+    let mut persisted: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    persisted["compression"] = "Zstd".into();
+    std::fs::write(&path, serde_json::to_vec(&persisted).unwrap()).unwrap();
+    let err = LsmStorage::open(&dir, LsmStorageOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<Error>(),
+        Some(&Error::IncompatibleOptions {
+            option: "compression",
+            persisted: "Zstd".to_string(),
+            given: "None".to_string(),
+        })
+    );
+}
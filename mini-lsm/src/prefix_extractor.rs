@@ -0,0 +1,78 @@
+use std::fmt;
+
+/// Extracts the prefix of a key, such as `tenant_id/` of `tenant_id/user_id`. Each SST has a
+/// bloom filter over the prefixes of its keys, which lets [`LsmStorage::scan_prefix`] skip the
+/// SSTs without keys of a prefix.
+///
+/// Its name is recorded with the filter of each SST, and the filter is only used with an extractor
+/// of the same name, so an extractor must extract the same prefixes as long as it keeps its name.
+///
+/// [`LsmStorage::scan_prefix`]: crate::lsm_storage::LsmStorage::scan_prefix
+pub trait PrefixExtractor: Send + Sync {
+    /// The name of the extractor.
+    fn name(&self) -> &str;
+
+    /// Extract the prefix of `key`, or `None` if the key has no prefix. The prefix of a key must
+    /// be its own prefix.
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]>;
+}
+
+impl fmt::Debug for dyn PrefixExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Extracts the first `len` bytes of a key. Shorter keys have no prefix.
+#[derive(Clone, Debug)]
+pub struct FixedPrefixExtractor {
+    len: usize,
+    name: String,
+}
+
+impl FixedPrefixExtractor {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            name: format!("fixed:{}", len),
+        }
+    }
+}
+
+impl PrefixExtractor for FixedPrefixExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        key.get(..self.len)
+    }
+}
+
+/// Extracts a key up to and including the first occurrence of a delimiter, such as `/`. Keys
+/// without the delimiter have no prefix.
+#[derive(Clone, Debug)]
+pub struct DelimitedPrefixExtractor {
+    delimiter: u8,
+    name: String,
+}
+
+impl DelimitedPrefixExtractor {
+    pub fn new(delimiter: u8) -> Self {
+        Self {
+            delimiter,
+            name: format!("delimited:{}", delimiter),
+        }
+    }
+}
+
+impl PrefixExtractor for DelimitedPrefixExtractor {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn prefix<'a>(&self, key: &'a [u8]) -> Option<&'a [u8]> {
+        let len = key.iter().position(|&b| b == self.delimiter)? + 1;
+        Some(&key[..len])
+    }
+}
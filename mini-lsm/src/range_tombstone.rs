@@ -0,0 +1,70 @@
+use bytes::{Buf, BufMut, Bytes};
+
+use crate::comparator::Comparator;
+
+/// A range tombstone deletes the versions of the keys in `start..end` written before it, that is,
+/// with a smaller sequence number.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RangeTombstone {
+    pub start: Bytes,
+    pub end: Bytes,
+    pub seq: u64,
+}
+
+impl RangeTombstone {
+    pub fn new(start: Bytes, end: Bytes, seq: u64) -> Self {
+        Self { start, end, seq }
+    }
+
+    /// Check if `key` is in the range of the tombstone, where keys are ordered by `comparator`.
+    pub fn contains(&self, comparator: &dyn Comparator, key: &[u8]) -> bool {
+        comparator.compare(&self.start, key).is_le() && comparator.compare(key, &self.end).is_lt()
+    }
+
+    /// Check if the version of `key` with sequence number `seq` is deleted by the tombstone.
+    pub fn covers(&self, comparator: &dyn Comparator, key: &[u8], seq: u64) -> bool {
+        seq < self.seq && self.contains(comparator, key)
+    }
+
+    /// Encode tombstones to a buffer, each as
+    /// `start_len (u32) | start | end_len (u32) | end | seq (u64)`.
+    pub fn encode(tombstones: &[RangeTombstone], buf: &mut Vec<u8>) {
+        for tombstone in tombstones {
+            buf.put_u32(tombstone.start.len() as u32);
+            buf.put_slice(&tombstone.start);
+            buf.put_u32(tombstone.end.len() as u32);
+            buf.put_slice(&tombstone.end);
+            buf.put_u64(tombstone.seq);
+        }
+    }
+
+    /// Decode tombstones from a buffer.
+    pub fn decode(mut buf: impl Buf) -> Vec<RangeTombstone> {
+        let mut tombstones = Vec::new();
+        while buf.has_remaining() {
+            let start_len = buf.get_u32() as usize;
+            let start = buf.copy_to_bytes(start_len);
+            let end_len = buf.get_u32() as usize;
+            let end = buf.copy_to_bytes(end_len);
+            let seq = buf.get_u64();
+            tombstones.push(RangeTombstone { start, end, seq });
+        }
+        tombstones
+    }
+}
+
+/// Get the sequence number of the newest tombstone that contains `key` and is visible at
+/// `read_seq`, or 0 if there is none.
+pub(crate) fn max_covering_seq<'a>(
+    comparator: &dyn Comparator,
+    tombstones: impl IntoIterator<Item = &'a RangeTombstone>,
+    key: &[u8],
+    read_seq: u64,
+) -> u64 {
+    tombstones
+        .into_iter()
+        .filter(|tombstone| tombstone.seq <= read_seq && tombstone.contains(comparator, key))
+        .map(|tombstone| tombstone.seq)
+        .max()
+        .unwrap_or(0)
+}
@@ -1,17 +1,81 @@
+pub(crate) mod bloom;
 mod builder;
+mod compression;
 mod iterator;
 
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
-pub use builder::SsTableBuilder;
+use bloom::{Bloom, PrefixBloom};
+pub use builder::{BlobFileOptions, SsTableBuilder, SsTableBuilderOptions};
 use bytes::{Buf, BufMut, Bytes};
+pub use compression::CompressionType;
 pub use iterator::SsTableIterator;
 
 use crate::block::Block;
+use crate::comparator::Comparator;
+use crate::error::Error;
 use crate::lsm_storage::BlockCache;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// Identifies an SST file. It is the last 8 bytes of the file.
+const SST_MAGIC: u64 = 0x6d69_6e69_6c73_6d00;
+
+/// The version of the format of the SSTs written. Version 1 has no blob references in the block
+/// meta section.
+const SST_FORMAT_VERSION: u32 = 2;
+
+/// The size of the footer: four offsets, the format version and the magic number.
+const FOOTER_SIZE: usize = SIZEOF_U64 * 4 + SIZEOF_U32 + SIZEOF_U64;
+
+/// Append the CRC32C of `data[start..]` to `data`.
+pub(crate) fn put_checksum(data: &mut Vec<u8>, start: usize) {
+    let checksum = crc32c::crc32c(&data[start..]);
+    data.put_u32(checksum);
+}
+
+/// Check the CRC32C at the end of `data`, and return the data before it if it matches.
+pub(crate) fn verify_checksum(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < SIZEOF_U32 {
+        return None;
+    }
+    let (payload, mut checksum) = data.split_at(data.len() - SIZEOF_U32);
+    (checksum.get_u32() == crc32c::crc32c(payload)).then_some(payload)
+}
+
+/// Encode the bytes of the blob file records an SST refers to, given as (blob file id, bytes), as
+/// `count (u32) | (blob file id (u64) | bytes (u64)) * count`.
+fn encode_blob_refs(blob_refs: &[(usize, u64)], buf: &mut Vec<u8>) {
+    buf.put_u32(blob_refs.len() as u32);
+    for (file_id, bytes) in blob_refs {
+        buf.put_u64(*file_id as u64);
+        buf.put_u64(*bytes);
+    }
+}
+
+/// Decode the blob references encoded by [`encode_blob_refs`], or return `None` if `buf` is too
+/// short.
+fn decode_blob_refs(buf: &mut &[u8]) -> Option<Vec<(usize, u64)>> {
+    if buf.remaining() < SIZEOF_U32 {
+        return None;
+    }
+    let count = buf.get_u32() as usize;
+    if buf.remaining() < count * SIZEOF_U64 * 2 {
+        return None;
+    }
+    Some(
+        (0..count)
+            .map(|_| (buf.get_u64() as usize, buf.get_u64()))
+            .collect(),
+    )
+}
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -19,6 +83,8 @@ pub struct BlockMeta {
     pub offset: usize,
     /// The first key of the data block.
     pub first_key: Bytes,
+    /// The last key of the data block.
+    pub last_key: Bytes,
 }
 
 impl BlockMeta {
@@ -26,16 +92,20 @@ impl BlockMeta {
     pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
         let mut estimated_size = 0;
         for meta in block_meta {
+            estimated_size += std::mem::size_of::<u64>();
             estimated_size += std::mem::size_of::<u32>();
-            estimated_size += std::mem::size_of::<u16>();
             estimated_size += meta.first_key.len();
+            estimated_size += std::mem::size_of::<u32>();
+            estimated_size += meta.last_key.len();
         }
         buf.reserve(estimated_size);
         let original_len = buf.len();
         for meta in block_meta {
-            buf.put_u32(meta.offset as u32);
-            buf.put_u16(meta.first_key.len() as u16);
+            buf.put_u64(meta.offset as u64);
+            buf.put_u32(meta.first_key.len() as u32);
             buf.put_slice(&meta.first_key);
+            buf.put_u32(meta.last_key.len() as u32);
+            buf.put_slice(&meta.last_key);
         }
         assert_eq!(estimated_size, buf.len() - original_len);
     }
@@ -44,10 +114,16 @@ impl BlockMeta {
     pub fn decode_block_meta(mut buf: impl Buf) -> Vec<BlockMeta> {
         let mut block_meta = Vec::new();
         while buf.has_remaining() {
-            let offset = buf.get_u32() as usize;
-            let first_key_len = buf.get_u16() as usize;
+            let offset = buf.get_u64() as usize;
+            let first_key_len = buf.get_u32() as usize;
             let first_key = buf.copy_to_bytes(first_key_len);
-            block_meta.push(BlockMeta { offset, first_key });
+            let last_key_len = buf.get_u32() as usize;
+            let last_key = buf.copy_to_bytes(last_key_len);
+            block_meta.push(BlockMeta {
+                offset,
+                first_key,
+                last_key,
+            });
         }
         block_meta
     }
@@ -91,85 +167,322 @@ impl FileObject {
         self.1
     }
 
-    /// Create a new file object (day 2) and write the file to the disk (day 4).
+    /// Create a new file object (day 2) and write the file to the disk (day 4). The file is
+    /// synced before returning, so that the WAL it replaces can be safely removed.
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        std::fs::write(path, &data)?;
+        let mut file = File::create(path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
         Ok(FileObject(
             File::options().read(true).write(false).open(path)?,
             data.len() as u64,
         ))
     }
 
-    pub fn open(_path: &Path) -> Result<Self> {
-        unimplemented!()
+    /// Open an existing file on the disk.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileObject(file, size))
     }
 }
 
+/// Get the key range of an SST, from its first key or range tombstone start to its last key or
+/// range tombstone end. The range tombstone ends are exclusive, so the range may be larger than
+/// needed.
+fn key_range(
+    block_metas: &[BlockMeta],
+    range_tombstones: &[RangeTombstone],
+    comparator: &dyn Comparator,
+) -> (Bytes, Bytes) {
+    let first_key = block_metas
+        .first()
+        .map(|meta| &meta.first_key)
+        .into_iter()
+        .chain(range_tombstones.iter().map(|tombstone| &tombstone.start))
+        .min_by(|a, b| comparator.compare(a, b))
+        .expect("empty SST");
+    let last_key = block_metas
+        .last()
+        .map(|meta| &meta.last_key)
+        .into_iter()
+        .chain(range_tombstones.iter().map(|tombstone| &tombstone.end))
+        .max_by(|a, b| comparator.compare(a, b))
+        .expect("empty SST");
+    (first_key.clone(), last_key.clone())
+}
+
+/// An SSTable, laid out as
+/// `data blocks | range tombstones | bloom filter | prefix bloom filter | block meta | footer`,
+/// where the footer is
+/// `range tombstones offset (u64) | bloom offset (u64) | prefix bloom offset (u64) |
+/// block meta offset (u64) | format version (u32) | magic (u64)`.
+/// The block meta section starts with the largest sequence number in the SST (u64), and the
+/// bytes of the blob file records the SST refers to in each blob file.
+/// The range tombstone and filter sections are empty if there is none. Each data block is
+/// stored as `block | compression type (u8)`, where the block may be compressed. Each data block,
+/// and each non-empty section after them, is followed by its CRC32C, which is verified when it
+/// is read. An SST may have no data blocks if it has range tombstones. Keys are ordered by the
+/// comparator the SST is built or opened with.
 pub struct SsTable {
     file: FileObject,
     block_metas: Vec<BlockMeta>,
-    block_meta_offset: usize,
+    /// Offset of the end of the data blocks.
+    data_end_offset: usize,
+    range_tombstones: Vec<RangeTombstone>,
+    bloom: Option<Bloom>,
+    prefix_bloom: Option<PrefixBloom>,
+    /// The largest sequence number in the SST.
+    max_seq: u64,
+    /// The bytes of the blob file records the SST refers to, as (blob file id, bytes).
+    blob_refs: Vec<(usize, u64)>,
     id: usize,
     block_cache: Option<Arc<BlockCache>>,
+    first_key: Bytes,
+    last_key: Bytes,
+    comparator: Arc<dyn Comparator>,
 }
 
 impl SsTable {
     #[cfg(test)]
     pub(crate) fn open_for_test(file: FileObject) -> Result<Self> {
-        Self::open(0, None, file)
+        Self::open(
+            0,
+            None,
+            file,
+            Arc::new(crate::comparator::BytewiseComparator),
+        )
     }
 
-    /// Open SSTable from a file.
-    pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
+    /// Open SSTable from a file, whose keys are ordered by `comparator`. Returns
+    /// [`Error::Corruption`] if the file does not end with the magic number, or the range
+    /// tombstones, a filter or the block meta fails its checksum, and
+    /// [`Error::UnsupportedFormat`] if it is written in an unknown version of the format.
+    pub fn open(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        comparator: Arc<dyn Comparator>,
+    ) -> Result<Self> {
+        let corruption = |offset| Error::Corruption {
+            file_id: id,
+            block_idx: None,
+            offset,
+        };
         let len = file.size();
-        let raw_meta_offset = file.read(len - 4, 4)?;
-        let block_meta_offset = (&raw_meta_offset[..]).get_u32() as u64;
-        let raw_meta = file.read(block_meta_offset, len - 4 - block_meta_offset)?;
+        let footer_offset = len
+            .checked_sub(FOOTER_SIZE as u64)
+            .ok_or_else(|| corruption(0))?;
+        let mut footer = &file.read(footer_offset, FOOTER_SIZE as u64)?[..];
+        if (&footer[FOOTER_SIZE - SIZEOF_U64..]).get_u64() != SST_MAGIC {
+            return Err(corruption(footer_offset).into());
+        }
+        let range_tombstones_offset = footer.get_u64();
+        let bloom_offset = footer.get_u64();
+        let prefix_bloom_offset = footer.get_u64();
+        let block_meta_offset = footer.get_u64();
+        let version = footer.get_u32();
+        if version == 0 || version > SST_FORMAT_VERSION {
+            return Err(Error::UnsupportedFormat {
+                file_id: id,
+                version,
+            }
+            .into());
+        }
+        if range_tombstones_offset > bloom_offset
+            || bloom_offset > prefix_bloom_offset
+            || prefix_bloom_offset > block_meta_offset
+            || block_meta_offset > footer_offset
+        {
+            return Err(corruption(footer_offset).into());
+        }
+
+        let raw_range_tombstones = file.read(
+            range_tombstones_offset,
+            bloom_offset - range_tombstones_offset,
+        )?;
+        let range_tombstones = if raw_range_tombstones.is_empty() {
+            Vec::new()
+        } else {
+            let raw_range_tombstones = verify_checksum(&raw_range_tombstones)
+                .filter(|tombstones| !tombstones.is_empty())
+                .ok_or_else(|| corruption(range_tombstones_offset))?;
+            RangeTombstone::decode(raw_range_tombstones)
+        };
+
+        let raw_bloom = file.read(bloom_offset, prefix_bloom_offset - bloom_offset)?;
+        let bloom = if raw_bloom.is_empty() {
+            None
+        } else {
+            let bloom = verify_checksum(&raw_bloom)
+                .and_then(|bloom| Bloom::decode(bloom).ok())
+                .ok_or_else(|| corruption(bloom_offset))?;
+            Some(bloom)
+        };
+
+        let raw_prefix_bloom =
+            file.read(prefix_bloom_offset, block_meta_offset - prefix_bloom_offset)?;
+        let prefix_bloom = if raw_prefix_bloom.is_empty() {
+            None
+        } else {
+            let prefix_bloom = verify_checksum(&raw_prefix_bloom)
+                .and_then(PrefixBloom::decode)
+                .ok_or_else(|| corruption(prefix_bloom_offset))?;
+            Some(prefix_bloom)
+        };
+
+        let raw_meta = file.read(block_meta_offset, footer_offset - block_meta_offset)?;
+        let mut raw_meta = verify_checksum(&raw_meta)
+            .filter(|meta| meta.len() >= std::mem::size_of::<u64>())
+            .ok_or_else(|| corruption(block_meta_offset))?;
+        let max_seq = raw_meta.get_u64();
+        let blob_refs = if version >= 2 {
+            decode_blob_refs(&mut raw_meta).ok_or_else(|| corruption(block_meta_offset))?
+        } else {
+            Vec::new()
+        };
+        let block_metas = BlockMeta::decode_block_meta(raw_meta);
+        if block_metas.is_empty() && range_tombstones.is_empty() {
+            return Err(corruption(block_meta_offset).into());
+        }
+        let (first_key, last_key) = key_range(&block_metas, &range_tombstones, comparator.as_ref());
         Ok(Self {
             file,
-            block_metas: BlockMeta::decode_block_meta(&raw_meta[..]),
-            block_meta_offset: block_meta_offset as usize,
+            first_key,
+            last_key,
+            block_metas,
+            data_end_offset: range_tombstones_offset as usize,
+            range_tombstones,
+            bloom,
+            prefix_bloom,
+            max_seq,
+            blob_refs,
             id,
             block_cache,
+            comparator,
         })
     }
 
-    /// Read a block from the disk.
+    /// Read a block from the disk and decompress it. Returns [`Error::Corruption`] if it fails its
+    /// checksum or cannot be decompressed.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let offset = self.block_metas[block_idx].offset;
         let offset_end = self
             .block_metas
             .get(block_idx + 1)
-            .map_or(self.block_meta_offset, |x| x.offset);
+            .map_or(self.data_end_offset, |x| x.offset);
         let block_data = self
             .file
             .read(offset as u64, (offset_end - offset) as u64)?;
-        Ok(Arc::new(Block::decode(&block_data[..])))
+        let corruption = Error::Corruption {
+            file_id: self.id,
+            block_idx: Some(block_idx),
+            offset: offset as u64,
+        };
+        let block_data = verify_checksum(&block_data)
+            .filter(|data| !data.is_empty())
+            .ok_or_else(|| corruption.clone())?;
+        let (block_data, compression_type) = block_data.split_at(block_data.len() - 1);
+        let block_data = CompressionType::from_u8(compression_type[0])?
+            .decompress(block_data)
+            .map_err(|_| corruption)?;
+        Ok(Arc::new(Block::decode(&block_data)))
     }
 
-    /// Read a block from disk, with block cache.
+    /// Read a block from disk, with block cache. The cache holds decompressed blocks.
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
         if let Some(ref block_cache) = self.block_cache {
             let blk = block_cache
                 .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| anyhow!("{}", e))?;
+                .map_err(|e| match e.downcast_ref::<Error>() {
+                    Some(e) => e.clone().into(),
+                    None => anyhow!("{}", e),
+                })?;
             Ok(blk)
         } else {
             self.read_block(block_idx)
         }
     }
 
-    /// Find the block that may contain `key`.
+    /// Find the block that may contain the newest version of `key`. Versions of a key may span
+    /// blocks, so a block starting with `key` may be preceded by newer versions of it.
     pub fn find_block_idx(&self, key: &[u8]) -> usize {
         self.block_metas
-            .partition_point(|meta| meta.first_key <= key)
+            .partition_point(|meta| self.comparator.compare(&meta.first_key, key).is_lt())
             .saturating_sub(1)
     }
 
+    /// Find the block that may contain the oldest version of the last key that <= `key`, or `None`
+    /// if every key of the SST is larger.
+    pub fn find_block_idx_for_prev(&self, key: &[u8]) -> Option<usize> {
+        self.block_metas
+            .partition_point(|meta| self.comparator.compare(&meta.first_key, key).is_le())
+            .checked_sub(1)
+    }
+
+    /// Check the bloom filter for `key`. Returns `false` only if the key is not in the SST.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom
+            .as_ref()
+            .map_or(true, |bloom| bloom.may_contain(Bloom::hash(key)))
+    }
+
+    /// Check the prefix bloom filter for `prefix`, extracted by `extractor`. Returns `false` only
+    /// if no key of the SST has the prefix. The filter is not used if it is built with another
+    /// extractor.
+    pub fn may_contain_prefix(&self, extractor: &dyn PrefixExtractor, prefix: &[u8]) -> bool {
+        match self.prefix_bloom {
+            Some(ref prefix_bloom) if prefix_bloom.extractor == extractor.name() => {
+                prefix_bloom.bloom.may_contain(Bloom::hash(prefix))
+            }
+            _ => true,
+        }
+    }
+
+    /// Get the comparator that orders the keys of the SST.
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.comparator
+    }
+
+    /// Get the range tombstones in the SST.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones
+    }
+
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
         self.block_metas.len()
     }
+
+    /// Get the smallest key in the SST, or the smallest start of its range tombstones.
+    pub fn first_key(&self) -> &Bytes {
+        &self.first_key
+    }
+
+    /// Get the largest key in the SST, or the largest end of its range tombstones.
+    pub fn last_key(&self) -> &Bytes {
+        &self.last_key
+    }
+
+    /// Get the size of the SST file in bytes.
+    pub fn table_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Get the largest sequence number in the SST.
+    pub fn max_seq(&self) -> u64 {
+        self.max_seq
+    }
+
+    /// Get the bytes of the blob file records the SST refers to, as (blob file id, bytes).
+    pub fn blob_refs(&self) -> &[(usize, u64)] {
+        &self.blob_refs
+    }
+
+    /// Get the id of the SST.
+    pub fn sst_id(&self) -> usize {
+        self.id
+    }
 }
 
 #[cfg(test)]
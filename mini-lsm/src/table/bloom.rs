@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut, Bytes};
+
+/// A bloom filter over the key hashes of an SST.
+///
+/// It is encoded as `filter bits | k (u8)`, where `k` is the number of probes per key.
+pub struct Bloom {
+    filter: Bytes,
+    k: u8,
+}
+
+impl Bloom {
+    /// Hash a key for building and probing the filter.
+    pub fn hash(key: &[u8]) -> u32 {
+        farmhash::fingerprint32(key)
+    }
+
+    /// Build a filter with `bits_per_key` bits for each of the key hashes.
+    pub fn build_from_key_hashes(keys: &[u32], bits_per_key: usize) -> Self {
+        // The false positive rate is the lowest with k = bits_per_key * ln(2).
+        let k = ((bits_per_key as f64 * 0.69) as u8).clamp(1, 30);
+        let nbits = (keys.len() * bits_per_key).max(64);
+        let nbytes = (nbits + 7) / 8;
+        let nbits = nbytes * 8;
+        let mut filter = vec![0; nbytes];
+        for &h in keys {
+            // Double hashing, derive the probes from one hash by rotating it.
+            let mut h = h;
+            let delta = h.rotate_left(15);
+            for _ in 0..k {
+                let bit = h as usize % nbits;
+                filter[bit / 8] |= 1 << (bit % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+        Self {
+            filter: filter.into(),
+            k,
+        }
+    }
+
+    /// Check if a key with hash `h` may be in the filter. A `false` answer is always correct.
+    pub fn may_contain(&self, h: u32) -> bool {
+        let nbits = self.filter.len() * 8;
+        let mut h = h;
+        let delta = h.rotate_left(15);
+        for _ in 0..self.k {
+            let bit = h as usize % nbits;
+            if self.filter[bit / 8] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Encode the filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_slice(&self.filter);
+        buf.put_u8(self.k);
+    }
+
+    /// Decode the filter from a buffer. Fails if it has no filter bits or no probes, which a
+    /// built filter always has.
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        if buf.len() < 2 || buf[buf.len() - 1] == 0 {
+            bail!("malformed bloom filter of {} bytes", buf.len());
+        }
+        let (filter, k) = buf.split_at(buf.len() - 1);
+        Ok(Self {
+            filter: Bytes::copy_from_slice(filter),
+            k: k[0],
+        })
+    }
+}
+
+/// A bloom filter over the key prefixes of an SST, with the name of the [`PrefixExtractor`] they
+/// are extracted by.
+///
+/// It is encoded as `name length (u16) | name | filter`.
+///
+/// [`PrefixExtractor`]: crate::prefix_extractor::PrefixExtractor
+pub struct PrefixBloom {
+    pub extractor: String,
+    pub bloom: Bloom,
+}
+
+impl PrefixBloom {
+    /// Encode the filter to a buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.put_u16(self.extractor.len() as u16);
+        buf.put_slice(self.extractor.as_bytes());
+        self.bloom.encode(buf);
+    }
+
+    /// Decode the filter from a buffer, or `None` if it is malformed.
+    pub fn decode(mut buf: &[u8]) -> Option<Self> {
+        if buf.len() < 2 {
+            return None;
+        }
+        let name_len = buf.get_u16() as usize;
+        if buf.len() < name_len {
+            return None;
+        }
+        let extractor = String::from_utf8(buf[..name_len].to_vec()).ok()?;
+        Some(Self {
+            extractor,
+            bloom: Bloom::decode(&buf[name_len..]).ok()?,
+        })
+    }
+}
@@ -1,49 +1,198 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::Result;
 use bytes::BufMut;
 
-use super::{BlockMeta, FileObject, SsTable};
+use super::{
+    encode_blob_refs, key_range, put_checksum, BlockMeta, Bloom, CompressionType, FileObject,
+    PrefixBloom, SsTable, SST_FORMAT_VERSION, SST_MAGIC,
+};
+use crate::blob::{encode_record, BlobRef};
 use crate::block::BlockBuilder;
+use crate::comparator::{BytewiseComparator, Comparator};
 use crate::lsm_storage::BlockCache;
+use crate::mvcc::ValueType;
+use crate::prefix_extractor::PrefixExtractor;
+use crate::range_tombstone::RangeTombstone;
+
+/// Options for building an SSTable.
+#[derive(Clone, Debug)]
+pub struct SsTableBuilderOptions {
+    /// Target size of a data block in bytes.
+    pub block_size: usize,
+    /// Number of bloom filter bits per key, or per prefix in the prefix bloom filter. No bloom
+    /// filter is built if it is 0.
+    pub bloom_bits_per_key: usize,
+    /// Extracts the key prefixes for the prefix bloom filter. No prefix bloom filter is built
+    /// without it.
+    pub prefix_extractor: Option<Arc<dyn PrefixExtractor>>,
+    /// How data blocks are compressed.
+    pub compression: CompressionType,
+    /// The order of the keys added to the SSTable.
+    pub comparator: Arc<dyn Comparator>,
+    /// Where large values are written. All values are stored in the SSTable without it.
+    pub blob_file: Option<BlobFileOptions>,
+}
+
+/// Options for writing the large values added to an SSTable to a blob file, which the SSTable
+/// refers to them in.
+#[derive(Clone)]
+pub struct BlobFileOptions {
+    /// Values of at least this many bytes are written to the blob file.
+    pub min_blob_size: usize,
+    /// Allocates the id and the path of the blob file. It is only called, and the blob file only
+    /// created, if a value is written to it.
+    pub new_blob_file: Arc<dyn Fn() -> (usize, PathBuf) + Send + Sync>,
+}
+
+impl fmt::Debug for BlobFileOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlobFileOptions")
+            .field("min_blob_size", &self.min_blob_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for SsTableBuilderOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 4096,
+            bloom_bits_per_key: 10,
+            prefix_extractor: None,
+            compression: CompressionType::default(),
+            comparator: Arc::new(BytewiseComparator),
+            blob_file: None,
+        }
+    }
+}
 
 /// Builds an SSTable from key-value pairs.
 pub struct SsTableBuilder {
     builder: BlockBuilder,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
     data: Vec<u8>,
     pub(super) meta: Vec<BlockMeta>,
-    block_size: usize,
+    key_hashes: Vec<u32>,
+    prefix_hashes: Vec<u32>,
+    range_tombstones: Vec<RangeTombstone>,
+    /// The largest sequence number added.
+    max_seq: u64,
+    /// The id and the path of the blob file, allocated when the first value is written to it.
+    blob_file: Option<(usize, PathBuf)>,
+    /// The records of the blob file.
+    blob_data: Vec<u8>,
+    /// The bytes of the blob file records referred to, by blob file id.
+    blob_refs: BTreeMap<usize, u64>,
+    options: SsTableBuilderOptions,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::with_options(SsTableBuilderOptions {
+            block_size,
+            ..Default::default()
+        })
+    }
+
+    /// Create a builder with the given options.
+    pub fn with_options(options: SsTableBuilderOptions) -> Self {
         Self {
             data: Vec::new(),
             meta: Vec::new(),
             first_key: Vec::new(),
-            block_size,
-            builder: BlockBuilder::new(block_size),
+            last_key: Vec::new(),
+            key_hashes: Vec::new(),
+            prefix_hashes: Vec::new(),
+            range_tombstones: Vec::new(),
+            max_seq: 0,
+            blob_file: None,
+            blob_data: Vec::new(),
+            blob_refs: BTreeMap::new(),
+            builder: BlockBuilder::new(options.block_size),
+            options,
         }
     }
 
-    /// Adds a key-value pair to SSTable
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+    /// Adds an entry written with sequence number `seq` to SSTable. Entries must be added by key,
+    /// and then from the newest version of the key to the oldest.
+    ///
+    /// A put of a value of at least [`BlobFileOptions::min_blob_size`] bytes is written to the blob
+    /// file, and the SSTable stores a [`ValueType::Blob`] reference to it instead. Blob references
+    /// are stored as they are.
+    pub fn add(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) {
+        match self.options.blob_file {
+            Some(ref blob_file)
+                if value_type == ValueType::Put && value.len() >= blob_file.min_blob_size =>
+            {
+                let (file_id, _) = self
+                    .blob_file
+                    .get_or_insert_with(|| (blob_file.new_blob_file)());
+                let blob_ref = BlobRef {
+                    file_id: *file_id,
+                    offset: self.blob_data.len() as u64,
+                    len: encode_record(&mut self.blob_data, key, value),
+                };
+                self.add_entry(key, seq, ValueType::Blob, &blob_ref.encode());
+            }
+            _ => self.add_entry(key, seq, value_type, value),
+        }
+    }
+
+    fn add_entry(&mut self, key: &[u8], seq: u64, value_type: ValueType, value: &[u8]) {
+        if value_type == ValueType::Blob {
+            let blob_ref = BlobRef::decode(value).expect("invalid blob reference");
+            *self.blob_refs.entry(blob_ref.file_id).or_default() += blob_ref.len as u64;
+        }
+        if self.options.bloom_bits_per_key > 0 {
+            let hash = Bloom::hash(key);
+            // Versions of the same key are adjacent.
+            if self.key_hashes.last() != Some(&hash) {
+                self.key_hashes.push(hash);
+            }
+            if let Some(prefix) = self
+                .options
+                .prefix_extractor
+                .as_ref()
+                .and_then(|extractor| extractor.prefix(key))
+            {
+                let hash = Bloom::hash(prefix);
+                if self.prefix_hashes.last() != Some(&hash) {
+                    self.prefix_hashes.push(hash);
+                }
+            }
+        }
+        self.max_seq = self.max_seq.max(seq);
         if self.first_key.is_empty() {
             self.first_key = key.to_vec();
         }
 
-        if self.builder.add(key, value) {
+        if self.builder.add(key, seq, value_type, value) {
+            self.last_key = key.to_vec();
             return;
         }
         // create a new block builder and append block data
         self.finish_block();
 
         // add the key-value pair to the next block
-        assert!(self.builder.add(key, value));
+        assert!(self.builder.add(key, seq, value_type, value));
         self.first_key = key.to_vec();
+        self.last_key = key.to_vec();
+    }
+
+    /// Adds a range tombstone to SSTable.
+    pub fn add_range_tombstone(&mut self, tombstone: RangeTombstone) {
+        self.max_seq = self.max_seq.max(tombstone.seq);
+        self.range_tombstones.push(tombstone);
+    }
+
+    /// Check if no key-value pair or range tombstone has been added to the SSTable.
+    pub fn is_empty(&self) -> bool {
+        self.meta.is_empty() && self.builder.is_empty() && self.range_tombstones.is_empty()
     }
 
     /// Get the estimated size of the SSTable.
@@ -51,36 +200,112 @@ impl SsTableBuilder {
         self.data.len()
     }
 
+    /// Compress the current block and append it to the data. A block that does not get smaller
+    /// is stored uncompressed.
     fn finish_block(&mut self) {
-        let builder = std::mem::replace(&mut self.builder, BlockBuilder::new(self.block_size));
+        let builder = std::mem::replace(
+            &mut self.builder,
+            BlockBuilder::new(self.options.block_size),
+        );
         let encoded_block = builder.build().encode();
         self.meta.push(BlockMeta {
             offset: self.data.len(),
             first_key: std::mem::take(&mut self.first_key).into(),
+            last_key: std::mem::take(&mut self.last_key).into(),
         });
-        self.data.extend(encoded_block);
+        let block_offset = self.data.len();
+        match self.options.compression.compress(&encoded_block) {
+            Some(compressed_block) => {
+                self.data.extend(compressed_block);
+                self.data.put_u8(self.options.compression.to_u8());
+            }
+            None => {
+                self.data.extend(encoded_block);
+                self.data.put_u8(CompressionType::None.to_u8());
+            }
+        }
+        put_checksum(&mut self.data, block_offset);
     }
 
     /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
     /// chapter 4 block cache.
+    ///
+    /// The blob file, if any value is written to it, is written before the SSTable, so that the
+    /// SSTable never refers to a missing one.
     pub fn build(
         mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        self.finish_block();
+        if !self.builder.is_empty() {
+            self.finish_block();
+        }
+        if let Some((_, ref path)) = self.blob_file {
+            let blob_data = std::mem::take(&mut self.blob_data);
+            FileObject::create(path, blob_data)?;
+        }
         let mut buf = self.data;
+        let range_tombstones_offset = buf.len();
+        if !self.range_tombstones.is_empty() {
+            RangeTombstone::encode(&self.range_tombstones, &mut buf);
+            put_checksum(&mut buf, range_tombstones_offset);
+        }
+        let bloom_offset = buf.len();
+        let bloom = (self.options.bloom_bits_per_key > 0).then(|| {
+            Bloom::build_from_key_hashes(&self.key_hashes, self.options.bloom_bits_per_key)
+        });
+        if let Some(ref bloom) = bloom {
+            bloom.encode(&mut buf);
+            put_checksum(&mut buf, bloom_offset);
+        }
+        let prefix_bloom_offset = buf.len();
+        let prefix_bloom = match self.options.prefix_extractor {
+            Some(ref extractor) if self.options.bloom_bits_per_key > 0 => Some(PrefixBloom {
+                extractor: extractor.name().to_string(),
+                bloom: Bloom::build_from_key_hashes(
+                    &self.prefix_hashes,
+                    self.options.bloom_bits_per_key,
+                ),
+            }),
+            _ => None,
+        };
+        if let Some(ref prefix_bloom) = prefix_bloom {
+            prefix_bloom.encode(&mut buf);
+            put_checksum(&mut buf, prefix_bloom_offset);
+        }
         let meta_offset = buf.len();
+        buf.put_u64(self.max_seq);
+        let blob_refs: Vec<(usize, u64)> = self.blob_refs.into_iter().collect();
+        encode_blob_refs(&blob_refs, &mut buf);
         BlockMeta::encode_block_meta(&self.meta, &mut buf);
-        buf.put_u32(meta_offset as u32);
+        put_checksum(&mut buf, meta_offset);
+        buf.put_u64(range_tombstones_offset as u64);
+        buf.put_u64(bloom_offset as u64);
+        buf.put_u64(prefix_bloom_offset as u64);
+        buf.put_u64(meta_offset as u64);
+        buf.put_u32(SST_FORMAT_VERSION);
+        buf.put_u64(SST_MAGIC);
         let file = FileObject::create(path.as_ref(), buf)?;
+        let (first_key, last_key) = key_range(
+            &self.meta,
+            &self.range_tombstones,
+            self.options.comparator.as_ref(),
+        );
         Ok(SsTable {
             id,
             file,
+            first_key,
+            last_key,
             block_metas: self.meta,
-            block_meta_offset: meta_offset,
+            data_end_offset: range_tombstones_offset,
+            range_tombstones: self.range_tombstones,
+            bloom,
+            prefix_bloom,
+            max_seq: self.max_seq,
+            blob_refs,
             block_cache,
+            comparator: self.options.comparator,
         })
     }
 
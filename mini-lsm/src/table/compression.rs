@@ -0,0 +1,67 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// How a data block is compressed on disk. Codecs other than `None` are behind cargo features.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CompressionType {
+    #[default]
+    None,
+    /// LZ4, with the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Snappy, with the `snappy` feature.
+    #[cfg(feature = "snappy")]
+    Snappy,
+}
+
+impl CompressionType {
+    /// The tag stored after each block.
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::None => 0,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => 1,
+            #[cfg(feature = "snappy")]
+            Self::Snappy => 2,
+        }
+    }
+
+    /// Parse the tag stored after a block. Fails if the codec is unknown or is not enabled.
+    pub fn from_u8(tag: u8) -> Result<Self> {
+        match tag {
+            0 => Ok(Self::None),
+            #[cfg(feature = "lz4")]
+            1 => Ok(Self::Lz4),
+            #[cfg(not(feature = "lz4"))]
+            1 => bail!("block is compressed with LZ4, but the `lz4` feature is not enabled"),
+            #[cfg(feature = "snappy")]
+            2 => Ok(Self::Snappy),
+            #[cfg(not(feature = "snappy"))]
+            2 => bail!("block is compressed with Snappy, but the `snappy` feature is not enabled"),
+            _ => bail!("unknown compression type {}", tag),
+        }
+    }
+
+    /// Compress `data`. Returns `None` if it is not compressed, or does not get smaller.
+    pub fn compress(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::None => None,
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Some(lz4_flex::compress_prepend_size(data)),
+            #[cfg(feature = "snappy")]
+            Self::Snappy => snap::raw::Encoder::new().compress_vec(data).ok(),
+        }
+        .filter(|compressed: &Vec<u8>| compressed.len() < data.len())
+    }
+
+    /// Decompress `data` compressed by [`CompressionType::compress`].
+    pub fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Ok(lz4_flex::decompress_size_prepended(data)?),
+            #[cfg(feature = "snappy")]
+            Self::Snappy => Ok(snap::raw::Decoder::new().decompress_vec(data)?),
+        }
+    }
+}
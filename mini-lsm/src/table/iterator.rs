@@ -4,72 +4,62 @@ use anyhow::Result;
 
 use super::SsTable;
 use crate::block::BlockIterator;
-use crate::iterators::StorageIterator;
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::mvcc::ValueType;
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
+    /// Iterates on the block at `blk_idx`, or on no block if the SST has no data blocks.
     blk_iter: BlockIterator,
     blk_idx: usize,
 }
 
 impl SsTableIterator {
-    fn seek_to_first_inner(table: &Arc<SsTable>) -> Result<(usize, BlockIterator)> {
-        Ok((
-            0,
-            BlockIterator::create_and_seek_to_first(table.read_block_cached(0)?),
-        ))
-    }
-
-    /// Create a new iterator and seek to the first key-value pair.
-    pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&table)?;
-        let iter = Self {
-            blk_iter,
+    /// Create an iterator holding the block at `blk_idx`, at its first entry.
+    fn new(table: Arc<SsTable>, blk_idx: usize) -> Result<Self> {
+        let blk_iter = if table.num_of_blocks() == 0 {
+            BlockIterator::empty()
+        } else {
+            BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?)
+        };
+        Ok(Self {
             table,
+            blk_iter,
             blk_idx,
-        };
-        Ok(iter)
+        })
     }
 
-    /// Seek to the first key-value pair.
-    pub fn seek_to_first(&mut self) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_first_inner(&self.table)?;
-        self.blk_idx = blk_idx;
-        self.blk_iter = blk_iter;
-        Ok(())
+    /// Create a new iterator and seek to the first key-value pair.
+    pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+        Self::new(table, 0)
     }
 
-    fn seek_to_key_inner(table: &Arc<SsTable>, key: &[u8]) -> Result<(usize, BlockIterator)> {
-        let mut blk_idx = table.find_block_idx(key);
-        let mut blk_iter =
-            BlockIterator::create_and_seek_to_key(table.read_block_cached(blk_idx)?, key);
-        if !blk_iter.is_valid() {
-            blk_idx += 1;
-            if blk_idx < table.num_of_blocks() {
-                blk_iter =
-                    BlockIterator::create_and_seek_to_first(table.read_block_cached(blk_idx)?);
-            }
-        }
-        Ok((blk_idx, blk_iter))
+    /// Create a new iterator and seek to the last key-value pair.
+    pub fn create_and_seek_to_last(table: Arc<SsTable>) -> Result<Self> {
+        let blk_idx = table.num_of_blocks().saturating_sub(1);
+        let mut iter = Self::new(table, blk_idx)?;
+        iter.seek_to_last()?;
+        Ok(iter)
     }
 
-    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    /// Create a new iterator and seek to the newest version of the first key which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: &[u8]) -> Result<Self> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&table, key)?;
-        let iter = Self {
-            blk_iter,
-            table,
-            blk_idx,
-        };
+        let blk_idx = table.find_block_idx(key);
+        let mut iter = Self::new(table, blk_idx)?;
+        iter.seek(key)?;
         Ok(iter)
     }
 
-    /// Seek to the first key-value pair which >= `key`.
-    pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
-        let (blk_idx, blk_iter) = Self::seek_to_key_inner(&self.table, key)?;
-        self.blk_iter = blk_iter;
-        self.blk_idx = blk_idx;
+    /// Make `blk_iter` hold the block at `blk_idx`. The block it holds is kept if it is the same
+    /// one, so seeking within the current block does not read it again. The position of `blk_iter`
+    /// is unspecified afterwards.
+    fn load_block(&mut self, blk_idx: usize) -> Result<()> {
+        if blk_idx != self.blk_idx {
+            self.blk_iter =
+                BlockIterator::create_and_seek_to_first(self.table.read_block_cached(blk_idx)?);
+            self.blk_idx = blk_idx;
+        }
         Ok(())
     }
 }
@@ -83,19 +73,81 @@ impl StorageIterator for SsTableIterator {
         self.blk_iter.key()
     }
 
+    fn seq(&self) -> u64 {
+        self.blk_iter.seq()
+    }
+
+    fn value_type(&self) -> ValueType {
+        self.blk_iter.value_type()
+    }
+
     fn is_valid(&self) -> bool {
         self.blk_iter.is_valid()
     }
 
     fn next(&mut self) -> Result<()> {
         self.blk_iter.next();
-        if !self.blk_iter.is_valid() {
-            self.blk_idx += 1;
-            if self.blk_idx < self.table.num_of_blocks() {
-                self.blk_iter = BlockIterator::create_and_seek_to_first(
-                    self.table.read_block_cached(self.blk_idx)?,
-                );
-            }
+        if !self.blk_iter.is_valid() && self.blk_idx + 1 < self.table.num_of_blocks() {
+            self.load_block(self.blk_idx + 1)?;
+            self.blk_iter.seek_to_first();
+        }
+        Ok(())
+    }
+}
+
+impl BidirectionalIterator for SsTableIterator {
+    fn seek_to_first(&mut self) -> Result<()> {
+        if self.table.num_of_blocks() == 0 {
+            return Ok(());
+        }
+        self.load_block(0)?;
+        self.blk_iter.seek_to_first();
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> Result<()> {
+        if self.table.num_of_blocks() == 0 {
+            return Ok(());
+        }
+        self.load_block(self.table.num_of_blocks() - 1)?;
+        self.blk_iter.seek_to_last();
+        Ok(())
+    }
+
+    fn seek(&mut self, key: &[u8]) -> Result<()> {
+        if self.table.num_of_blocks() == 0 {
+            return Ok(());
+        }
+        let blk_idx = self.table.find_block_idx(key);
+        self.load_block(blk_idx)?;
+        self.blk_iter
+            .seek_to_key(key, self.table.comparator().as_ref());
+        if !self.blk_iter.is_valid() && blk_idx + 1 < self.table.num_of_blocks() {
+            self.load_block(blk_idx + 1)?;
+            self.blk_iter.seek_to_first();
+        }
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        if self.table.num_of_blocks() == 0 {
+            return Ok(());
+        }
+        // If every key of the SST is larger than `key`, so is every key of the current block, and
+        // seeking in it makes the iterator invalid.
+        if let Some(blk_idx) = self.table.find_block_idx_for_prev(key) {
+            self.load_block(blk_idx)?;
+        }
+        self.blk_iter
+            .seek_for_prev(key, self.table.comparator().as_ref());
+        Ok(())
+    }
+
+    fn prev(&mut self) -> Result<()> {
+        self.blk_iter.prev();
+        if !self.blk_iter.is_valid() && self.blk_idx > 0 {
+            self.load_block(self.blk_idx - 1)?;
+            self.blk_iter.seek_to_last();
         }
         Ok(())
     }
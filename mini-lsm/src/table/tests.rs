@@ -4,13 +4,17 @@ use bytes::Bytes;
 use tempfile::{tempdir, TempDir};
 
 use super::*;
-use crate::iterators::StorageIterator;
+use crate::blob::{BlobFile, BlobRef};
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::mvcc::ValueType;
+use crate::prefix_extractor::{DelimitedPrefixExtractor, FixedPrefixExtractor};
+use crate::range_tombstone::RangeTombstone;
 use crate::table::SsTableBuilder;
 
 #[test]
 fn test_sst_build_single_key() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"233", b"233333");
+    builder.add(b"233", 1, ValueType::Put, b"233333");
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -18,12 +22,12 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_blocks() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"11", b"11");
-    builder.add(b"22", b"22");
-    builder.add(b"33", b"11");
-    builder.add(b"44", b"22");
-    builder.add(b"55", b"11");
-    builder.add(b"66", b"22");
+    builder.add(b"11", 2, ValueType::Put, b"11");
+    builder.add(b"22", 3, ValueType::Put, b"22");
+    builder.add(b"33", 4, ValueType::Put, b"11");
+    builder.add(b"44", 5, ValueType::Put, b"22");
+    builder.add(b"55", 6, ValueType::Put, b"11");
+    builder.add(b"66", 7, ValueType::Put, b"22");
     assert!(builder.meta.len() >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
@@ -46,7 +50,7 @@ fn generate_sst() -> (TempDir, SsTable) {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(&key[..], &value[..]);
+        builder.add(&key[..], idx as u64, ValueType::Put, &value[..]);
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -122,9 +126,432 @@ fn test_sst_seek_key() {
                 as_bytes(&value_of(i)),
                 as_bytes(value)
             );
-            iter.seek_to_key(&format!("key_{:03}", i * 5 + offset).into_bytes())
+            iter.seek(&format!("key_{:03}", i * 5 + offset).into_bytes())
                 .unwrap();
         }
-        iter.seek_to_key(b"k").unwrap();
+        iter.seek(b"k").unwrap();
     }
 }
+
+#[test]
+fn test_sst_bloom_filter() {
+    let (_dir, sst) = generate_sst();
+    let sst = SsTable::open_for_test(sst.file).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(sst.may_contain(&key_of(idx)));
+    }
+    // Keys between the keys of the SST are absent, most of them should be filtered out.
+    let false_positives = (0..num_of_keys())
+        .filter(|idx| sst.may_contain(format!("key_{:03}", idx * 5 + 1).as_bytes()))
+        .count();
+    assert!(false_positives < num_of_keys() / 10);
+}
+
+#[test]
+fn test_sst_prefix_bloom_filter() {
+    let extractor = DelimitedPrefixExtractor::new(b'/');
+    let mut builder = SsTableBuilder::with_options(SsTableBuilderOptions {
+        block_size: 128,
+        prefix_extractor: Some(Arc::new(extractor.clone())),
+        ..Default::default()
+    });
+    for tenant in 0..50 {
+        for idx in 0..5 {
+            let key = format!("tenant_{:03}/key_{}", tenant * 2, idx);
+            builder.add(key.as_bytes(), 1, ValueType::Put, b"value");
+        }
+    }
+    // Keys without a prefix are not in the filter.
+    builder.add(b"zzz", 1, ValueType::Put, b"value");
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+
+    for tenant in 0..50 {
+        let prefix = format!("tenant_{:03}/", tenant * 2);
+        assert!(sst.may_contain_prefix(&extractor, prefix.as_bytes()));
+    }
+    // Tenants between the tenants of the SST are absent, most of them should be filtered out.
+    let false_positives = (0..50)
+        .filter(|tenant| {
+            let prefix = format!("tenant_{:03}/", tenant * 2 + 1);
+            sst.may_contain_prefix(&extractor, prefix.as_bytes())
+        })
+        .count();
+    assert!(false_positives < 5);
+    // The filter is not used with another extractor.
+    assert!(sst.may_contain_prefix(&FixedPrefixExtractor::new(8), b"tenant_0"));
+}
+
+#[test]
+fn test_sst_without_bloom_filter() {
+    let mut builder = SsTableBuilder::with_options(SsTableBuilderOptions {
+        block_size: 128,
+        bloom_bits_per_key: 0,
+        ..Default::default()
+    });
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), idx as u64, ValueType::Put, &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert!(sst.may_contain(b"key_001"));
+    let mut iter = SsTableIterator::create_and_seek_to_key(Arc::new(sst), &key_of(10)).unwrap();
+    assert_eq!(iter.key(), key_of(10));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(11));
+}
+
+/// Flip a bit of the file at `path` at `offset`.
+fn corrupt_file(path: &std::path::Path, offset: usize) {
+    let mut data = std::fs::read(path).unwrap();
+    data[offset] ^= 1;
+    std::fs::write(path, data).unwrap();
+}
+
+#[test]
+fn test_sst_corrupted_block() {
+    let (dir, sst) = generate_sst();
+    let block_offset = sst.block_metas[1].offset;
+    drop(sst);
+    let path = dir.path().join("1.sst");
+    corrupt_file(&path, block_offset + 1);
+    let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.read_block(0).is_ok());
+    let err = sst.read_block(1).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(&crate::error::Error::Corruption {
+            file_id: 0,
+            block_idx: Some(1),
+            offset: block_offset as u64,
+        })
+    );
+
+    // The error is surfaced when the iterator reaches the block.
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let result = loop {
+        if let Err(e) = iter.next() {
+            break e;
+        }
+        assert!(iter.is_valid());
+    };
+    assert!(result.downcast_ref::<crate::error::Error>().is_some());
+}
+
+#[test]
+fn test_sst_corrupted_meta() {
+    let (dir, sst) = generate_sst();
+    let file_size = sst.table_size() as usize;
+    drop(sst);
+    let path = dir.path().join("1.sst");
+    // The byte before the footer belongs to the checksum of the block meta.
+    corrupt_file(&path, file_size - FOOTER_SIZE - 1);
+    let err = SsTable::open_for_test(FileObject::open(&path).unwrap())
+        .err()
+        .unwrap();
+    assert!(matches!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(crate::error::Error::Corruption {
+            block_idx: None,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sst_malformed_bloom_filter() {
+    assert!(Bloom::decode(&[]).is_err());
+    assert!(Bloom::decode(&[6]).is_err());
+    assert!(Bloom::decode(&[0xff, 0]).is_err());
+    assert!(Bloom::decode(&[0xff, 6]).is_ok());
+
+    // A filter without probes fails to open even if it passes its checksum.
+    let (dir, sst) = generate_sst();
+    let file_size = sst.table_size() as usize;
+    drop(sst);
+    let path = dir.path().join("1.sst");
+    let mut data = std::fs::read(&path).unwrap();
+    let offset_of = |idx: usize| {
+        let start = file_size - FOOTER_SIZE + idx * SIZEOF_U64;
+        (&data[start..start + SIZEOF_U64]).get_u64() as usize
+    };
+    let (bloom_offset, checksum_offset) = (offset_of(1), offset_of(2) - SIZEOF_U32);
+    data[checksum_offset - 1] = 0;
+    let checksum = crc32c::crc32c(&data[bloom_offset..checksum_offset]);
+    data[checksum_offset..checksum_offset + SIZEOF_U32].copy_from_slice(&checksum.to_be_bytes());
+    std::fs::write(&path, data).unwrap();
+    let err = SsTable::open_for_test(FileObject::open(&path).unwrap())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(&crate::error::Error::Corruption {
+            file_id: 0,
+            block_idx: None,
+            offset: bloom_offset as u64,
+        })
+    );
+}
+
+#[test]
+fn test_sst_footer() {
+    let (dir, sst) = generate_sst();
+    let file_size = sst.table_size() as usize;
+    drop(sst);
+    let path = dir.path().join("1.sst");
+    let data = std::fs::read(&path).unwrap();
+    let open = |data: &[u8]| {
+        std::fs::write(&path, data).unwrap();
+        SsTable::open_for_test(FileObject::open(&path).unwrap())
+    };
+
+    // Offsets beyond 4 GiB are kept.
+    let metas = vec![BlockMeta {
+        offset: 5 << 30,
+        first_key: Bytes::from("a"),
+        last_key: Bytes::from("b"),
+    }];
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    assert_eq!(BlockMeta::decode_block_meta(&buf[..]), metas);
+
+    // A file without the magic number is not an SST.
+    let mut corrupted = data.clone();
+    corrupted[file_size - 1] ^= 1;
+    let err = open(&corrupted).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(&crate::error::Error::Corruption {
+            file_id: 0,
+            block_idx: None,
+            offset: (file_size - FOOTER_SIZE) as u64,
+        })
+    );
+    let err = open(&data[..SIZEOF_U64]).err().unwrap();
+    assert!(err.downcast_ref::<crate::error::Error>().is_some());
+
+    // An unknown version of the format is rejected.
+    let mut newer = data.clone();
+    let version_offset = file_size - SIZEOF_U64 - SIZEOF_U32;
+    newer[version_offset..version_offset + SIZEOF_U32]
+        .copy_from_slice(&(SST_FORMAT_VERSION + 1).to_be_bytes());
+    let err = open(&newer).err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(&crate::error::Error::UnsupportedFormat {
+            file_id: 0,
+            version: SST_FORMAT_VERSION + 1,
+        })
+    );
+    assert!(open(&data).is_ok());
+}
+
+fn check_sst_with_compression(compression: CompressionType) {
+    let mut builder = SsTableBuilder::with_options(SsTableBuilderOptions {
+        block_size: 128,
+        compression,
+        ..Default::default()
+    });
+    for idx in 0..num_of_keys() {
+        builder.add(&key_of(idx), idx as u64, ValueType::Put, &value_of(idx));
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_no_compression() {
+    check_sst_with_compression(CompressionType::None);
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_sst_lz4_compression() {
+    check_sst_with_compression(CompressionType::Lz4);
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_sst_snappy_compression() {
+    check_sst_with_compression(CompressionType::Snappy);
+}
+
+#[test]
+fn test_sst_seek_key_versions() {
+    // The versions of each key span several blocks.
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..5 {
+        for seq in (1..=20).rev() {
+            builder.add(&key_of(idx), seq, ValueType::Put, &value_of(seq as usize));
+        }
+    }
+    let dir = tempdir().unwrap();
+    let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert_eq!(sst.max_seq(), 20);
+    for idx in 0..5 {
+        let mut iter = SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(idx)).unwrap();
+        for seq in (1..=20).rev() {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.seq(), seq);
+            assert_eq!(iter.value(), value_of(seq as usize));
+            iter.next().unwrap();
+        }
+    }
+
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    for idx in (0..5).rev() {
+        for seq in 1..=20 {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.seq(), seq);
+            iter.prev().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterate_backward() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_last(sst).unwrap();
+    for i in (0..num_of_keys()).rev() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    for i in 0..num_of_keys() {
+        iter.seek_for_prev(&format!("key_{:03}", i * 5 + 2).into_bytes())
+            .unwrap();
+        assert_eq!(iter.key(), key_of(i));
+        iter.seek_for_prev(&key_of(i)).unwrap();
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+    }
+    iter.seek_for_prev(b"k").unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_range_tombstones() {
+    let tombstone = RangeTombstone::new(Bytes::from("key_1"), Bytes::from("key_3"), 2);
+    let mut builder = SsTableBuilder::new(16);
+    builder.add_range_tombstone(tombstone.clone());
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.first_key(), &b"key_1"[..]);
+    assert_eq!(sst.last_key(), &b"key_3"[..]);
+    assert_eq!(sst.max_seq(), 2);
+
+    let sst = Arc::new(SsTable::open_for_test(sst.file).unwrap());
+    assert_eq!(sst.range_tombstones(), &[tombstone][..]);
+    let iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_to_key(sst, b"key_2").unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_blob_values() {
+    let dir = tempdir().unwrap();
+    let blob_path = dir.path().join("2.blob");
+    let mut builder = SsTableBuilder::with_options(SsTableBuilderOptions {
+        block_size: 128,
+        blob_file: Some(BlobFileOptions {
+            min_blob_size: 100,
+            new_blob_file: {
+                let blob_path = blob_path.clone();
+                Arc::new(move || (2, blob_path.clone()))
+            },
+        }),
+        ..Default::default()
+    });
+    let large_value = |idx: usize| vec![idx as u8; 100 + idx];
+    for idx in 0..10 {
+        let key = format!("key_{}", idx);
+        if idx % 2 == 0 {
+            builder.add(key.as_bytes(), 1, ValueType::Put, &large_value(idx));
+        } else {
+            builder.add(key.as_bytes(), 1, ValueType::Put, b"small");
+        }
+    }
+    // A blob reference from another SST is stored as it is.
+    let other_ref = BlobRef {
+        file_id: 1,
+        offset: 0,
+        len: 1000,
+    };
+    builder.add(b"key_x", 1, ValueType::Blob, &other_ref.encode());
+    let path = dir.path().join("1.sst");
+    builder.build_for_test(&path).unwrap();
+    let sst = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let blob_file = BlobFile::open(2, &blob_path).unwrap();
+    assert_eq!(
+        sst.blob_refs(),
+        &[(1, 1000), (2, blob_file.size())][..],
+        "the SST refers to all of its own blob file"
+    );
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..10 {
+        if idx % 2 == 0 {
+            assert_eq!(iter.value_type(), ValueType::Blob);
+            let blob_ref = BlobRef::decode(iter.value()).unwrap();
+            assert_eq!(blob_ref.file_id, 2);
+            assert_eq!(
+                blob_file.read(iter.key(), &blob_ref).unwrap(),
+                Bytes::from(large_value(idx))
+            );
+            // The record must belong to the key.
+            let err = blob_file.read(b"key_x", &blob_ref).unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<crate::error::Error>(),
+                Some(&crate::error::Error::BlobCorruption {
+                    file_id: 2,
+                    offset: blob_ref.offset,
+                })
+            );
+        } else {
+            assert_eq!(iter.value_type(), ValueType::Put);
+            assert_eq!(iter.value(), b"small");
+        }
+        iter.next().unwrap();
+    }
+    assert_eq!(iter.key(), b"key_x");
+    assert_eq!(BlobRef::decode(iter.value()), Some(other_ref));
+
+    // A corrupted record fails its checksum.
+    let mut data = std::fs::read(&blob_path).unwrap();
+    data[10] ^= 1;
+    std::fs::write(&blob_path, data).unwrap();
+    let blob_file = BlobFile::open(2, &blob_path).unwrap();
+    let first_ref = BlobRef {
+        file_id: 2,
+        offset: 0,
+        len: (SIZEOF_U32 * 3 + 5 + 100) as u32,
+    };
+    let err = blob_file.read(b"key_0", &first_ref).unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<crate::error::Error>(),
+        Some(&crate::error::Error::BlobCorruption {
+            file_id: 2,
+            offset: 0,
+        })
+    );
+}
@@ -1 +1,12 @@
+pub mod blob_tests;
+pub mod column_family_tests;
+pub mod comparator_tests;
 pub mod day4_tests;
+pub mod day5_tests;
+pub mod day6_tests;
+pub mod large_entry_tests;
+pub mod merge_tests;
+pub mod mvcc_tests;
+pub mod prefix_tests;
+pub mod reverse_scan_tests;
+pub mod seek_tests;
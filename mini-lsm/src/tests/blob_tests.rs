@@ -0,0 +1,170 @@
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::tests::merge_tests::AppendOperator;
+
+fn blob_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        min_blob_size: Some(1024),
+        compaction_options: CompactionOptions::NoCompaction,
+        merge_operator: Some(Arc::new(AppendOperator)),
+        ..Default::default()
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:03}", idx).into_bytes()
+}
+
+/// Large values go to blob files, and small values stay in the SSTs.
+fn value_of(idx: usize, version: usize) -> Vec<u8> {
+    let len = if idx % 2 == 0 { 2000 + idx } else { 10 };
+    vec![(idx + version) as u8; len]
+}
+
+fn blob_file_ids(path: &Path) -> Vec<usize> {
+    let mut ids: Vec<usize> = std::fs::read_dir(path)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".blob")?.parse().ok()
+        })
+        .collect();
+    ids.sort();
+    ids
+}
+
+fn check_values(storage: &LsmStorage, num_keys: usize, version: impl Fn(usize) -> usize) {
+    for idx in 0..num_keys {
+        assert_eq!(
+            storage.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx, version(idx))))
+        );
+    }
+    let end = key_of(num_keys);
+    let mut iter = storage
+        .scan(Bound::Unbounded, Bound::Excluded(&end))
+        .unwrap();
+    for idx in 0..num_keys {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, version(idx)));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let mut iter = storage
+        .scan_reverse(Bound::Unbounded, Bound::Excluded(&end))
+        .unwrap();
+    for idx in (0..num_keys).rev() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx, version(idx)));
+        iter.prev().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_blob_values() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, blob_options()).unwrap();
+        for idx in 0..20 {
+            storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+        }
+        storage.sync().unwrap();
+        assert_eq!(blob_file_ids(dir.path()).len(), 1);
+        check_values(&storage, 20, |_| 0);
+
+        // Merge operands are applied on top of a value in a blob file.
+        storage.merge(&key_of(20), b"base").unwrap();
+        storage.sync().unwrap();
+        storage.put(&key_of(21), &[b'x'; 2000]).unwrap();
+        storage.sync().unwrap();
+        storage.merge(&key_of(21), b"tail").unwrap();
+        storage.sync().unwrap();
+        let mut expected = vec![b'x'; 2000];
+        expected.extend_from_slice(b",tail");
+        assert_eq!(
+            storage.get(&key_of(21)).unwrap(),
+            Some(Bytes::from(expected.clone()))
+        );
+        let mut iter = storage
+            .scan(Bound::Included(&key_of(21)), Bound::Unbounded)
+            .unwrap();
+        assert_eq!(iter.value(), &expected[..]);
+        iter.seek_to_last().unwrap();
+        assert_eq!(iter.value(), &expected[..]);
+        storage.close().unwrap();
+    }
+
+    // The blob files are found again through the SSTs.
+    let storage = LsmStorage::open(&dir, blob_options()).unwrap();
+    check_values(&storage, 20, |_| 0);
+    assert_eq!(storage.get(&key_of(20)).unwrap(), Some(Bytes::from("base")));
+}
+
+#[test]
+fn test_orphan_blob_file_removed() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, blob_options()).unwrap();
+    storage.put(&key_of(0), &value_of(0, 0)).unwrap();
+    storage.put(&key_of(1), &value_of(1, 0)).unwrap();
+    storage.close().unwrap();
+    let blob_files = blob_file_ids(dir.path());
+    assert_eq!(blob_files.len(), 1);
+
+    // A blob file written before a crash, whose SST was never recorded.
+    let orphan = dir.path().join(format!("{:05}.blob", blob_files[0] + 100));
+    std::fs::write(&orphan, b"orphan").unwrap();
+    let storage = LsmStorage::open(&dir, blob_options()).unwrap();
+    assert!(!orphan.exists());
+    assert_eq!(blob_file_ids(dir.path()), blob_files);
+    check_values(&storage, 2, |_| 0);
+}
+
+#[test]
+fn test_blob_garbage_collection() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+            base_level_size: 1 << 20,
+            level_size_multiplier: 2,
+        }),
+        ..blob_options()
+    };
+    let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+    for idx in 0..20 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    let first_blob_file = blob_file_ids(dir.path())[0];
+    for idx in 0..16 {
+        storage.put(&key_of(idx), &value_of(idx, 1)).unwrap();
+    }
+    storage.sync().unwrap();
+    let second_blob_file = *blob_file_ids(dir.path()).last().unwrap();
+    assert_ne!(first_blob_file, second_blob_file);
+
+    // Compaction drops most of the values in the first blob file, so the SST still referring to
+    // it is rewritten. The second blob file is kept as it is.
+    storage.inner.trigger_compaction().unwrap();
+    let blob_files = blob_file_ids(dir.path());
+    assert_eq!(blob_files.len(), 2);
+    assert!(!blob_files.contains(&first_blob_file));
+    assert!(blob_files.contains(&second_blob_file));
+    let version = |idx| usize::from(idx < 16);
+    check_values(&storage, 20, version);
+    storage.close().unwrap();
+
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    check_values(&storage, 20, version);
+    assert_eq!(blob_file_ids(dir.path()), blob_files);
+}
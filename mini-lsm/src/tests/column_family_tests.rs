@@ -0,0 +1,82 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::tests::day4_tests::check_iter_result;
+use crate::write_batch::WriteBatch;
+
+#[test]
+fn test_column_families() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        let users = storage.create_column_family("users").unwrap();
+        assert!(storage.create_column_family("users").is_err());
+
+        storage.put(b"1", b"default").unwrap();
+        storage.put_cf(&users, b"1", b"alice").unwrap();
+        assert_eq!(storage.get(b"1").unwrap(), Some(Bytes::from("default")));
+        assert_eq!(
+            storage.get_cf(&users, b"1").unwrap(),
+            Some(Bytes::from("alice"))
+        );
+
+        // A batch across column families is logged as one WAL record.
+        let mut batch = WriteBatch::new();
+        batch.put(b"2", b"default").put_cf(&users, b"2", b"bob");
+        batch.delete_cf(&users, b"1");
+        storage.write(&batch).unwrap();
+        storage.sync().unwrap();
+        storage.put_cf(&users, b"3", b"carol").unwrap();
+        // Crash without flushing the last write.
+    }
+
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    let users = storage.column_family("users").unwrap();
+    check_iter_result(
+        storage
+            .scan_cf(&users, Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+        vec![
+            (Bytes::from("2"), Bytes::from("bob")),
+            (Bytes::from("3"), Bytes::from("carol")),
+        ],
+    );
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("default")),
+            (Bytes::from("2"), Bytes::from("default")),
+        ],
+    );
+
+    storage.drop_column_family("users").unwrap();
+    assert!(storage.drop_column_family("default").is_err());
+    assert!(users.is_dropped());
+    assert!(storage.get_cf(&users, b"2").is_err());
+    assert!(storage.put_cf(&users, b"4", b"dave").is_err());
+    storage.close().unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert!(storage.column_family("users").is_none());
+    // A column family created with the name of a dropped one starts empty.
+    let users = storage.create_column_family("users").unwrap();
+    assert_eq!(storage.get_cf(&users, b"2").unwrap(), None);
+    assert_eq!(storage.get(b"2").unwrap(), Some(Bytes::from("default")));
+    let num_ssts = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_str()
+                .unwrap()
+                .ends_with(".sst")
+        })
+        .count();
+    assert_eq!(num_ssts, storage.inner.default_cf.snapshot().sstables.len());
+}
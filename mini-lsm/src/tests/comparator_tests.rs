@@ -0,0 +1,101 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::comparator::ReverseBytewiseComparator;
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn reverse_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+            base_level_size: 8192,
+            level_size_multiplier: 2,
+        }),
+        comparator: Arc::new(ReverseBytewiseComparator),
+        ..Default::default()
+    }
+}
+
+fn collect(iter: impl StorageIterator) -> Vec<Bytes> {
+    let mut iter = iter;
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_reverse_comparator() {
+    let dir = tempdir().unwrap();
+    let key_of = |idx: usize| Bytes::from(format!("key_{:05}", idx));
+    {
+        let storage = LsmStorage::open(&dir, reverse_options()).unwrap();
+        for round in 0..4 {
+            for idx in 0..200 {
+                storage
+                    .put(&key_of(idx), round.to_string().as_bytes())
+                    .unwrap();
+            }
+            storage.sync().unwrap();
+            storage.inner.trigger_compaction().unwrap();
+        }
+        // The range is from the larger key to the smaller one.
+        storage.delete_range(&key_of(150), &key_of(100)).unwrap();
+        storage.delete_range(&key_of(10), &key_of(20)).unwrap();
+        storage.delete(&key_of(5)).unwrap();
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+
+        let txn = storage.new_txn();
+        txn.put(&key_of(120), b"txn");
+        txn.put(&key_of(300), b"txn");
+        let expected: Vec<Bytes> = (0..=300)
+            .rev()
+            .filter(|idx| !(101..=150).contains(idx) || *idx == 120)
+            .filter(|idx| *idx != 5 && (*idx < 200 || *idx == 300))
+            .map(key_of)
+            .collect();
+        assert_eq!(
+            collect(txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap()),
+            expected
+        );
+        storage.close().unwrap();
+    }
+
+    let storage = LsmStorage::open(&dir, reverse_options()).unwrap();
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(Bytes::from("3")));
+    assert_eq!(storage.get(&key_of(120)).unwrap(), None);
+    assert_eq!(
+        collect(
+            storage
+                .scan(Bound::Included(&key_of(103)), Bound::Excluded(&key_of(96)))
+                .unwrap()
+        ),
+        vec![key_of(100), key_of(99), key_of(98), key_of(97)]
+    );
+    storage.close().unwrap();
+    drop(storage);
+
+    // The data is ordered by the comparator it was written with.
+    let err = LsmStorage::open(&dir, LsmStorageOptions::default())
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.downcast_ref::<Error>(),
+        Some(&Error::IncompatibleOptions {
+            option: "comparator",
+            persisted: "reverse_bytewise".to_string(),
+            given: "bytewise".to_string(),
+        })
+    );
+}
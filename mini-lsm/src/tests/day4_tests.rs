@@ -9,7 +9,7 @@ fn as_bytes(x: &[u8]) -> Bytes {
     Bytes::copy_from_slice(x)
 }
 
-fn check_iter_result(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>) {
+pub(crate) fn check_iter_result(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>) {
     let mut iter = iter;
     for (k, v) in expected {
         assert!(iter.is_valid());
@@ -34,9 +34,9 @@ fn check_iter_result(iter: impl StorageIterator, expected: Vec<(Bytes, Bytes)>)
 
 #[test]
 fn test_storage_get() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -49,9 +49,9 @@ fn test_storage_get() {
 
 #[test]
 fn test_storage_scan_memtable_1() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -79,9 +79,9 @@ fn test_storage_scan_memtable_1() {
 
 #[test]
 fn test_storage_scan_memtable_2() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.put(b"3", b"23333").unwrap();
@@ -109,9 +109,9 @@ fn test_storage_scan_memtable_2() {
 
 #[test]
 fn test_storage_get_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -125,9 +125,9 @@ fn test_storage_get_after_sync() {
 
 #[test]
 fn test_storage_scan_memtable_1_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -156,9 +156,9 @@ fn test_storage_scan_memtable_1_after_sync() {
 
 #[test]
 fn test_storage_scan_memtable_2_after_sync() {
-    use crate::lsm_storage::LsmStorage;
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
     let dir = tempdir().unwrap();
-    let storage = LsmStorage::open(&dir).unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
     storage.put(b"1", b"233").unwrap();
     storage.put(b"2", b"2333").unwrap();
     storage.sync().unwrap();
@@ -185,3 +185,58 @@ fn test_storage_scan_memtable_2_after_sync() {
         vec![(Bytes::from("2"), Bytes::from("2333"))],
     );
 }
+
+#[test]
+fn test_storage_freeze_full_memtable() {
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_buffer_size: 1024,
+        max_imm_memtables: 2,
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for i in 0..1000 {
+        let key = format!("key_{:05}", i);
+        storage.put(key.as_bytes(), b"value").unwrap();
+        let snapshot = storage.inner.default_cf.state.read().clone();
+        assert!(snapshot.memtable.approximate_size() < 1024);
+        assert!(snapshot.imm_memtables.len() <= 2);
+    }
+    for i in 0..1000 {
+        let key = format!("key_{:05}", i);
+        assert_eq!(&storage.get(key.as_bytes()).unwrap().unwrap()[..], b"value");
+    }
+    // The frozen memtables are flushed by the flush thread.
+    storage.close().unwrap();
+    assert!(storage.inner.default_cf.state.read().sstables.len() > 1);
+}
+
+#[test]
+fn test_storage_empty_value() {
+    use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"").unwrap();
+    storage.delete(b"3").unwrap();
+    storage.put(b"4", b"").unwrap();
+    storage.sync().unwrap();
+    storage.put(b"2", b"").unwrap();
+    storage.delete(b"4").unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"");
+    assert!(storage.get(b"3").unwrap().is_none());
+    assert!(storage.get(b"4").unwrap().is_none());
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::new()),
+            (Bytes::from("2"), Bytes::new()),
+        ],
+    );
+    storage.sync().unwrap();
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"");
+    assert!(storage.get(b"4").unwrap().is_none());
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions, TieredCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn leveled_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 8192,
+            level_size_multiplier: 2,
+        }),
+        ..Default::default()
+    }
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{:010}", idx, round).into_bytes()
+}
+
+fn check_storage(storage: &LsmStorage, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for (key, value) in expected {
+        assert_eq!(
+            &storage.get(key).unwrap().unwrap()[..],
+            &value[..],
+            "key: {:?}",
+            Bytes::copy_from_slice(key)
+        );
+    }
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    for (key, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), &key[..]);
+        assert_eq!(iter.value(), &value[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_leveled_compaction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    let mut expected = BTreeMap::new();
+    for round in 0..10 {
+        for idx in (round * 50)..(round * 50 + 500) {
+            if idx % 7 == round {
+                storage.delete(&key_of(idx)).unwrap();
+                expected.remove(&key_of(idx));
+            } else {
+                storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+                expected.insert(key_of(idx), value_of(idx, round));
+            }
+        }
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+
+        let snapshot = storage.inner.default_cf.state.read().clone();
+        assert!(snapshot.l0_sstables.len() < 2);
+        assert_eq!(snapshot.levels.len(), 3);
+        for (_, ssts) in &snapshot.levels {
+            // SSTs in a level are sorted and do not overlap.
+            for pair in ssts.windows(2) {
+                assert!(
+                    snapshot.sstables[&pair[0]].last_key()
+                        < snapshot.sstables[&pair[1]].first_key()
+                );
+            }
+        }
+        check_storage(&storage, &expected);
+    }
+    let snapshot = storage.inner.default_cf.state.read().clone();
+    assert!(!snapshot.levels[2].1.is_empty());
+}
+
+#[test]
+fn test_leveled_compaction_recover() {
+    let dir = tempdir().unwrap();
+    let mut expected = BTreeMap::new();
+    let levels = {
+        let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+        for round in 0..6 {
+            for idx in 0..300 {
+                storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+                expected.insert(key_of(idx), value_of(idx, round));
+            }
+            storage.sync().unwrap();
+            storage.inner.trigger_compaction().unwrap();
+        }
+        storage.close().unwrap();
+        let snapshot = storage.inner.default_cf.state.read().clone();
+        (snapshot.l0_sstables.clone(), snapshot.levels.clone())
+    };
+
+    let storage = LsmStorage::open(&dir, leveled_options()).unwrap();
+    let snapshot = storage.inner.default_cf.state.read().clone();
+    assert_eq!(snapshot.l0_sstables, levels.0);
+    assert_eq!(snapshot.levels, levels.1);
+    check_storage(&storage, &expected);
+
+    // Files of compacted SSTs are removed.
+    let num_ssts = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".sst")
+        })
+        .count();
+    assert_eq!(num_ssts, snapshot.sstables.len());
+}
+
+fn tiered_options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Tiered(TieredCompactionOptions {
+            num_tiers: 3,
+            max_size_amplification_percent: 200,
+            size_ratio: 1,
+            min_merge_width: 2,
+        }),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_tiered_compaction() {
+    let dir = tempdir().unwrap();
+    let mut expected = BTreeMap::new();
+    let levels = {
+        let storage = LsmStorage::open(&dir, tiered_options()).unwrap();
+        for round in 0..10 {
+            for idx in (round * 50)..(round * 50 + 500) {
+                if idx % 7 == round {
+                    storage.delete(&key_of(idx)).unwrap();
+                    expected.remove(&key_of(idx));
+                } else {
+                    storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+                    expected.insert(key_of(idx), value_of(idx, round));
+                }
+            }
+            storage.sync().unwrap();
+            storage.inner.trigger_compaction().unwrap();
+
+            let snapshot = storage.inner.default_cf.state.read().clone();
+            assert!(snapshot.l0_sstables.is_empty());
+            assert!(snapshot.levels.len() < 3);
+            for (tier_id, ssts) in &snapshot.levels {
+                assert_eq!(*tier_id, ssts[0]);
+            }
+            check_storage(&storage, &expected);
+        }
+        storage.close().unwrap();
+        let snapshot = storage.inner.default_cf.state.read().clone();
+        snapshot.levels.clone()
+    };
+
+    let storage = LsmStorage::open(&dir, tiered_options()).unwrap();
+    assert_eq!(storage.inner.default_cf.state.read().levels, levels);
+    check_storage(&storage, &expected);
+}
@@ -0,0 +1,354 @@
+use std::ops::Bound;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::error::Error;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::manifest::{Manifest, ManifestRecord};
+use crate::tests::day4_tests::check_iter_result;
+use crate::wal::WalSyncPolicy;
+use crate::write_batch::WriteBatch;
+
+#[test]
+fn test_wal_recover_memtable() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"2").unwrap();
+        // dropped without `sync`, as if the process crashed
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233")),
+            (Bytes::from("3"), Bytes::from("23333")),
+        ],
+    );
+}
+
+#[test]
+fn test_wal_recover_multiple_memtables() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        wal_sync_policy: WalSyncPolicy::Batch(4),
+        ..Default::default()
+    };
+    {
+        let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+    }
+    {
+        let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+        storage.put(b"2", b"23333").unwrap();
+        storage.put(b"3", b"233333").unwrap();
+    }
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"233333");
+
+    // Flushing removes the WALs of the flushed memtables.
+    storage.sync().unwrap();
+    let num_wals = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".wal")
+        })
+        .count();
+    assert_eq!(num_wals, 1);
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"23333");
+}
+
+#[test]
+fn test_recover_memtable_without_wal() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+    }
+    // Crash after recording a new memtable in the manifest, but before creating its WAL.
+    let wal_id = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            name.strip_suffix(".wal")?.parse::<usize>().ok()
+        })
+        .max()
+        .unwrap();
+    let (manifest, _) = Manifest::recover(dir.path().join("MANIFEST")).unwrap();
+    manifest
+        .add_record(ManifestRecord::NewMemtable(wal_id + 1))
+        .unwrap();
+    drop(manifest);
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+        storage.put(b"2", b"2333").unwrap();
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233")),
+            (Bytes::from("2"), Bytes::from("2333")),
+        ],
+    );
+}
+
+#[test]
+fn test_manifest_recover_sstables() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"3", b"23333").unwrap();
+        storage.delete(b"1").unwrap();
+        storage.sync().unwrap();
+        storage.put(b"4", b"233333").unwrap();
+    }
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        assert!(storage.get(b"1").unwrap().is_none());
+        assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+        assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+        assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+        storage.put(b"1", b"2").unwrap();
+        storage.sync().unwrap();
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("2")),
+            (Bytes::from("2"), Bytes::from("2333")),
+            (Bytes::from("3"), Bytes::from("23333")),
+            (Bytes::from("4"), Bytes::from("233333")),
+        ],
+    );
+}
+
+#[test]
+fn test_close_and_reopen() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        storage.close().unwrap();
+        // Closing flushes the memtable, so there is nothing left to recover from the WAL.
+        let snapshot = storage.inner.default_cf.state.read().clone();
+        assert!(snapshot.memtable.is_empty());
+        assert!(snapshot.imm_memtables.is_empty());
+        assert_eq!(snapshot.l0_sstables.len(), 1);
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert!(storage
+        .inner
+        .default_cf
+        .state
+        .read()
+        .imm_memtables
+        .is_empty());
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+}
+
+#[test]
+fn test_background_flush() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+    }
+    // The memtable recovered from the WAL is flushed by the flush thread.
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !storage
+        .inner
+        .default_cf
+        .state
+        .read()
+        .imm_memtables
+        .is_empty()
+    {
+        assert!(Instant::now() < deadline, "memtable was not flushed");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(storage.inner.default_cf.state.read().l0_sstables.len(), 1);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    storage.close().unwrap();
+}
+
+#[test]
+fn test_write_after_close() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.close().unwrap();
+    for err in [
+        storage.put(b"2", b"2333").unwrap_err(),
+        storage.delete(b"1").unwrap_err(),
+    ] {
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::Closed));
+    }
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+}
+
+#[test]
+fn test_write_after_close_past_max_imm_memtables() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        write_buffer_size: 1,
+        max_imm_memtables: 1,
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    let txn = storage.new_txn();
+    txn.put(b"0", b"233");
+    for idx in 0..3 {
+        storage.put(idx.to_string().as_bytes(), b"233").unwrap();
+    }
+    // Each write froze its memtable, and no flush thread is left to drain the last one.
+    drop(storage);
+    let err = txn.commit().unwrap_err();
+    assert_eq!(err.downcast_ref::<Error>(), Some(&Error::Closed));
+
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.close().unwrap();
+    for idx in 0..3 {
+        let err = storage.put(idx.to_string().as_bytes(), b"233").unwrap_err();
+        assert_eq!(err.downcast_ref::<Error>(), Some(&Error::Closed));
+    }
+}
+
+#[test]
+fn test_get_corrupted_sst() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.close().unwrap();
+    }
+    let sst_path = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().map_or(false, |ext| ext == "sst"))
+        .unwrap();
+    let mut data = std::fs::read(&sst_path).unwrap();
+    data[0] ^= 1;
+    std::fs::write(&sst_path, data).unwrap();
+
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    let err = storage.get(b"1").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Corruption {
+            block_idx: Some(0),
+            offset: 0,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_write_batch() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        storage.put(b"1", b"233").unwrap();
+        storage.put(b"2", b"2333").unwrap();
+        let snapshot = storage.snapshot();
+        let mut batch = WriteBatch::new();
+        batch
+            .put(b"1", b"23333")
+            .delete(b"2")
+            .put(b"3", b"233333")
+            .put(b"3", b"2333333");
+        storage.write(&batch).unwrap();
+        storage.write(&WriteBatch::new()).unwrap();
+
+        check_iter_result(
+            storage
+                .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+                .unwrap(),
+            vec![
+                (Bytes::from("1"), Bytes::from("233")),
+                (Bytes::from("2"), Bytes::from("2333")),
+            ],
+        );
+        // dropped without `sync`, as if the process crashed
+    }
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("23333")),
+            (Bytes::from("3"), Bytes::from("2333333")),
+        ],
+    );
+}
+
+#[test]
+fn test_delete_range() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+        for key in ["1", "2", "3", "4"] {
+            storage.put(key.as_bytes(), b"233").unwrap();
+        }
+        storage.sync().unwrap();
+        let snapshot = storage.snapshot();
+        storage.delete_range(b"2", b"4").unwrap();
+        storage.delete_range(b"4", b"1").unwrap();
+        storage.put(b"3", b"2333").unwrap();
+
+        assert!(storage.get(b"2").unwrap().is_none());
+        assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"2333");
+        assert_eq!(
+            &storage.get_with_snapshot(b"2", &snapshot).unwrap().unwrap()[..],
+            b"233"
+        );
+        check_iter_result(
+            storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+            vec![
+                (Bytes::from("1"), Bytes::from("233")),
+                (Bytes::from("3"), Bytes::from("2333")),
+                (Bytes::from("4"), Bytes::from("233")),
+            ],
+        );
+        // dropped without `sync`, as if the process crashed
+    }
+    // Recover the range tombstones from the WAL, and then flush them to an SST.
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.sync().unwrap();
+    drop(storage);
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert!(storage.get(b"2").unwrap().is_none());
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233")),
+            (Bytes::from("3"), Bytes::from("2333")),
+            (Bytes::from("4"), Bytes::from("233")),
+        ],
+    );
+}
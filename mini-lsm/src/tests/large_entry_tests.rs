@@ -0,0 +1,91 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions, MAX_KEY_SIZE};
+
+fn large_key(idx: usize) -> Vec<u8> {
+    let mut key = vec![b'k'; 100 << 10];
+    key.extend(format!("{:03}", idx).as_bytes());
+    key
+}
+
+fn large_value(idx: usize) -> Vec<u8> {
+    vec![idx as u8; (idx + 1) * (300 << 10)]
+}
+
+#[test]
+fn test_large_entries() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 2,
+            base_level_size: 1 << 20,
+            level_size_multiplier: 2,
+        }),
+        ..Default::default()
+    };
+    {
+        let storage = LsmStorage::open(&dir, options.clone()).unwrap();
+        for idx in 0..6 {
+            storage.put(&large_key(idx), &large_value(idx)).unwrap();
+            storage
+                .put(format!("small_{}", idx).as_bytes(), b"value")
+                .unwrap();
+            if idx % 2 == 1 {
+                storage.sync().unwrap();
+            }
+        }
+        storage.delete_range(&large_key(1), &large_key(2)).unwrap();
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+        storage.close().unwrap();
+    }
+
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for idx in 0..6 {
+        let value = storage.get(&large_key(idx)).unwrap();
+        if idx == 1 {
+            assert_eq!(value, None);
+        } else {
+            assert_eq!(value, Some(Bytes::from(large_value(idx))));
+        }
+    }
+    let mut iter = storage
+        .scan(Bound::Unbounded, Bound::Excluded(b"small"))
+        .unwrap();
+    for idx in [0, 2, 3, 4, 5] {
+        assert_eq!(iter.key(), &large_key(idx)[..]);
+        assert_eq!(iter.value(), &large_value(idx)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_entry_too_large() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    let key = vec![b'k'; MAX_KEY_SIZE + 1];
+    let expected = Error::TooLarge {
+        what: "key",
+        len: key.len() as u64,
+        limit: MAX_KEY_SIZE as u64,
+    };
+    let err = storage.put(&key, b"value").unwrap_err();
+    assert_eq!(err.downcast_ref::<Error>(), Some(&expected));
+    let err = storage.delete_range(b"a", &key).unwrap_err();
+    assert_eq!(err.downcast_ref::<Error>(), Some(&expected));
+    // Nothing is written.
+    assert_eq!(storage.get(&key).unwrap(), None);
+    assert!(!storage
+        .scan(Bound::Unbounded, Bound::Unbounded)
+        .unwrap()
+        .is_valid());
+    storage.put(&key[..MAX_KEY_SIZE], b"value").unwrap();
+}
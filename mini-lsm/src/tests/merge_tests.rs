@@ -0,0 +1,178 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::merge_operator::MergeOperator;
+use crate::mvcc::ValueType;
+use crate::table::SsTableIterator;
+use crate::tests::day4_tests::check_iter_result;
+
+/// Appends the operands to the value, separated by commas.
+pub(crate) struct AppendOperator;
+
+impl MergeOperator for AppendOperator {
+    fn name(&self) -> &str {
+        "append"
+    }
+
+    fn full_merge(&self, _key: &[u8], base: Option<&[u8]>, operands: &[&[u8]]) -> Result<Bytes> {
+        let mut values: Vec<&[u8]> = base.into_iter().collect();
+        values.extend_from_slice(operands);
+        Ok(values.join(&b","[..]).into())
+    }
+
+    fn partial_merge(&self, _key: &[u8], operands: &[&[u8]]) -> Option<Bytes> {
+        Some(operands.join(&b","[..]).into())
+    }
+}
+
+fn options_with_append_operator() -> LsmStorageOptions {
+    LsmStorageOptions {
+        merge_operator: Some(Arc::new(AppendOperator)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_merge() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, options_with_append_operator()).unwrap();
+        storage.put(b"1", b"a").unwrap();
+        storage.merge(b"1", b"b").unwrap();
+        storage.merge(b"2", b"a").unwrap();
+        storage.sync().unwrap();
+        let snapshot = storage.snapshot();
+        storage.merge(b"1", b"c").unwrap();
+        storage.merge(b"2", b"b").unwrap();
+        storage.delete(b"3").unwrap();
+        storage.merge(b"3", b"a").unwrap();
+        storage.put(b"4", b"a").unwrap();
+        storage.delete_range(b"4", b"5").unwrap();
+        storage.merge(b"4", b"b").unwrap();
+
+        assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"a,b,c");
+        assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"a,b");
+        assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"a");
+        assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"b");
+        assert_eq!(
+            &storage.get_with_snapshot(b"1", &snapshot).unwrap().unwrap()[..],
+            b"a,b"
+        );
+        check_iter_result(
+            storage
+                .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+                .unwrap(),
+            vec![
+                (Bytes::from("1"), Bytes::from("a,b")),
+                (Bytes::from("2"), Bytes::from("a")),
+            ],
+        );
+        // dropped without `sync`, as if the process crashed
+    }
+    let storage = LsmStorage::open(&dir, options_with_append_operator()).unwrap();
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("a,b,c")),
+            (Bytes::from("2"), Bytes::from("a,b")),
+            (Bytes::from("3"), Bytes::from("a")),
+            (Bytes::from("4"), Bytes::from("b")),
+        ],
+    );
+    check_iter_result(
+        storage
+            .scan(Bound::Excluded(b"1"), Bound::Included(b"2"))
+            .unwrap(),
+        vec![(Bytes::from("2"), Bytes::from("a,b"))],
+    );
+}
+
+#[test]
+fn test_merge_without_operator() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    assert!(storage.merge(b"1", b"a").is_err());
+    assert!(storage.get(b"1").unwrap().is_none());
+}
+
+#[test]
+fn test_merge_after_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            base_level_size: 8192,
+            level_size_multiplier: 2,
+        }),
+        ..options_with_append_operator()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    let key_of = |idx: usize| format!("key_{:05}", idx).into_bytes();
+    let mut snapshot = None;
+    for round in 0..6 {
+        for idx in 0..100 {
+            storage
+                .merge(&key_of(idx), round.to_string().as_bytes())
+                .unwrap();
+        }
+        if round == 2 {
+            snapshot = Some(storage.snapshot());
+        }
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+
+    let snapshot = snapshot.unwrap();
+    for idx in 0..100 {
+        assert_eq!(
+            &storage.get(&key_of(idx)).unwrap().unwrap()[..],
+            b"0,1,2,3,4,5"
+        );
+        assert_eq!(
+            &storage
+                .get_with_snapshot(&key_of(idx), &snapshot)
+                .unwrap()
+                .unwrap()[..],
+            b"0,1,2"
+        );
+    }
+    drop(snapshot);
+
+    // Compact everything into the bottom level, where the operands become values.
+    for round in 6..8 {
+        storage
+            .merge(&key_of(0), round.to_string().as_bytes())
+            .unwrap();
+        storage
+            .merge(&key_of(99), round.to_string().as_bytes())
+            .unwrap();
+        storage.sync().unwrap();
+    }
+    storage.inner.trigger_compaction().unwrap();
+    let state = storage.inner.default_cf.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    for sst in state.sstables.values() {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            assert_eq!(iter.value_type(), ValueType::Put);
+            iter.next().unwrap();
+        }
+    }
+    assert_eq!(
+        &storage.get(&key_of(0)).unwrap().unwrap()[..],
+        b"0,1,2,3,4,5,6,7"
+    );
+    assert_eq!(
+        &storage.get(&key_of(1)).unwrap().unwrap()[..],
+        b"0,1,2,3,4,5"
+    );
+}
@@ -0,0 +1,323 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::error::Error;
+use crate::iterators::StorageIterator;
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::table::SsTableIterator;
+use crate::tests::day4_tests::check_iter_result;
+
+#[test]
+fn test_snapshot_read() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    let snapshot = storage.snapshot();
+    storage.put(b"1", b"23333").unwrap();
+    storage.delete(b"2").unwrap();
+    storage.put(b"3", b"233333").unwrap();
+
+    assert_eq!(
+        &storage.get_with_snapshot(b"1", &snapshot).unwrap().unwrap()[..],
+        b"233"
+    );
+    assert_eq!(
+        &storage.get_with_snapshot(b"2", &snapshot).unwrap().unwrap()[..],
+        b"2333"
+    );
+    assert!(storage
+        .get_with_snapshot(b"3", &snapshot)
+        .unwrap()
+        .is_none());
+    check_iter_result(
+        storage
+            .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+            .unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233")),
+            (Bytes::from("2"), Bytes::from("2333")),
+        ],
+    );
+    check_iter_result(
+        storage
+            .scan_with_snapshot(Bound::Excluded(b"1"), Bound::Unbounded, &snapshot)
+            .unwrap(),
+        vec![(Bytes::from("2"), Bytes::from("2333"))],
+    );
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("23333")),
+            (Bytes::from("3"), Bytes::from("233333")),
+        ],
+    );
+}
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize, round: usize) -> Vec<u8> {
+    format!("value_{:05}_{:010}", idx, round).into_bytes()
+}
+
+#[test]
+fn test_snapshot_read_after_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 3,
+            base_level_size: 8192,
+            level_size_multiplier: 2,
+        }),
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for idx in 0..200 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    let snapshot = storage.snapshot();
+    for round in 1..5 {
+        for idx in 0..200 {
+            if idx % 5 == round {
+                storage.delete(&key_of(idx)).unwrap();
+            } else {
+                storage.put(&key_of(idx), &value_of(idx, round)).unwrap();
+            }
+        }
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    assert!(!storage
+        .inner
+        .default_cf
+        .state
+        .read()
+        .levels
+        .iter()
+        .all(|(_, ssts)| ssts.is_empty()));
+
+    for idx in 0..200 {
+        assert_eq!(
+            &storage
+                .get_with_snapshot(&key_of(idx), &snapshot)
+                .unwrap()
+                .unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+        let latest = storage.get(&key_of(idx)).unwrap();
+        if idx % 5 == 4 {
+            assert!(latest.is_none());
+        } else {
+            assert_eq!(&latest.unwrap()[..], &value_of(idx, 4)[..]);
+        }
+    }
+    let mut iter = storage
+        .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+        .unwrap();
+    for idx in 0..200 {
+        assert_eq!(iter.key(), &key_of(idx)[..]);
+        assert_eq!(iter.value(), &value_of(idx, 0)[..]);
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_txn_read_own_writes() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+    storage.put(b"3", b"23333").unwrap();
+
+    let txn = storage.new_txn();
+    txn.put(b"1", b"233333");
+    txn.delete(b"2");
+    txn.put(b"4", b"2333333");
+    assert_eq!(&txn.get(b"1").unwrap().unwrap()[..], b"233333");
+    assert!(txn.get(b"2").unwrap().is_none());
+    check_iter_result(
+        txn.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233333")),
+            (Bytes::from("3"), Bytes::from("23333")),
+            (Bytes::from("4"), Bytes::from("2333333")),
+        ],
+    );
+    check_iter_result(
+        txn.scan(Bound::Excluded(b"1"), Bound::Included(b"3"))
+            .unwrap(),
+        vec![(Bytes::from("3"), Bytes::from("23333"))],
+    );
+    // Nothing is visible outside the transaction until it commits.
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233");
+    txn.commit().unwrap();
+    storage.put(b"5", b"23333333").unwrap();
+    check_iter_result(
+        storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap(),
+        vec![
+            (Bytes::from("1"), Bytes::from("233333")),
+            (Bytes::from("3"), Bytes::from("23333")),
+            (Bytes::from("4"), Bytes::from("2333333")),
+            (Bytes::from("5"), Bytes::from("23333333")),
+        ],
+    );
+
+    let txn = storage.new_txn();
+    txn.put(b"1", b"2");
+    drop(txn);
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"233333");
+}
+
+fn assert_conflict(result: anyhow::Result<()>, key: &str) {
+    assert_eq!(
+        result.unwrap_err().downcast_ref::<Error>(),
+        Some(&Error::TransactionConflict {
+            key: Bytes::copy_from_slice(key.as_bytes())
+        })
+    );
+}
+
+#[test]
+fn test_txn_conflict() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir, LsmStorageOptions::default()).unwrap();
+    storage.put(b"1", b"233").unwrap();
+    storage.put(b"2", b"2333").unwrap();
+
+    // Write-write conflict.
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    txn1.put(b"1", b"1");
+    txn2.put(b"1", b"2");
+    txn1.commit().unwrap();
+    assert_conflict(txn2.commit(), "1");
+    assert_eq!(&storage.get(b"1").unwrap().unwrap()[..], b"1");
+
+    // Read-write conflict, where the read key is flushed to an SST in the meantime.
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    assert_eq!(&txn1.get(b"2").unwrap().unwrap()[..], b"2333");
+    txn1.put(b"3", b"1");
+    txn2.delete(b"2");
+    txn2.commit().unwrap();
+    storage.sync().unwrap();
+    assert_conflict(txn1.commit(), "2");
+    assert!(storage.get(b"3").unwrap().is_none());
+
+    // A key yielded by a scan is read.
+    let txn1 = storage.new_txn();
+    let mut iter = txn1.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.key(), b"1");
+    iter.next().unwrap();
+    drop(iter);
+    txn1.put(b"4", b"1");
+    storage.put(b"1", b"3").unwrap();
+    assert_conflict(txn1.commit(), "1");
+
+    // A key inserted into the range of a scan is a phantom read.
+    let txn1 = storage.new_txn();
+    let iter = txn1
+        .scan(Bound::Included(b"4"), Bound::Excluded(b"9"))
+        .unwrap();
+    assert!(!iter.is_valid());
+    drop(iter);
+    txn1.put(b"9", b"1");
+    storage.put(b"7", b"1").unwrap();
+    assert_conflict(txn1.commit(), "7");
+    storage.delete(b"7").unwrap();
+
+    // Only the range the iterator covered is read.
+    let txn1 = storage.new_txn();
+    let iter = txn1.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    assert_eq!(iter.key(), b"1");
+    drop(iter);
+    txn1.put(b"9", b"1");
+    storage.put(b"8", b"1").unwrap();
+    txn1.commit().unwrap();
+    storage.delete(b"8").unwrap();
+
+    // Disjoint transactions, and read-only transactions, commit.
+    let txn1 = storage.new_txn();
+    let txn2 = storage.new_txn();
+    let txn3 = storage.new_txn();
+    txn1.put(b"5", b"1");
+    txn2.put(b"6", b"1");
+    assert!(txn3.get(b"5").unwrap().is_none());
+    txn1.commit().unwrap();
+    txn2.commit().unwrap();
+    txn3.commit().unwrap();
+}
+
+#[test]
+fn test_delete_range_after_compaction() {
+    let dir = tempdir().unwrap();
+    let options = LsmStorageOptions {
+        target_sst_size: 4096,
+        compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+            level0_file_num_compaction_trigger: 2,
+            max_levels: 1,
+            base_level_size: 8192,
+            level_size_multiplier: 2,
+        }),
+        ..Default::default()
+    };
+    let storage = LsmStorage::open(&dir, options).unwrap();
+    for idx in 0..200 {
+        storage.put(&key_of(idx), &value_of(idx, 0)).unwrap();
+    }
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.delete_range(&key_of(50), &key_of(150)).unwrap();
+    storage.put(&key_of(100), &value_of(100, 1)).unwrap();
+    storage.sync().unwrap();
+    storage.inner.trigger_compaction().unwrap();
+
+    // The versions covered by the tombstone are kept for the snapshot.
+    for idx in 0..200 {
+        assert_eq!(
+            &storage
+                .get_with_snapshot(&key_of(idx), &snapshot)
+                .unwrap()
+                .unwrap()[..],
+            &value_of(idx, 0)[..]
+        );
+    }
+    drop(snapshot);
+
+    // Compact every SST again, with the first and the last key in each new L0 SST.
+    for round in 1..3 {
+        storage.put(&key_of(0), &value_of(0, round)).unwrap();
+        storage.put(&key_of(199), &value_of(199, round)).unwrap();
+        storage.sync().unwrap();
+    }
+    storage.inner.trigger_compaction().unwrap();
+
+    let state = storage.inner.default_cf.state.read().clone();
+    assert!(state.l0_sstables.is_empty());
+    for sst in state.sstables.values() {
+        assert!(sst.range_tombstones().is_empty());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        while iter.is_valid() {
+            let key = iter.key();
+            assert!(key < &key_of(50)[..] || key >= &key_of(150)[..] || key == &key_of(100)[..]);
+            iter.next().unwrap();
+        }
+    }
+    for idx in 0..200 {
+        let value = storage.get(&key_of(idx)).unwrap();
+        match idx {
+            100 => assert_eq!(&value.unwrap()[..], &value_of(100, 1)[..]),
+            50..=149 => assert!(value.is_none()),
+            0 | 199 => assert_eq!(&value.unwrap()[..], &value_of(idx, 2)[..]),
+            _ => assert_eq!(&value.unwrap()[..], &value_of(idx, 0)[..]),
+        }
+    }
+}
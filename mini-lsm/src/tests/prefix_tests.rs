@@ -0,0 +1,129 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use moka::sync::ConcurrentCacheExt;
+use tempfile::tempdir;
+
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::prefix_extractor::DelimitedPrefixExtractor;
+
+fn options() -> LsmStorageOptions {
+    LsmStorageOptions {
+        target_sst_size: 4096,
+        prefix_extractor: Some(Arc::new(DelimitedPrefixExtractor::new(b'/'))),
+        ..Default::default()
+    }
+}
+
+fn key_of(tenant: usize, idx: usize) -> Bytes {
+    Bytes::from(format!("tenant_{:03}/key_{:05}", tenant, idx))
+}
+
+fn collect(iter: impl StorageIterator) -> Vec<Bytes> {
+    let mut iter = iter;
+    let mut keys = Vec::new();
+    while iter.is_valid() {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_scan_prefix() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, options()).unwrap();
+        // Each SST has the keys of a few tenants.
+        for tenant in (0..20).step_by(2) {
+            for idx in 0..50 {
+                storage.put(&key_of(tenant, idx), b"value").unwrap();
+            }
+            storage.sync().unwrap();
+        }
+        storage.put(b"tenant_002", b"value").unwrap();
+        storage.put(b"tenant_002/", b"value").unwrap();
+        storage.delete(&key_of(4, 10)).unwrap();
+        storage
+            .delete_range(&key_of(4, 20), &key_of(4, 45))
+            .unwrap();
+        storage.close().unwrap();
+    }
+
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    let expected: Vec<Bytes> = (0..50)
+        .filter(|idx| *idx != 10 && !(20..45).contains(idx))
+        .map(|idx| key_of(4, idx))
+        .collect();
+    assert_eq!(
+        collect(storage.scan_prefix(b"tenant_004/").unwrap()),
+        expected
+    );
+    let mut expected: Vec<Bytes> = (0..50).map(|idx| key_of(2, idx)).collect();
+    expected.insert(0, Bytes::from("tenant_002/"));
+    assert_eq!(
+        collect(storage.scan_prefix(b"tenant_002/").unwrap()),
+        expected
+    );
+    // A prefix that is not extracted from keys is not filtered, but the scan stops at it.
+    assert_eq!(
+        collect(storage.scan_prefix(b"tenant_018/key_0004").unwrap()),
+        (40..50).map(|idx| key_of(18, idx)).collect::<Vec<_>>()
+    );
+    assert!(collect(storage.scan_prefix(b"tenant_020/").unwrap()).is_empty());
+
+    // Moving backward stays within the prefix too.
+    let mut iter = storage.scan_prefix(b"tenant_006/").unwrap();
+    iter.seek_to_last().unwrap();
+    assert_eq!(iter.key(), key_of(6, 49));
+    iter.seek_for_prev(b"tenant_007").unwrap();
+    assert_eq!(iter.key(), key_of(6, 49));
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(6, 48));
+    iter.seek_for_prev(b"tenant_006").unwrap();
+    assert!(!iter.is_valid());
+    iter.seek(&key_of(6, 30)).unwrap();
+    assert_eq!(iter.key(), key_of(6, 30));
+    iter.seek(b"tenant_007").unwrap();
+    assert!(!iter.is_valid());
+    assert_eq!(
+        storage
+            .scan(Bound::Included(b"tenant_006/"), Bound::Unbounded)
+            .unwrap()
+            .key(),
+        key_of(6, 0)
+    );
+}
+
+#[test]
+fn test_scan_prefix_skips_tables() {
+    let dir = tempdir().unwrap();
+    {
+        let storage = LsmStorage::open(&dir, options()).unwrap();
+        for tenant in (0..20).step_by(2) {
+            for idx in 0..50 {
+                storage.put(&key_of(tenant, idx), b"value").unwrap();
+            }
+            storage.sync().unwrap();
+        }
+        storage.close().unwrap();
+    }
+
+    // The block cache of the reopened storage is empty.
+    let storage = LsmStorage::open(&dir, options()).unwrap();
+    let block_cache = storage.inner.block_cache.clone();
+    for tenant in (1..20).step_by(2) {
+        let prefix = format!("tenant_{:03}/", tenant);
+        assert!(collect(storage.scan_prefix(prefix.as_bytes()).unwrap()).is_empty());
+    }
+    block_cache.sync();
+    assert_eq!(block_cache.entry_count(), 0);
+    assert_eq!(
+        collect(storage.scan_prefix(b"tenant_010/").unwrap()).len(),
+        50
+    );
+    block_cache.sync();
+    assert!(block_cache.entry_count() > 0);
+}
@@ -0,0 +1,164 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+use crate::tests::merge_tests::AppendOperator;
+
+fn collect_backward(iter: impl BidirectionalIterator) -> Vec<(Bytes, Bytes)> {
+    let mut iter = iter;
+    let mut result = Vec::new();
+    while iter.is_valid() {
+        result.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.prev().unwrap();
+    }
+    result
+}
+
+fn key_of(idx: usize) -> Bytes {
+    Bytes::from(format!("key_{:05}", idx))
+}
+
+#[test]
+fn test_scan_reverse() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(
+        &dir,
+        LsmStorageOptions {
+            merge_operator: Some(Arc::new(AppendOperator)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    storage.put(b"1", b"a").unwrap();
+    storage.put(b"2", b"a").unwrap();
+    storage.put(b"3", b"a").unwrap();
+    storage.put(b"5", b"a").unwrap();
+    storage.sync().unwrap();
+    let snapshot = storage.snapshot();
+    storage.merge(b"1", b"b").unwrap();
+    storage.delete(b"2").unwrap();
+    storage.put(b"4", b"b").unwrap();
+    storage.delete_range(b"4", b"5").unwrap();
+    storage.merge(b"5", b"b").unwrap();
+    storage.put(b"6", b"b").unwrap();
+
+    assert_eq!(
+        collect_backward(
+            storage
+                .scan_reverse(Bound::Unbounded, Bound::Unbounded)
+                .unwrap()
+        ),
+        vec![
+            (Bytes::from("6"), Bytes::from("b")),
+            (Bytes::from("5"), Bytes::from("a,b")),
+            (Bytes::from("3"), Bytes::from("a")),
+            (Bytes::from("1"), Bytes::from("a,b")),
+        ]
+    );
+    assert_eq!(
+        collect_backward(
+            storage
+                .scan_reverse(Bound::Excluded(b"1"), Bound::Excluded(b"6"))
+                .unwrap()
+        ),
+        vec![
+            (Bytes::from("5"), Bytes::from("a,b")),
+            (Bytes::from("3"), Bytes::from("a")),
+        ]
+    );
+    assert_eq!(
+        collect_backward(
+            storage
+                .scan_reverse(Bound::Included(b"1"), Bound::Included(b"4"))
+                .unwrap()
+        ),
+        vec![
+            (Bytes::from("3"), Bytes::from("a")),
+            (Bytes::from("1"), Bytes::from("a,b")),
+        ]
+    );
+
+    let mut iter = storage
+        .scan_with_snapshot(Bound::Unbounded, Bound::Unbounded, &snapshot)
+        .unwrap();
+    iter.seek_to_last().unwrap();
+    assert_eq!(
+        collect_backward(iter),
+        vec![
+            (Bytes::from("5"), Bytes::from("a")),
+            (Bytes::from("3"), Bytes::from("a")),
+            (Bytes::from("2"), Bytes::from("a")),
+            (Bytes::from("1"), Bytes::from("a")),
+        ]
+    );
+}
+
+#[test]
+fn test_scan_change_direction() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(
+        &dir,
+        LsmStorageOptions {
+            target_sst_size: 4096,
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 2,
+                base_level_size: 8192,
+                level_size_multiplier: 2,
+            }),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for round in 0..3 {
+        for idx in (round..300).step_by(3) {
+            storage
+                .put(&key_of(idx), round.to_string().as_bytes())
+                .unwrap();
+        }
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    for idx in (0..300).step_by(10) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+
+    let expected: Vec<Bytes> = (0..300).filter(|idx| idx % 10 != 0).map(key_of).collect();
+    let keys: Vec<Bytes> = collect_backward(
+        storage
+            .scan_reverse(Bound::Unbounded, Bound::Unbounded)
+            .unwrap(),
+    )
+    .into_iter()
+    .map(|(key, _)| key)
+    .collect();
+    assert_eq!(keys, expected.iter().rev().cloned().collect::<Vec<_>>());
+
+    // Walk forward and backward over the same keys.
+    let mut iter = storage.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+    let mut pos = 0;
+    for step in 1..=20 {
+        for _ in 0..step {
+            iter.next().unwrap();
+            pos += 1;
+            assert_eq!(iter.key(), expected[pos]);
+        }
+        for _ in 0..step / 2 {
+            iter.prev().unwrap();
+            pos -= 1;
+            assert_eq!(iter.key(), expected[pos]);
+        }
+    }
+    iter.seek_for_prev(&key_of(100)).unwrap();
+    assert_eq!(iter.key(), key_of(99));
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(101));
+}
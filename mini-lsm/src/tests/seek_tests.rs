@@ -0,0 +1,102 @@
+use std::ops::Bound;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use crate::compact::{CompactionOptions, LeveledCompactionOptions};
+use crate::iterators::{BidirectionalIterator, StorageIterator};
+use crate::lsm_storage::{LsmStorage, LsmStorageOptions};
+
+fn key_of(idx: usize) -> Bytes {
+    Bytes::from(format!("key_{:05}", idx))
+}
+
+/// Read up to `limit` keys from the current position of `iter`.
+fn read_page(iter: &mut impl StorageIterator, limit: usize) -> Vec<Bytes> {
+    let mut keys = Vec::new();
+    while iter.is_valid() && keys.len() < limit {
+        keys.push(Bytes::copy_from_slice(iter.key()));
+        iter.next().unwrap();
+    }
+    keys
+}
+
+#[test]
+fn test_scan_seek() {
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(
+        &dir,
+        LsmStorageOptions {
+            target_sst_size: 4096,
+            compaction_options: CompactionOptions::Leveled(LeveledCompactionOptions {
+                level0_file_num_compaction_trigger: 2,
+                max_levels: 2,
+                base_level_size: 8192,
+                level_size_multiplier: 2,
+            }),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    for round in 0..3 {
+        for idx in (round..300).step_by(3) {
+            storage
+                .put(&key_of(idx), round.to_string().as_bytes())
+                .unwrap();
+        }
+        storage.sync().unwrap();
+        storage.inner.trigger_compaction().unwrap();
+    }
+    for idx in (0..300).step_by(7) {
+        storage.delete(&key_of(idx)).unwrap();
+    }
+    storage.delete_range(&key_of(200), &key_of(250)).unwrap();
+    let expected: Vec<Bytes> = (0..300)
+        .filter(|idx| idx % 7 != 0 && !(200..250).contains(idx))
+        .map(key_of)
+        .collect();
+
+    // Page through the range with the same iterator, seeking past the last key of each page.
+    let mut iter = storage
+        .scan(Bound::Excluded(&key_of(10)), Bound::Included(&key_of(280)))
+        .unwrap();
+    let mut keys = read_page(&mut iter, 17);
+    loop {
+        let mut next_key = keys.last().unwrap().to_vec();
+        next_key.push(0);
+        iter.seek(&next_key).unwrap();
+        let page = read_page(&mut iter, 17);
+        if page.is_empty() {
+            break;
+        }
+        keys.extend(page);
+    }
+    let in_range = |key: &&Bytes| **key > key_of(10) && **key <= key_of(280);
+    assert_eq!(
+        keys,
+        expected
+            .iter()
+            .filter(in_range)
+            .cloned()
+            .collect::<Vec<_>>()
+    );
+
+    // The bounds of the scan still apply.
+    iter.seek(&key_of(0)).unwrap();
+    assert_eq!(iter.key(), key_of(11));
+    iter.seek(&key_of(210)).unwrap();
+    assert_eq!(iter.key(), key_of(250));
+    iter.seek(&key_of(281)).unwrap();
+    assert!(!iter.is_valid());
+
+    // Seeking also changes the direction of the iterator.
+    iter.seek_to_last().unwrap();
+    iter.prev().unwrap();
+    iter.seek(&key_of(100)).unwrap();
+    assert_eq!(
+        read_page(&mut iter, 3),
+        vec![key_of(100), key_of(101), key_of(102)]
+    );
+    iter.prev().unwrap();
+    assert_eq!(iter.key(), key_of(102));
+}
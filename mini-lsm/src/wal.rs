@@ -0,0 +1,261 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::mvcc::{InternalKey, ValueType};
+use crate::range_tombstone::RangeTombstone;
+
+const SIZEOF_U8: usize = std::mem::size_of::<u8>();
+const SIZEOF_U32: usize = std::mem::size_of::<u32>();
+const SIZEOF_U64: usize = std::mem::size_of::<u64>();
+
+/// The tag of a range tombstone entry, whose key and value are the start and the end of the range.
+/// It is distinct from the tags of [`ValueType`].
+const RANGE_DELETE_TAG: u8 = 2;
+
+/// A write replayed from the log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WalEntry {
+    Point(InternalKey, ValueType, Bytes),
+    RangeDelete(RangeTombstone),
+}
+
+/// Controls when the write-ahead log calls `fsync`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WalSyncPolicy {
+    /// Call `fsync` after every record. An acknowledged write is never lost.
+    #[default]
+    EveryWrite,
+    /// Call `fsync` once every `n` records. Up to `n - 1` acknowledged writes may be lost.
+    Batch(usize),
+    /// Call `fsync` on a write if at least the given interval has passed since the last one.
+    Periodic(Duration),
+}
+
+struct WalWriter {
+    file: File,
+    /// Number of records written since the last `fsync`.
+    unsynced: usize,
+    /// When the last `fsync` happened.
+    last_sync: Instant,
+}
+
+/// A write-ahead log backing the memtables of all column families created together.
+///
+/// Each record holds a batch of key-value pairs written together, and is encoded as
+/// `seq (u64) | count (u32) | entries | checksum (u32)`, where each entry is
+/// `column_family (u32) | key_len (u32) | key | tag (u8) | value_len (u32) | value`. The tag is a
+/// [`ValueType`], or marks a range tombstone. The `i`-th entry is written with sequence number
+/// `seq + i`, and the checksum is the CRC32C of everything before it in the record. Replay applies
+/// a record as a whole or not at all.
+pub struct Wal {
+    writer: Mutex<WalWriter>,
+    sync_policy: WalSyncPolicy,
+}
+
+impl Wal {
+    fn new(file: File, sync_policy: WalSyncPolicy) -> Self {
+        Self {
+            writer: Mutex::new(WalWriter {
+                file,
+                unsynced: 0,
+                last_sync: Instant::now(),
+            }),
+            sync_policy,
+        }
+    }
+
+    /// Create a new, empty log file at `path`.
+    pub fn create(path: impl AsRef<Path>, sync_policy: WalSyncPolicy) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to create WAL {}", path.as_ref().display()))?;
+        Ok(Self::new(file, sync_policy))
+    }
+
+    /// Replay the log at `path`, and reopen it for appending. Returns the writes in the log, each
+    /// with the id of its column family.
+    ///
+    /// A record that is truncated or fails its checksum is treated as a torn write from a crash:
+    /// replay stops there and the log is truncated to the last good record.
+    pub fn recover(
+        path: impl AsRef<Path>,
+        sync_policy: WalSyncPolicy,
+    ) -> Result<(Self, Vec<(u32, WalEntry)>)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path.as_ref())
+            .with_context(|| format!("failed to open WAL {}", path.as_ref().display()))?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let (entries, valid_len) = Self::replay(&buf);
+        if valid_len < buf.len() {
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+        Ok((Self::new(file, sync_policy), entries))
+    }
+
+    /// Decode records from `buf`. Returns their writes, and the length of the valid prefix.
+    fn replay(buf: &[u8]) -> (Vec<(u32, WalEntry)>, usize) {
+        let mut entries = Vec::new();
+        let mut rbuf = buf;
+        while let Some((record_entries, len)) = Self::decode_record(rbuf) {
+            entries.extend(record_entries);
+            rbuf = &rbuf[len..];
+        }
+        (entries, buf.len() - rbuf.len())
+    }
+
+    /// Decode a single record, returning `None` if it is incomplete or corrupted.
+    fn decode_record(record: &[u8]) -> Option<(Vec<(u32, WalEntry)>, usize)> {
+        let mut buf = record;
+        if buf.remaining() < SIZEOF_U64 + SIZEOF_U32 {
+            return None;
+        }
+        let seq = buf.get_u64();
+        let count = buf.get_u32() as u64;
+        let mut entries = Vec::new();
+        for i in 0..count {
+            if buf.remaining() < SIZEOF_U32 * 2 {
+                return None;
+            }
+            let column_family = buf.get_u32();
+            let key_len = buf.get_u32() as usize;
+            if buf.remaining() < key_len + SIZEOF_U8 + SIZEOF_U32 {
+                return None;
+            }
+            let key = Bytes::copy_from_slice(&buf[..key_len]);
+            buf.advance(key_len);
+            let tag = buf.get_u8();
+            let value_len = buf.get_u32() as usize;
+            if buf.remaining() < value_len {
+                return None;
+            }
+            let value = Bytes::copy_from_slice(&buf[..value_len]);
+            buf.advance(value_len);
+            let entry = match tag {
+                RANGE_DELETE_TAG => WalEntry::RangeDelete(RangeTombstone::new(key, value, seq + i)),
+                tag => WalEntry::Point(
+                    InternalKey::new(key, seq + i),
+                    ValueType::from_u8(tag).ok()?,
+                    value,
+                ),
+            };
+            entries.push((column_family, entry));
+        }
+        if buf.remaining() < SIZEOF_U32 {
+            return None;
+        }
+        let body_len = record.len() - buf.remaining();
+        let checksum = buf.get_u32();
+        if checksum != crc32c::crc32c(&record[..body_len]) {
+            return None;
+        }
+        Some((entries, body_len + SIZEOF_U32))
+    }
+
+    /// Append a key-value pair of a column family written with sequence number `seq` to the log.
+    pub fn put(&self, seq: u64, column_family: u32, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_batch(seq, &[(column_family, key, ValueType::Put, value)])
+    }
+
+    /// Append a batch of writes as a single record. A write is the id of a column family, a key,
+    /// its type and its value, and the `i`-th write is written with sequence number `seq + i`.
+    pub fn put_batch(&self, seq: u64, entries: &[(u32, &[u8], ValueType, &[u8])]) -> Result<()> {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|&(column_family, key, value_type, value)| {
+                (column_family, key, value_type.to_u8(), value)
+            })
+            .collect();
+        self.append_record(seq, &entries)
+    }
+
+    /// Append a range tombstone of a column family deleting `start..end` with sequence number
+    /// `seq` to the log.
+    pub fn delete_range(
+        &self,
+        seq: u64,
+        column_family: u32,
+        start: &[u8],
+        end: &[u8],
+    ) -> Result<()> {
+        self.append_record(seq, &[(column_family, start, RANGE_DELETE_TAG, end)])
+    }
+
+    /// Append a record of `(column family, key, tag, value)` entries, calling `fsync` as required
+    /// by the sync policy.
+    ///
+    /// The record is always handed to the OS before returning, so it survives a process crash
+    /// even if the policy defers `fsync`.
+    fn append_record(&self, seq: u64, entries: &[(u32, &[u8], u8, &[u8])]) -> Result<()> {
+        let entries_len: usize = entries
+            .iter()
+            .map(|(_, key, _, value)| key.len() + value.len() + SIZEOF_U8 + SIZEOF_U32 * 3)
+            .sum();
+        let mut buf = Vec::with_capacity(SIZEOF_U64 + SIZEOF_U32 * 2 + entries_len);
+        buf.put_u64(seq);
+        buf.put_u32(entries.len() as u32);
+        for (column_family, key, tag, value) in entries {
+            buf.put_u32(*column_family);
+            buf.put_u32(key.len() as u32);
+            buf.put_slice(key);
+            buf.put_u8(*tag);
+            buf.put_u32(value.len() as u32);
+            buf.put_slice(value);
+        }
+        buf.put_u32(crc32c::crc32c(&buf));
+
+        let mut writer = self.writer.lock();
+        writer.file.write_all(&buf)?;
+        writer.unsynced += 1;
+        let need_sync = match self.sync_policy {
+            WalSyncPolicy::EveryWrite => true,
+            WalSyncPolicy::Batch(n) => writer.unsynced >= n,
+            WalSyncPolicy::Periodic(interval) => writer.last_sync.elapsed() >= interval,
+        };
+        if need_sync {
+            Self::sync_inner(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// `fsync` the log file.
+    pub fn sync(&self) -> Result<()> {
+        Self::sync_inner(&mut self.writer.lock())
+    }
+
+    /// `fsync` the log file if the periodic sync interval has passed with records unsynced.
+    ///
+    /// Called in the background, so the last writes before an idle period are not left unsynced
+    /// until the next write.
+    pub fn sync_if_due(&self) -> Result<()> {
+        let mut writer = self.writer.lock();
+        if let WalSyncPolicy::Periodic(interval) = self.sync_policy {
+            if writer.unsynced > 0 && writer.last_sync.elapsed() >= interval {
+                Self::sync_inner(&mut writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_inner(writer: &mut WalWriter) -> Result<()> {
+        writer.file.sync_all()?;
+        writer.unsynced = 0;
+        writer.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests;
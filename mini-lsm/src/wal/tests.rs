@@ -0,0 +1,175 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use bytes::Bytes;
+use tempfile::tempdir;
+
+use super::{Wal, WalEntry, WalSyncPolicy};
+use crate::mvcc::{InternalKey, ValueType};
+use crate::range_tombstone::RangeTombstone;
+
+fn point(cf: u32, key: &str, seq: u64, value_type: ValueType, value: &str) -> (u32, WalEntry) {
+    (
+        cf,
+        WalEntry::Point(
+            InternalKey::new(Bytes::copy_from_slice(key.as_bytes()), seq),
+            value_type,
+            Bytes::copy_from_slice(value.as_bytes()),
+        ),
+    )
+}
+
+fn recover(path: &std::path::Path) -> (Wal, Vec<(u32, WalEntry)>) {
+    Wal::recover(path, WalSyncPolicy::EveryWrite).unwrap()
+}
+
+#[test]
+fn test_wal_recover() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::EveryWrite).unwrap();
+        wal.put(1, 0, b"key1", b"value1").unwrap();
+        wal.put(2, 0, b"key2", b"value2").unwrap();
+        wal.put(3, 0, b"key1", b"value11").unwrap();
+        wal.put(4, 0, b"key3", b"").unwrap();
+    }
+    let (_, entries) = recover(&path);
+    assert_eq!(
+        entries,
+        vec![
+            point(0, "key1", 1, ValueType::Put, "value1"),
+            point(0, "key2", 2, ValueType::Put, "value2"),
+            point(0, "key1", 3, ValueType::Put, "value11"),
+            point(0, "key3", 4, ValueType::Put, ""),
+        ]
+    );
+}
+
+#[test]
+fn test_wal_recover_torn_write() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::Batch(16)).unwrap();
+        wal.put(1, 0, b"key1", b"value1").unwrap();
+        wal.put(2, 0, b"key2", b"value2").unwrap();
+        wal.sync().unwrap();
+    }
+    // Simulate a crash in the middle of writing a record.
+    let valid_len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap()
+        .write_all(&[0, 0, 0, 4, b'k', b'e'])
+        .unwrap();
+
+    let (wal, entries) = recover(&path);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), valid_len);
+
+    // New records are appended after the last good one.
+    wal.put(3, 0, b"key3", b"value3").unwrap();
+    drop(wal);
+    let (_, entries) = recover(&path);
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2], point(0, "key3", 3, ValueType::Put, "value3"));
+}
+
+#[test]
+fn test_wal_recover_batch() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::EveryWrite).unwrap();
+        let batch: [(u32, &[u8], ValueType, &[u8]); 3] = [
+            (0, b"key1", ValueType::Put, b"value1"),
+            (0, b"key2", ValueType::Delete, b""),
+            (0, b"key1", ValueType::Put, b"value11"),
+        ];
+        wal.put_batch(1, &batch).unwrap();
+        let batch: [(u32, &[u8], ValueType, &[u8]); 2] = [
+            (0, b"key3", ValueType::Put, b"value3"),
+            (0, b"key4", ValueType::Delete, b""),
+        ];
+        wal.put_batch(4, &batch).unwrap();
+    }
+    // Simulate a crash in the middle of writing the second batch.
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 8)
+        .unwrap();
+
+    let (_, entries) = recover(&path);
+    assert_eq!(
+        entries,
+        vec![
+            point(0, "key1", 1, ValueType::Put, "value1"),
+            point(0, "key2", 2, ValueType::Delete, ""),
+            point(0, "key1", 3, ValueType::Put, "value11"),
+        ]
+    );
+}
+
+#[test]
+fn test_wal_recover_column_families() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::EveryWrite).unwrap();
+        let batch: [(u32, &[u8], ValueType, &[u8]); 2] = [
+            (0, b"key1", ValueType::Put, b"value1"),
+            (3, b"key1", ValueType::Put, b"value2"),
+        ];
+        wal.put_batch(1, &batch).unwrap();
+        wal.delete_range(3, 3, b"key0", b"key2").unwrap();
+    }
+
+    let (_, entries) = recover(&path);
+    assert_eq!(
+        entries,
+        vec![
+            point(0, "key1", 1, ValueType::Put, "value1"),
+            point(3, "key1", 2, ValueType::Put, "value2"),
+            (
+                3,
+                WalEntry::RangeDelete(RangeTombstone::new(
+                    Bytes::from("key0"),
+                    Bytes::from("key2"),
+                    3
+                ))
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_wal_recover_range_tombstone() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.wal");
+    {
+        let wal = Wal::create(&path, WalSyncPolicy::EveryWrite).unwrap();
+        wal.put(1, 0, b"key1", b"value1").unwrap();
+        wal.delete_range(2, 0, b"key0", b"key2").unwrap();
+    }
+
+    let (_, entries) = recover(&path);
+    assert_eq!(
+        entries,
+        vec![
+            point(0, "key1", 1, ValueType::Put, "value1"),
+            (
+                0,
+                WalEntry::RangeDelete(RangeTombstone::new(
+                    Bytes::from("key0"),
+                    Bytes::from("key2"),
+                    2
+                ))
+            ),
+        ]
+    );
+}
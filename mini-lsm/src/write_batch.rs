@@ -0,0 +1,82 @@
+use bytes::Bytes;
+
+use crate::column_family::{ColumnFamily, DEFAULT_COLUMN_FAMILY_ID};
+use crate::mvcc::ValueType;
+
+/// A batch of puts and deletes, applied atomically by [`LsmStorage::write`]: a reader sees
+/// either all of them or none, and so does recovery after a crash. The writes may span column
+/// families.
+///
+/// [`LsmStorage::write`]: crate::lsm_storage::LsmStorage::write
+#[derive(Clone, Debug, Default)]
+pub struct WriteBatch {
+    /// The writes in order, each with the id of its column family, where a delete is `None`.
+    entries: Vec<(u32, Bytes, Option<Bytes>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Put a key-value pair. A later write of the same key in the batch overrides it.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, key, Some(value))
+    }
+
+    /// Put a key-value pair into a column family.
+    pub fn put_cf(&mut self, cf: &ColumnFamily, key: &[u8], value: &[u8]) -> &mut Self {
+        self.push(cf.id(), key, Some(value))
+    }
+
+    /// Remove a key. A later write of the same key in the batch overrides it.
+    pub fn delete(&mut self, key: &[u8]) -> &mut Self {
+        self.push(DEFAULT_COLUMN_FAMILY_ID, key, None)
+    }
+
+    /// Remove a key from a column family.
+    pub fn delete_cf(&mut self, cf: &ColumnFamily, key: &[u8]) -> &mut Self {
+        self.push(cf.id(), key, None)
+    }
+
+    fn push(&mut self, cf_id: u32, key: &[u8], value: Option<&[u8]>) -> &mut Self {
+        assert!(!key.is_empty(), "key cannot be empty");
+
+        self.entries.push((
+            cf_id,
+            Bytes::copy_from_slice(key),
+            value.map(Bytes::copy_from_slice),
+        ));
+        self
+    }
+
+    /// Get the number of writes in the batch.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all writes from the batch.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Get the writes in order, each as the id of its column family, a key, its type and its
+    /// value.
+    pub(crate) fn entries(&self) -> Vec<(u32, &[u8], ValueType, &[u8])> {
+        self.entries
+            .iter()
+            .map(|(cf_id, key, value)| {
+                (
+                    *cf_id,
+                    &key[..],
+                    ValueType::of(value),
+                    value.as_deref().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+}